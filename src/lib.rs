@@ -4,7 +4,7 @@ pub mod packet_sniffer {
     //! # How to use packet_sniffer
     //! To create a new `packet_sniffer` object simply call the constructor
     //! ```
-    //! let sniffer = Sniffer::new(file_name, network_device_number, time_interval, filter);
+    //! let sniffer = Sniffer::new(file_name, "eth0", time_interval, filter);
     //! ```
     //! #
     //! To start capturing the network traffic use the [`start_capture`] function
@@ -15,8 +15,39 @@ pub mod packet_sniffer {
     //! #
     //! It is possible to pause and resume the capture through the command line
     //! 
-    use etherparse::{IpHeader, PacketHeaders, TransportHeader};
-    use pcap::{Device,Capture};
+    mod banner;
+    mod capture;
+    mod coap;
+    mod commands;
+    #[cfg(unix)]
+    mod control;
+    mod database;
+    mod devices;
+    mod dhcp;
+    mod discovery;
+    mod dns;
+    mod http;
+    mod metrics;
+    mod modbus;
+    mod mqtt;
+    mod netflow;
+    mod packet_store;
+    mod pcapng;
+    mod quic;
+    mod reassembly;
+    mod series;
+    mod reader;
+    mod report;
+    mod requests;
+    mod streams;
+    mod summary;
+    mod tcp_metrics;
+    mod tls;
+    mod tui;
+    mod zeek;
+
+    use etherparse::{Ethernet2Header, IpHeader, PacketHeaders, TcpOptionElement, TransportHeader};
+    use pcap::{BpfProgram,Device,Capture,Linktype,Packet,PacketHeader,Stat};
     use sprintf::sprintf;
     use std::sync::{Condvar, Arc, Mutex};
     use std::time::{Duration, Instant};
@@ -26,6 +57,40 @@ pub mod packet_sniffer {
     use chrono::prelude::*;
     use std::fs::File;
     use std::io::{Write, stdin, stdout};
+    use quic::QuicTracker;
+    use dhcp::HostInventory;
+    use banner::{BannerTracker, Finding};
+    use discovery::ServiceCatalogue;
+    use modbus::ModbusSummary;
+    use mqtt::MqttSummary;
+    use coap::CoapSummary;
+    use pcapng::{Interface, PcapOutput, PcapngWriter};
+    use capture::{CapturedPacket, MultiCapture};
+    pub use capture::{CaptureOptions, Direction, TimestampType};
+    use packet_store::PacketStore;
+    use reassembly::{Segment, TcpReassembler};
+    use streams::StreamExport;
+    use tcp_metrics::{TcpMetrics, TcpPacket};
+    use series::TimeSeries;
+    use summary::Summary;
+    use serde_json::json;
+    use tui::{Action, Status, Tui};
+    use commands::{Command, TimeChange};
+    #[cfg(unix)]
+    use control::ControlSocket;
+    use requests::{Query, Request};
+    use http::HttpServer;
+    use metrics::Counters;
+    use netflow::FlowExport;
+    use zeek::ConnLog;
+    use database::{Database, Snapshot};
+    pub use database::HistoryFilter;
+    pub use devices::ListFormat;
+    use report::{ReportFile, ReportJob};
+    pub use report::{Compression, ReportPolicy};
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::net::IpAddr;
 
     /// This enum provides the different errors that can happen inside the sniffer mod
     /// ## Properties
@@ -33,12 +98,28 @@ pub mod packet_sniffer {
     /// * `DeviceNotFound`: Inserted device not found
     /// * `InvalidFilter`: Invalid Filter: correct syntax is available at <https://biot.com/capstats/bpf.html>
     /// * `OpenErrorCapture` Impossible to open capture for the selected device:
+    /// * `PcapOutputError`: Impossible to write the pcap/pcapng output file
+    /// * `TerminalError`: Impossible to set up the terminal for the full-screen interface
+    /// * `ConnectionNotFound`: No connection has the requested number
+    /// * `ControlSocketError`: Impossible to open or reach the control socket
+    /// * `HttpServerError`: Impossible to start the HTTP server
+    /// * `FlowExportError`: Impossible to send the flows to the collector, or unsupported NetFlow version
+    /// * `DatabaseError`: Impossible to open, write or query the SQLite database
+    /// * `InvalidFileTemplate`: Invalid `strftime` field in the name of the report file
     #[derive(Debug)]
     pub enum SnifferError {
         DevicesListImpossibleToGet,
         DeviceNotFound,
         InvalidFilter,
         OpenErrorCapture,
+        PcapOutputError,
+        ConnectionNotFound,
+        TerminalError,
+        ControlSocketError,
+        HttpServerError,
+        FlowExportError,
+        DatabaseError,
+        InvalidFileTemplate,
     }
     impl Display for SnifferError {
         fn fmt(&self, f: &mut Formatter) -> Result{
@@ -47,6 +128,14 @@ pub mod packet_sniffer {
                 SnifferError::DevicesListImpossibleToGet => write!(f, "No devices available"),
                 SnifferError::InvalidFilter => write!(f, "Invalid Filter: correct syntax is available at https://biot.com/capstats/bpf.html"),
                 SnifferError::OpenErrorCapture => write!(f, "Impossible to open capture for the selected device "),
+                SnifferError::PcapOutputError => write!(f, "Impossible to write the pcap/pcapng output file"),
+                SnifferError::ConnectionNotFound => write!(f, "Connection not found"),
+                SnifferError::TerminalError => write!(f, "Impossible to set up the terminal for the full-screen interface"),
+                SnifferError::ControlSocketError => write!(f, "Impossible to open or reach the control socket"),
+                SnifferError::HttpServerError => write!(f, "Impossible to start the HTTP server"),
+                SnifferError::FlowExportError => write!(f, "Impossible to send the flows to the collector, or unsupported NetFlow version"),
+                SnifferError::DatabaseError => write!(f, "Impossible to open, write or query the SQLite database"),
+                SnifferError::InvalidFileTemplate => write!(f, "Invalid strftime field in the report file name: syntax is available at https://docs.rs/chrono/latest/chrono/format/strftime/"),
             }
         }
     }
@@ -77,6 +166,8 @@ pub mod packet_sniffer {
         bytes_a_to_b: u32,
         bytes_b_to_a: u32,
         app_prot: String,
+        details: Vec<String>,
+        interfaces: Vec<String>,
     }
 
     impl Display for IpV {
@@ -122,7 +213,7 @@ pub mod packet_sniffer {
                 t = Transport::UDP;
             }
 
-            Self { l3: ip, ip_1, ip_2, l4: t, port_1, port_2, ts_start, ts_end, bytes_a_to_b: bytes, bytes_b_to_a: 0, app_prot}
+            Self { l3: ip, ip_1, ip_2, l4: t, port_1, port_2, ts_start, ts_end, bytes_a_to_b: bytes, bytes_b_to_a: 0, app_prot, details: vec![], interfaces: vec![]}
         }
 
         /// Update an istance of a Connection
@@ -139,6 +230,24 @@ pub mod packet_sniffer {
             }
             self.ts_end = new_ts_end;
         }
+
+        /// Add a piece of information decoded from the payload, skipping duplicates
+        /// ## Parameters
+        /// * `detail`: Text shown in the "Details" column of the report
+        fn add_detail(&mut self, detail: String){
+            if !self.details.contains(&detail) {
+                self.details.push(detail);
+            }
+        }
+
+        /// Records that a packet of the connection was captured on `interface`
+        /// ## Parameters
+        /// * `interface`: Name of the capture device
+        fn add_interface(&mut self, interface: &str){
+            if !self.interfaces.iter().any(|i| i == interface) {
+                self.interfaces.push(interface.to_string());
+            }
+        }
     }
 
     impl PartialEq for Connection {
@@ -175,33 +284,163 @@ pub mod packet_sniffer {
         state: Mutex<ExecState>,
         cv: Condvar
     }
+
+    impl SyncStruct {
+        /// Pauses or resumes the capture. Returns `false` if it was already in the requested state
+        fn set_pause(&self, pause: bool) -> bool {
+            let mut res = self.state.lock().unwrap();
+            if res.pause == pause {
+                return false;
+            }
+            res.pause = pause;
+            self.cv.notify_all();
+            true
+        }
+
+        /// Ends the capture as if the timer expired
+        fn stop(&self) {
+            let mut res = self.state.lock().unwrap();
+            res.time_interval = 0.0;
+            res.pause = false;
+            self.cv.notify_all();
+        }
+
+        /// Extends, shortens or sets the capture time left, returning the new time left (secs)
+        fn change_time(&self, change: &TimeChange) -> f64 {
+            let mut res = self.state.lock().unwrap();
+            let remaining = if res.pause {
+                res.time_interval
+            } else {
+                res.time_interval - res.start_time.elapsed().as_secs_f64()
+            };
+            let time = match change {
+                TimeChange::Add(n) => remaining + n,
+                TimeChange::Set(n) => *n,
+            };
+            res.time_interval = if time.is_finite() { time.max(0.0) } else { remaining };
+            //the timer thread restarts waiting from now
+            if !res.pause {
+                res.start_time = Instant::now();
+            }
+            self.cv.notify_all();
+            res.time_interval
+        }
+
+        /// Capture time left (secs)
+        fn remaining(&self) -> f64 {
+            let res = self.state.lock().unwrap();
+            if res.pause {
+                res.time_interval
+            } else {
+                res.time_interval - res.start_time.elapsed().as_secs_f64()
+            }
+        }
+    }
    
     /// This struct defines the Sniffer
     /// ## Properties
     /// * `file_name`: The name of the file (or the absolute or relative path) where the result of the capture is going to be printed
-    /// * `dev`: Device identification number [an integer value ranging from 0 onwards which corresponde to a newtwork device interface]
+    /// * `devs`: Names of the capture devices, each one captured by a thread of its own
+    /// * `capture_options`: Promiscuous mode, snaplen, buffer size, timeout, immediate mode, timestamps and direction of the capture
     /// * `filter`: A filter (syntax is available at <https://biot.com/capstats/bpf.html>)
     /// * `connections`: A vector of Connections
     /// * `waiter`: Synchronization variable
+    /// * `quic`: QUIC Initial decryption state of each connection
+    /// * `hosts`: Host inventory built from DHCP/DHCPv6 messages
+    /// * `banners`: Banner and command state of the cleartext protocols
+    /// * `services`: Catalogue of the services announced through mDNS and SSDP
+    /// * `modbus`: Modbus/TCP summary of each connection
+    /// * `mqtt`: MQTT session summary of each connection
+    /// * `coap`: CoAP summary of each connection
+    /// * `pcap_output`: Settings of the pcapng output, if enabled
+    /// * `packets`: First packets of each connection, available for extraction
+    /// * `linktype`: Link-layer header type of the capture
+    /// * `reassembly`: TCP stream reassembly of each connection
+    /// * `streams`: Export of the reassembled TCP payload, if enabled
+    /// * `tcp_metrics`: RTT, retransmissions and window metrics of each TCP connection
+    /// * `series`: Traffic time series, if enabled
+    /// * `series_file`: The name of the file the time series are exported to (CSV, or JSON if it ends with `.json`)
+    /// * `local_addrs`: Addresses of the capture device, telling outgoing packets from incoming ones
+    /// * `json_file`: The name of the file the report is also written to as JSON, if any
+    /// * `tui`: `true` to show the full-screen interface instead of the command prompt
+    /// * `counters`: Packets, bytes, packets not accounted and statistics of pcap counted so far
+    /// * `drop_threshold`: Percentage of dropped packets above which a warning is shown, 0 = never
+    /// * `drop_warned`: `true` while the percentage of dropped packets is above the threshold
+    /// * `control_socket`: Path of the control socket, if enabled
+    /// * `http_port`: Port of the HTTP API and dashboard, if enabled
+    /// * `flows`: NetFlow/IPFIX export, if enabled
+    /// * `conn_log`: Zeek `conn.log` state of the connections, if enabled
+    /// * `conn_log_file`: The name of the file the `conn.log` is written to (Zeek TSV, or Zeek JSON if it ends with `.json`)
+    /// * `database`: SQLite database of the capture history, if enabled, owned by the database writer thread during the capture
+    /// * `database_writer`: Channel towards the database writer thread, during the capture
+    /// * `snapshot_ends`: End of each connection at the previous snapshot, only the connections changed since are saved again
+    /// * `snapshot_interval`: Time between two snapshots saved to the database (secs), 0 to save them only on pause and at the end
+    /// * `report`: Report file, written following its output policy, by the report writer thread during the capture
+    /// * `report_writer`: Channel towards the report writer thread, which renders the reports from a copy of the state, during the capture
+    /// * `report_interval`: Time between two periodic reports (secs), 0 = none
+    /// * `report_delta`: `true` if the periodic reports only hold the traffic since the previous one
+    /// * `last_report`: Bytes A->B and B->A of each connection at the previous periodic report, and its time
     pub struct Sniffer{
         file_name: String,
-        dev: String,
+        devs: Vec<String>,
+        capture_options: CaptureOptions,
         filter: String,
         connections: Vec<Connection>,
-        waiter: Arc<SyncStruct>
+        waiter: Arc<SyncStruct>,
+        quic: QuicTracker,
+        hosts: HostInventory,
+        banners: BannerTracker,
+        services: ServiceCatalogue,
+        modbus: HashMap<usize, ModbusSummary>,
+        mqtt: HashMap<usize, MqttSummary>,
+        coap: HashMap<usize, CoapSummary>,
+        pcap_output: Option<PcapOutput>,
+        packets: PacketStore,
+        linktype: Linktype,
+        reassembly: TcpReassembler,
+        streams: Option<StreamExport>,
+        tcp_metrics: HashMap<usize, TcpMetrics>,
+        series: Option<TimeSeries>,
+        series_file: String,
+        local_addrs: Vec<String>,
+        json_file: Option<String>,
+        tui: bool,
+        counters: Counters,
+        drop_threshold: f64,
+        drop_warned: bool,
+        #[cfg(unix)]
+        control_socket: Option<String>,
+        http_port: Option<u16>,
+        flows: Option<FlowExport>,
+        conn_log: Option<ConnLog>,
+        conn_log_file: String,
+        database: Option<Database>,
+        database_writer: Option<Sender<Snapshot>>,
+        snapshot_ends: Vec<DateTime<Local>>,
+        snapshot_interval: f64,
+        report: Arc<Mutex<ReportFile>>,
+        report_writer: Option<Sender<ReportJob>>,
+        report_interval: f64,
+        report_delta: bool,
+        last_report: ReportBytes,
     }
 
     impl Sniffer {
         /// Creates an istance of Sniffer
         /// ## Parameters
         /// * `file_name`: The name of the file (or the absolute or relative path) where the result of the capture is going to be printed
-        /// * `dev`: Device identification number [an integer value ranging from 0 onwards which corresponds to a network device interface]
+        /// * `dev`: Device name (e.g. "eth0"), "default" for the device pcap would choose, or its number in the devices list [an integer value ranging from 0 onwards, see `list_devices`]
         /// * `time_interval`: Sniffing Duration (secs)
         /// * `filter`: (OPTIONAL) A filter (syntax is available at <https://biot.com/capstats/bpf.html>),
         /// #
         /// Please notice that the result of the capture is going stored in your working folder, unless a path is provided
-        pub fn new(file_name: String, dev: usize, time_interval: f64, filter: String) -> std::result::Result<Self, SnifferError> {
-            
+        pub fn new(file_name: String, dev: &str, time_interval: f64, filter: String) -> std::result::Result<Self, SnifferError> {
+            let dev_name = devices::resolve(dev)?;
+            Ok(Sniffer::with_device(file_name, dev_name, time_interval, filter))
+        }
+
+        /// Creates a sniffer capturing from `dev_name`, a device name already resolved
+        fn with_device(file_name: String, dev_name: String, time_interval: f64, filter: String) -> Self {
             let s= Mutex::new(ExecState{
                 time_interval,
                 start_time: Instant::now(),
@@ -213,29 +452,265 @@ pub mod packet_sniffer {
                 state: s,
                 cv: Condvar::new()    
             });
-            
-            let devs = Device::list();
-            let dev_name;
-            match devs {
-                Err(_e) => return Err(SnifferError::DevicesListImpossibleToGet),
-                Ok(devs) => {
-                    if devs.len() <= dev {
-                        return Err(SnifferError::DeviceNotFound)
-                    }
-                    dev_name = devs.get(dev).unwrap().name.clone();
-                }
-            }
 
-            return Ok(Sniffer {
+            let report = ReportFile::new(&file_name, ReportPolicy::default());
+            Sniffer {
                 file_name,
-                dev: dev_name,
+                devs: vec![dev_name],
+                capture_options: CaptureOptions::default(),
                 filter,
                 connections: vec![],
-                waiter: wait
+                waiter: wait,
+                quic: QuicTracker::default(),
+                hosts: HostInventory::default(),
+                banners: BannerTracker::default(),
+                services: ServiceCatalogue::default(),
+                modbus: HashMap::new(),
+                mqtt: HashMap::new(),
+                coap: HashMap::new(),
+                pcap_output: None,
+                packets: PacketStore::default(),
+                linktype: Linktype::ETHERNET,
+                reassembly: TcpReassembler::default(),
+                streams: None,
+                tcp_metrics: HashMap::new(),
+                series: None,
+                series_file: String::new(),
+                local_addrs: vec![],
+                json_file: None,
+                tui: false,
+                counters: Counters::default(),
+                drop_threshold: 1.0,
+                drop_warned: false,
+                #[cfg(unix)]
+                control_socket: None,
+                http_port: None,
+                flows: None,
+                conn_log: None,
+                conn_log_file: String::new(),
+                database: None,
+                database_writer: None,
+                snapshot_ends: vec![],
+                snapshot_interval: 0.0,
+                report: Arc::new(Mutex::new(report)),
+                report_writer: None,
+                report_interval: 0.0,
+                report_delta: false,
+                last_report: (vec![], Local::now()),
+            }
+        }
+
+        /// Captures also from another device, at the same time as the first one. The connections seen on several devices
+        /// are accounted once, and list all the devices they were seen on
+        /// ## Parameters
+        /// * `dev`: Device name, "default", or its number in the devices list, as in `new`
+        pub fn add_device(&mut self, dev: &str) -> std::result::Result<(), SnifferError> {
+            let dev_name = devices::resolve(dev)?;
+            if !self.devs.contains(&dev_name) {
+                self.devs.push(dev_name);
+            }
+            Ok(())
+        }
+
+        /// Sets the percentage of packets dropped by the kernel or by the interface above which a warning is shown,
+        /// 1% by default. The statistics of pcap are read every second and at the end of the capture
+        /// ## Parameters
+        /// * `threshold`: Percentage of the packets received by pcap, 0 to never warn
+        pub fn set_drop_warning(&mut self, threshold: f64) {
+            self.drop_threshold = threshold;
+        }
+
+        /// Sets how the devices are opened, by default in promiscuous mode with a snaplen of 65535 bytes,
+        /// a read timeout of 500 ms, the default buffer size and timestamps of pcap, capturing both directions
+        /// ## Parameters
+        /// * `options`: Settings of the capture
+        /// ```no_run
+        /// use packet_sniffer::packet_sniffer::{CaptureOptions, Sniffer};
+        /// let mut sniffer = Sniffer::new(String::from("results.txt"), "default", 60.0, String::new()).unwrap();
+        /// sniffer.set_capture_options(CaptureOptions { promisc: false, snaplen: 128, nanos: true, ..Default::default() });
+        /// ```
+        pub fn set_capture_options(&mut self, options: CaptureOptions) {
+            self.capture_options = options;
+        }
+
+        /// Saves the captured packets to a pcapng file, each one commented with the number of its connection
+        /// ## Parameters
+        /// * `path`: The name of the pcapng file (or the absolute or relative path)
+        /// * `filter`: (OPTIONAL) A secondary filter selecting the packets to save (syntax is available at <https://biot.com/capstats/bpf.html>), all of them are saved if empty
+        /// * `max_size`: (OPTIONAL) Size (bytes) after which a new file is started, 0 to disable
+        /// * `rotate_secs`: (OPTIONAL) Seconds after which a new file is started, 0 to disable
+        /// #
+        /// When rotating, files are numbered: `capture.pcapng` becomes `capture-0001.pcapng`, `capture-0002.pcapng`, ...
+        pub fn set_pcap_output(&mut self, path: String, filter: String, max_size: u64, rotate_secs: u64) {
+            self.pcap_output = Some(PcapOutput {
+                path,
+                filter,
+                rotate_size: if max_size > 0 { Some(max_size) } else { None },
+                rotate_time: if rotate_secs > 0 { Some(Duration::from_secs(rotate_secs)) } else { None },
             });
         }
         
     
+        /// Keeps the first packets of each connection, so that they can be extracted with [`dump_connection`](Sniffer::dump_connection),
+        /// during the capture with the "extract" command or control request, or after it.
+        /// The packets are kept in memory from the first one of the connection until either limit is reached,
+        /// the later ones are not kept: the extracted file holds the start of the connection, not its last packets
+        /// ## Parameters
+        /// * `max_packets`: Number of packets kept for each connection, 0 to disable
+        /// * `max_bytes`: Captured bytes kept for each connection
+        pub fn set_flow_buffer(&mut self, max_packets: usize, max_bytes: usize) {
+            self.packets = PacketStore::new(max_packets, max_bytes);
+        }
+
+        /// Writes the packets kept for a connection to a pcap file
+        /// ## Parameters
+        /// * `connection`: Number of the connection, as shown in the report
+        /// * `path`: The name of the pcap file (or the absolute or relative path)
+        /// #
+        /// Returns the number of packets written. Only the first packets allowed by [`set_flow_buffer`](Sniffer::set_flow_buffer) are available
+        pub fn dump_connection(&self, connection: usize, path: &str) -> std::result::Result<usize, SnifferError> {
+            if connection == 0 || connection > self.connections.len() {
+                return Err(SnifferError::ConnectionNotFound);
+            }
+            let mut file = Capture::dead(self.linktype)
+                .and_then(|cap| cap.savefile(path))
+                .map_err(|_| SnifferError::PcapOutputError)?;
+            let mut count = 0;
+            for (header, data) in self.packets.packets(connection - 1) {
+                file.write(&Packet::new(header, data));
+                count += 1;
+            }
+            file.flush().map_err(|_| SnifferError::PcapOutputError)?;
+            Ok(count)
+        }
+
+        /// Writes the reassembled payload of the TCP connections to a folder, one file for each direction:
+        /// `conn17_<sender ip>_<sender port>-<receiver ip>_<receiver port>.bin` holds the bytes sent by the sender to the receiver
+        /// of connection #17, in order and without retransmissions
+        /// ## Parameters
+        /// * `dir`: The folder (absolute or relative path) where the files are written, it must exist
+        /// * `filter`: (OPTIONAL) A filter selecting the connections to export (syntax is available at <https://biot.com/capstats/bpf.html>),
+        ///   all of them are exported if empty. A connection is exported starting from its first packet matching the filter.
+        ///   It is compiled for the link type of each device when the capture starts
+        pub fn set_stream_export(&mut self, dir: String, filter: String) {
+            self.streams = Some(StreamExport::new(dir, filter));
+        }
+
+        /// Keeps time series of the packets and bytes sent and received by the capture device, by application protocol
+        /// and by connection, exported along with the report
+        /// ## Parameters
+        /// * `file_name`: The name of the file (or the absolute or relative path) where the series are exported: JSON if it ends with `.json`, CSV otherwise
+        /// * `bucket_secs`: Length of each time bucket (secs)
+        /// * `top`: Number of connections exported, the ones with the most bytes
+        pub fn set_time_series(&mut self, file_name: String, bucket_secs: f64, top: usize) {
+            self.series = Some(TimeSeries::new((bucket_secs * 1_000_000.0) as i64, top));
+            self.series_file = file_name;
+        }
+
+        /// Writes the report also as JSON: the connections and the summary, every time the text report is written
+        /// ## Parameters
+        /// * `file_name`: The name of the JSON file (or the absolute or relative path)
+        pub fn set_json_report(&mut self, file_name: String) {
+            self.json_file = Some(file_name);
+        }
+
+        /// Shows a full-screen interface during the capture instead of the command prompt: the live connection table
+        /// (sortable by bytes, start time or protocol), bandwidth sparklines, a filter box and the details of the
+        /// selected connection. Keys: "p"/"r" pause and resume, "b"/"t"/"a" sort, "/" filter, "q" quit
+        pub fn set_tui(&mut self, enabled: bool) {
+            self.tui = enabled;
+        }
+
+        /// Accepts JSON-lines requests on a Unix domain socket during the capture, to manage it from another process:
+        /// `{"cmd":"pause"}`, `{"cmd":"resume"}`, `{"cmd":"stop"}`, `{"cmd":"snapshot","file":"<file>"}` (the file is optional),
+        /// `{"cmd":"status"}` and `{"cmd":"filter","filter":"<filter>"}`. Each request is answered by a JSON line
+        /// with `"ok"` set to `true`, or to `false` along with an `"error"` message
+        /// ## Parameters
+        /// * `path`: Path of the socket file, removed when the capture is over
+        #[cfg(unix)]
+        pub fn set_control_socket(&mut self, path: String) {
+            self.control_socket = Some(path);
+        }
+
+        /// Serves a REST API and a dashboard on `http://127.0.0.1:<port>/` during the capture:
+        /// `GET /connections` and `GET /stats` return the connection table and the counters as JSON,
+        /// `POST /control/pause`, `/control/resume`, `/control/stop`, `/control/snapshot` and `/control/filter`
+        /// (with `{"filter":"..."}` as body) manage the capture. The dashboard needs no external asset.
+        /// Only the requests with `Host: 127.0.0.1:<port>` or `Host: localhost:<port>` and no foreign `Origin` are served,
+        /// and the control requests must have `Content-Type: application/json`, so that web pages cannot reach the API
+        /// `GET /metrics` exports packets, bytes, parse errors, pcap drops, connections and bytes by application and
+        /// transport protocol to Prometheus, at most 50 application protocols are labelled, the others as "other"
+        /// ## Parameters
+        /// * `port`: TCP port, bound on localhost only
+        pub fn set_http_server(&mut self, port: u16) {
+            self.http_port = Some(port);
+        }
+
+        /// Exports each direction of the connections as a flow to a NetFlow v5, NetFlow v9 or IPFIX collector over UDP.
+        /// A flow is exported once it has been idle for `idle_timeout` or has lasted for `active_timeout`, and at the end
+        /// of the capture; its counters restart after each export. NetFlow v5 carries only the IPv4 flows
+        /// ## Parameters
+        /// * `collector`: Address of the collector, as `host:port`
+        /// * `version`: 5, 9, or 10 for IPFIX
+        /// * `domain`: Observation domain ID (source ID for v9, engine type and ID for v5)
+        /// * `active_timeout`, `idle_timeout`: Timeouts (secs)
+        pub fn set_flow_export(&mut self, collector: &str, version: u16, domain: u32, active_timeout: f64, idle_timeout: f64) -> std::result::Result<(), SnifferError> {
+            let export = FlowExport::new(collector, version, domain, active_timeout, idle_timeout)
+                .map_err(|_| SnifferError::FlowExportError)?;
+            self.flows = Some(export);
+            Ok(())
+        }
+
+        /// Writes the connections also as a Zeek `conn.log`, every time the report is written: uid, originator and
+        /// responder addresses and ports, service, duration, bytes and packets of each side, connection state and history.
+        /// The originator is the sender of the first packet (address B)
+        /// ## Parameters
+        /// * `file_name`: The name of the file (or the absolute or relative path): Zeek JSON if it ends with `.json`, Zeek TSV otherwise
+        pub fn set_zeek_log(&mut self, file_name: String) {
+            self.conn_log = Some(ConnLog::default());
+            self.conn_log_file = file_name;
+        }
+
+        /// Saves the capture history to a SQLite database, created if missing: the session (device, filter, start and end),
+        /// and snapshots taken periodically, on pause and at the end of the capture. Each snapshot records the totals of the
+        /// session and updates the connections changed since the previous one: the database keeps only the latest state of
+        /// each connection. The history can be read back with `query_history`
+        /// ## Parameters
+        /// * `path`: The database file (absolute or relative path)
+        /// * `snapshot_interval`: Time between two snapshots (secs), 0 to take them only on pause and at the end
+        pub fn set_database(&mut self, path: &str, snapshot_interval: f64) -> std::result::Result<(), SnifferError> {
+            self.database = Some(Database::open(path).map_err(|_| SnifferError::DatabaseError)?);
+            self.snapshot_interval = snapshot_interval;
+            Ok(())
+        }
+
+        /// Sets how the report file is written, by default it is overwritten on every pause and at the end.
+        /// When templated, the `strftime` fields of the file name, e.g. `results-%Y%m%d-%H%M%S.txt`, are replaced by the time
+        /// the file is started: a new file is then started on every report when overwriting, or on every rotation when appending.
+        /// Otherwise the name is used as it is, `%` included. Rotated files are renamed `<name>-<time>.<ext>` (unless templated)
+        /// and optionally compressed. Returns an error if the name is templated and has an invalid field
+        /// ## Parameters
+        /// * `policy`: Overwrite or append a section per report, templated name, size and time rotation, retention and compression
+        pub fn set_report_policy(&mut self, policy: ReportPolicy) -> std::result::Result<(), SnifferError> {
+            if policy.templated && !report::valid_template(&self.file_name) {
+                return Err(SnifferError::InvalidFileTemplate);
+            }
+            self.report = Arc::new(Mutex::new(ReportFile::new(&self.file_name, policy)));
+            Ok(())
+        }
+
+        /// Writes a report every `interval` seconds during the capture, following the output policy of the report file
+        /// (append or use a templated name to keep them all). The file is written by a thread of its own, so that the
+        /// capture goes on meanwhile
+        /// ## Parameters
+        /// * `interval`: Time between two reports (secs), 0 = none
+        /// * `delta`: `false` for cumulative reports, `true` for reports of the traffic since the previous one:
+        ///   only the connections with new bytes, and their bytes since then
+        pub fn set_report_interval(&mut self, interval: f64, delta: bool) {
+            self.report_interval = interval;
+            self.report_delta = delta;
+        }
+
     /// Returns nothing (unit) if the function succeds, otherwise returns a 'SnifferError' enum which details 
     /// the error encountered during the execution
     /// ```
@@ -247,56 +722,149 @@ pub mod packet_sniffer {
     /// It is possible to pause and resume the capture through the command line,
     /// by typing "p" and "r" respectively
         pub fn start_capture(& mut self) -> std::result::Result<(), SnifferError>{
-            let mut cap = Capture::from_device(self.dev.as_str()).unwrap()
-                        .promisc(true).timeout(500) //aggiunto timeout di 0.5s
-                        .open().map_err(|_| SnifferError::OpenErrorCapture)?;
-            cap.filter(&self.filter, true).map_err(|_| SnifferError::InvalidFilter)?;
+            //one thread per device
+            let capture = MultiCapture::open(&self.devs, &self.filter, &self.capture_options)?;
+            self.linktype = capture.linktypes()[0];
+            let devices: Vec<Device> = Device::list().unwrap_or_default().into_iter()
+                .filter(|d| self.devs.contains(&d.name))
+                .collect();
+            self.local_addrs = devices.iter()
+                .flat_map(|d| d.addresses.iter().map(|a| format_ip(&a.addr)))
+                .collect();
+            let mut pcap_out = match &self.pcap_output {
+                Some(out) => {
+                    //the secondary filter is compiled for the link type of each device
+                    let programs = if out.filter.is_empty() {
+                        None
+                    } else {
+                        let mut programs = vec![];
+                        for linktype in capture.linktypes() {
+                            let dead = Capture::dead(*linktype).map_err(|_| SnifferError::InvalidFilter)?;
+                            programs.push(dead.compile(&out.filter, true).map_err(|_| SnifferError::InvalidFilter)?);
+                        }
+                        Some(programs)
+                    };
+                    let interfaces = self.devs.iter().zip(capture.linktypes()).map(|(name, linktype)| Interface {
+                        name: name.clone(),
+                        desc: devices.iter().find(|d| &d.name == name).and_then(|d| d.desc.clone()),
+                        linktype: linktype.0,
+                        snaplen: self.capture_options.snaplen as u32,
+                        nanos: self.capture_options.nanos,
+                    }).collect();
+                    let writer = PcapngWriter::new(&out.path, out.rotate_size, out.rotate_time, interfaces)
+                        .map_err(|_| SnifferError::PcapOutputError)?;
+                    Some((writer, programs))
+                },
+                None => None,
+            };
+            if let Some(export) = self.streams.as_mut() {
+                export.compile(capture.linktypes()).map_err(|_| SnifferError::InvalidFilter)?;
+            }
             
             
+            let mut tui = if self.tui {
+                Some(Tui::new().map_err(|_| SnifferError::TerminalError)?)
+            } else {
+                None
+            };
+            let interactive = tui.is_none();
+            let mut paused = false;
+
+            //requests of the control socket that need the capture state are answered by the capture loop
+            let (query_sender, query_receiver) : (Sender<Query>, Receiver<Query>) = channel();
+            #[cfg(unix)]
+            let _control = match &self.control_socket {
+                Some(path) => Some(ControlSocket::bind(path, Arc::clone(&self.waiter), query_sender.clone())
+                    .map_err(|e| {
+                        eprintln!("{}: {}", path, e);
+                        SnifferError::ControlSocketError
+                    })?),
+                None => None,
+            };
+            let _http = match self.http_port {
+                Some(port) => Some(HttpServer::start(port, Arc::clone(&self.waiter), query_sender)
+                    .map_err(|_| SnifferError::HttpServerError)?),
+                None => None,
+            };
+
+            let mut last_expire = Instant::now();
+            let mut last_snapshot = Instant::now();
+            let mut last_report = Instant::now();
+            let report_writer = self.start_reports();
+            if let Some(Err(e)) = self.database.as_mut().map(|db| db.start_session(&self.devs.join(","), &self.filter)) {
+                eprintln!("Error writing the database: {}", e);
+                self.database = None;
+            }
+            //the snapshots are saved by a thread of their own
+            self.snapshot_ends.clear();
+            let database_writer = self.database.take().map(|db| {
+                let (sender, handle) = database::spawn_writer(db);
+                self.database_writer = Some(sender);
+                handle
+            });
+
             let (sender_end, receiver_end) : (Sender<String>, Receiver<String>) = channel();
+            //commands typed by the user that need the capture state are run by the capture loop
+            let (cmd_sender, cmd_receiver) : (Sender<Command>, Receiver<Command>) = channel();
             //cloning sync variable to be able to pass it to the sync thread
             let var = Arc::clone(&self.waiter);
-            println!("> Starting capture from device: {} ...", self.dev); 
-            println!("> Type \"p\" to pause ");
+            if interactive {
+                println!("> Starting capture from device: {} ...", self.devs.join(", "));
+                println!("> Type \"p\" to pause, \"help\" for the list of commands ");
+            }
             //TIMER THREAD (2)
             //"move" allows to use all variables outside the newly created thread
             let t = thread::spawn(move || {
                 let w = Arc::clone(&var);
                 //USER COMMAND THREAD (3) 
                 // - ttl depends on the father thread (this thread doesn't end on its own, stays in loop forever)
-                thread::spawn(move || {
-                    let mut cmd= String::new();
-                    loop{
-                        cmd.clear();
-                        print!("> ");
-                        stdout().flush().expect("Error flushing stdout buffer");
-                        //stdin().read_line è in attesa di comando da std input
-                        match stdin().read_line(&mut cmd){
-                            Ok(_val) => (),
-                            Err(e) => {eprintln!("{}", e); continue}
-                        }
-                        let r = cmd.trim();
-                        match r {
-                            "p" => {
-                                let mut res= w.state.lock().unwrap();
-                                if res.pause {drop(res); continue;}
-                                res.pause = true;
-                                w.cv.notify_all();
-                                drop(res);
-
-                            },
-                            "r" => {
-                                let mut res= w.state.lock().unwrap();
-                                if !res.pause {drop(res); continue;}
-                                res.pause = false;
-                                w.cv.notify_all();
-                                drop(res);
+                // - not started with the full-screen interface, which reads the keyboard itself
+                if interactive {
+                    thread::spawn(move || {
+                        let mut cmd= String::new();
+                        //the capture loop prints the prompt after the output of the commands it runs
+                        let mut prompt = true;
+                        loop{
+                            cmd.clear();
+                            if prompt {
+                                print!("> ");
+                                stdout().flush().expect("Error flushing stdout buffer");
+                            }
+                            prompt = true;
+                            //stdin().read_line è in attesa di comando da std input
+                            match stdin().read_line(&mut cmd){
+                                Ok(0) => return,
+                                Ok(_val) => (),
+                                Err(e) => {eprintln!("{}", e); continue}
+                            }
+                            let r = match commands::parse(&cmd) {
+                                Ok(r) => r,
+                                Err(e) => {eprintln!("{}", e); continue}
+                            };
+                            match r {
+                                Command::Pause => {
+                                    if !w.set_pause(true) { println!("The capture is already paused"); }
+                                },
+                                Command::Resume => {
+                                    if !w.set_pause(false) { println!("The capture is not paused"); }
+                                },
+                                Command::Time(change) => println!("Time left: {:.1} secs", w.change_time(&change)),
+                                Command::Quit => {
+                                    w.stop();
+                                    return;
+                                },
+                                Command::Help => println!("{}", commands::HELP),
+                                Command::Clear => print!("\x1B[2J\x1B[1;1H"),
+                                Command::Nothing => (),
+                                r => {
+                                    if cmd_sender.send(r).is_err() { return; }
+                                    prompt = false;
+                                }
                             }
-                             _ => ()
                         }
-                    }
 
-                });
+                    });
+                }
                 //back to timer thread
                 loop {
                     let mut s = var.state.lock().unwrap();
@@ -311,7 +879,7 @@ pub mod packet_sniffer {
                         let timer = s.time_interval.clone();
                         //inside function "wait" lock is freed, and is taken back when wait is over
                         //in fact the lock "s" is passed to the function
-                        let res = var.cv.wait_timeout(s, Duration::from_secs(timer as u64)).unwrap();
+                        let res = var.cv.wait_timeout(s, Duration::from_secs_f64(timer.max(0.0))).unwrap();
                         if res.1.timed_out() {
                             sender_end.send(String::from("timeout")).unwrap();
                             return ;
@@ -323,7 +891,9 @@ pub mod packet_sniffer {
                     //code reached only when mutex is in pause status (sniffer is paused)
                     s.pause_time=Instant::now();
                     s.time_interval -= (s.pause_time-s.start_time).as_secs_f64();
-                    println!("Time left: {:.1} secs", s.time_interval);
+                    if interactive {
+                        println!("Time left: {:.1} secs", s.time_interval);
+                    }
                 
                     sender_end.send(String::from("pause")).unwrap();
                 }
@@ -337,19 +907,26 @@ pub mod packet_sniffer {
                     Ok(val) => {
                         match val.as_str(){
                             "timeout" => break,
+                            "resume" => {
+                                //no message when resuming only to end the capture
+                                if paused && interactive && self.waiter.remaining() > 0.0 {
+                                    print!("RESUME!\n> Type \"p\" to pause\n> ");
+                                    stdout().flush().unwrap();
+                                }
+                                paused = false;
+                            },
                             "pause" => {
-                                self.print_connection();
-                                print!("> {} printed, work paused!\n> Type \"r\" to resume\n> ", self.file_name);
-                                stdout().flush().unwrap();
-                                //recv è bloccante -> il thread principale rimane in attesa passiva sul canale finché il timer thread non comunica "resume"
-                                let r = receiver_end.recv().unwrap();
-                                match r.as_str(){
-                                    "resume" => {
-                                            print!("RESUME!\n> Type \"p\" to pause\n> ");
-                                            stdout().flush().unwrap();
-                                    }
-                                    _ => ()
+                                let file_name = self.file_name.clone();
+                                self.print_report(None, move |printed| if interactive {
+                                    print!("> {} printed, work paused!\n> Type \"r\" to resume\n> ", printed.as_deref().unwrap_or(&file_name));
+                                    stdout().flush().unwrap();
+                                });
+                                self.save_snapshot();
+                                if let Some((writer, _)) = pcap_out.as_mut() {
+                                    writer.flush().unwrap_or_else(|e| eprintln!("Error writing the pcapng file: {}", e));
                                 }
+                                //the capture loop keeps running while paused, to serve the commands and the full-screen interface
+                                paused = true;
                             },
                             _ => ()
                         }
@@ -358,104 +935,600 @@ pub mod packet_sniffer {
                     _ => ()
                 }
 
-                match cap.next() {
-                    Ok(packet) => {
-                        match PacketHeaders::from_ethernet_slice(&packet) {
-                            Err(value) => println!("Err {:?}", value),
-                            Ok(value) => {
-                                let mut temp_l3: u8 = 6;
-                                #[allow(unused_assignments)]
-                                let mut temp_ip_1  = "".to_string();
-                                #[allow(unused_assignments)]
-                                let mut temp_ip_2 = "".to_string();
-                                let mut temp_l4: u8 = 0; 
-                                #[allow(unused_assignments)]
-                                let mut temp_port_1 = "".to_string();
-                                #[allow(unused_assignments)]
-                                let mut temp_port_2 = "".to_string();
-                                #[allow(unused_assignments)]
-                                let mut temp_prot = "".to_string();
-                                if value.ip.is_none() { continue; }
-                                match value.ip.unwrap() {
-                                    IpHeader::Version4(h, _e) => {
-                                        temp_l3 = 4;
-                                        let dest = sprintf!(
-                                            "%d.%d.%d.%d",
-                                            h.destination[0],
-                                            h.destination[1],
-                                            h.destination[2],
-                                            h.destination[3]
-                                        );
-                                        temp_ip_1 = dest.clone().unwrap();
-                                        let sour = sprintf!(
-                                            "%d.%d.%d.%d",
-                                            h.source[0],
-                                            h.source[1],
-                                            h.source[2],
-                                            h.source[3]
-                                        );
-                                        temp_ip_2 = sour.clone().unwrap();
-                                    }
-                                    IpHeader::Version6(h, _e) => {
-                                        let dest = sprintf!("%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x", 
-                                            h.destination[0],h.destination[1],h.destination[2],h.destination[3],
-                                            h.destination[4],h.destination[5],h.destination[6],h.destination[7],
-                                            h.destination[8],h.destination[9],h.destination[10],h.destination[11],
-                                            h.destination[12],h.destination[13],h.destination[14],h.destination[15]);
-                                            temp_ip_1 = dest.clone().unwrap();
-                                        let sour = sprintf!("%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x",
-                                            h.source[0],h.source[1],h.source[2],h.source[3],
-                                            h.source[4],h.source[5],h.source[6],h.source[7],
-                                            h.source[8],h.source[9],h.source[10],h.source[11],
-                                            h.source[12],h.source[13],h.source[14],h.source[15]);
-                                            temp_ip_2 = sour.clone().unwrap();
-                                    },
-                                }
-                                if value.transport.is_none(){ continue; }
-                                match value.transport.unwrap() {
-                                    TransportHeader::Tcp(h) => {
-                                        temp_port_1= h.destination_port.to_string();
-                                        temp_port_2 = h.source_port.to_string();
-                                    }
-                                    TransportHeader::Udp(h) => {
-                                        temp_l4 = 1;
-                                        temp_port_1= h.destination_port.to_string();
-                                        temp_port_2 = h.source_port.to_string();
-                                    }
-                                    _ => continue
-                                }
-                                temp_prot = self.app_prot(temp_l4.clone(), temp_port_1.clone(), temp_port_2.clone());
-                                //salviamo il vettore di connection
-                                let temp_ts = DateTime::from_local(NaiveDateTime::from_timestamp(packet.header.ts.tv_sec as i64, packet.header.ts.tv_usec as u32), *(chrono::Local::now().offset()))+*(chrono::Local::now().offset());
-                    
-                                let temp_connection = Connection::new(temp_l3,temp_ip_1.clone(),temp_ip_2,temp_l4,temp_port_1,temp_port_2,temp_ts.clone(),temp_ts.clone(),
-                                    packet.header.len,temp_prot);
-
-                                let mut found = false;
-                                let mut i: usize=0;
-                                while i < self.connections.len() {
-                                    if self.connections[i] == temp_connection{
-                                        self.connections[i].update(temp_ts, packet.header.len, temp_ip_1);
-                                        found = true;
-                                        break;
-                                    }
-                                    i+=1;
-                                }
-                                
-                                if !found {
-                                    self.connections.push(temp_connection);
-                                }
-                            }
-                        }
-                    },
-                    _ => (),
+                while let Ok(cmd) = cmd_receiver.try_recv() {
+                    self.run_command(cmd, &capture);
+                }
+                while let Ok(query) = query_receiver.try_recv() {
+                    self.answer_query(query, paused, &capture);
+                }
+                if last_expire.elapsed() >= Duration::from_secs(1) {
+                    last_expire = Instant::now();
+                    self.export_flows(false);
+                    let crossed = capture.stats().is_some_and(|stats| self.update_stats(stats));
+                    if let Some(warning) = self.drop_warning().filter(|_| crossed && interactive) {
+                        eprintln!("{}", warning);
+                    }
+                }
+                if self.snapshot_interval > 0.0 && last_snapshot.elapsed().as_secs_f64() >= self.snapshot_interval {
+                    last_snapshot = Instant::now();
+                    self.save_snapshot();
+                }
+
+                if let Some(ui) = tui.as_mut() {
+                    match ui.keys(paused) {
+                        Ok(Some(Action::Pause)) => { self.waiter.set_pause(true); },
+                        Ok(Some(Action::Resume)) => { self.waiter.set_pause(false); },
+                        Ok(Some(Action::Quit)) => self.waiter.stop(),
+                        _ => (),
+                    }
+                    let device = self.devs.join(",");
+                    let warning = self.drop_warning();
+                    let status = Status { device: &device, paused, remaining: self.waiter.remaining(), warning: warning.as_deref() };
+                    if let Err(e) = ui.draw(&self.connections, &|i| self.connection_details(i), &status) {
+                        tui = None;
+                        eprintln!("{}: {}", SnifferError::TerminalError, e);
+                    }
+                }
+                if paused {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                if self.report_interval > 0.0 && last_report.elapsed().as_secs_f64() >= self.report_interval {
+                    last_report = Instant::now();
+                    self.periodic_report();
+                }
+
+                if let Some(captured) = capture.next(Duration::from_millis(50)) {
+                    self.handle_packet(captured, &mut pcap_out);
                 }
             }
             t.join().unwrap();
-            self.print_connection();
+            drop(tui);
+            self.drain_capture(capture, &mut pcap_out);
+            if let Some(warning) = self.drop_warning() {
+                eprintln!("{}", warning);
+            }
+            if let Some((writer, _)) = pcap_out.as_mut() {
+                writer.flush().unwrap_or_else(|e| eprintln!("Error writing the pcapng file: {}", e));
+            }
+            self.export_flows(true);
+            self.save_snapshot();
+            self.database_writer = None;
+            if let Some(handle) = database_writer {
+                self.database = handle.join().ok();
+            }
+            self.finish_reports(report_writer);
             return Ok(());
         }
 
+        /// Ends the capture threads and processes the packets still queued, which were captured before the end,
+        /// then records the final statistics of pcap
+        fn drain_capture(&mut self, capture: MultiCapture, pcap_out: &mut Option<(PcapngWriter, Option<Vec<BpfProgram>>)>) {
+            capture.stop();
+            while let Some(captured) = capture.drain() {
+                self.handle_packet(captured, pcap_out);
+            }
+            if let Some(stats) = capture.close() {
+                self.update_stats(stats);
+            }
+        }
+
+        /// Counts and processes a captured packet, and writes it to the pcapng file if it passes the secondary filter
+        fn handle_packet(&mut self, captured: CapturedPacket, pcap_out: &mut Option<(PcapngWriter, Option<Vec<BpfProgram>>)>) {
+            //the pcapng file keeps the nanoseconds, the rest of the sniffer works in microseconds
+            let mut header = captured.header;
+            if self.capture_options.nanos {
+                header.ts.tv_usec /= 1000;
+            }
+            let packet = Packet::new(&header, &captured.data);
+            self.counters.packets += 1;
+            self.counters.bytes += packet.header.len as u64;
+            let connection = self.process_packet(captured.interface, &packet);
+            if let Some(i) = connection {
+                self.packets.add(i, packet.header, packet.data);
+            }
+            if let Some((writer, programs)) = pcap_out.as_mut() {
+                if programs.as_ref().is_none_or(|p| p[captured.interface].filter(packet.data)) {
+                    if let Err(e) = writer.write_packet(captured.interface, &captured.header, packet.data, connection) {
+                        eprintln!("Error writing the pcapng file: {}", e);
+                        *pcap_out = None;
+                    }
+                }
+            }
+        }
+
+        /// Records the statistics of pcap. Returns `true` if the percentage of dropped packets has just crossed the threshold
+        fn update_stats(&mut self, stats: Stat) -> bool {
+            self.counters.received = stats.received as u64;
+            self.counters.dropped = stats.dropped as u64;
+            self.counters.if_dropped = stats.if_dropped as u64;
+            let above = self.drop_threshold > 0.0 && self.counters.drop_rate().is_some_and(|r| r > self.drop_threshold);
+            let crossed = above && !self.drop_warned;
+            self.drop_warned = above;
+            crossed
+        }
+
+        /// Warning about the packets dropped, while their percentage is above the threshold
+        fn drop_warning(&self) -> Option<String> {
+            if !self.drop_warned {
+                return None;
+            }
+            self.counters.drop_rate().map(|r| format!("WARNING: {:.2}% of the packets were dropped (threshold {}%), the connection totals are incomplete",
+                r, self.drop_threshold))
+        }
+
+        /// Sends a snapshot to the database writer thread: the totals, and the connections changed since the previous snapshot
+        fn save_snapshot(&mut self){
+            if self.database_writer.is_none() {
+                return;
+            }
+            let changed = self.connections.iter().enumerate()
+                .filter(|(i, con)| self.snapshot_ends.get(*i) != Some(&con.ts_end))
+                .map(|(i, con)| (i + 1, con.clone(), self.connection_details(i)))
+                .collect();
+            self.snapshot_ends = self.connections.iter().map(|c| c.ts_end).collect();
+            let snapshot = Snapshot {
+                changed,
+                connections: self.connections.len(),
+                bytes: self.connections.iter().map(|c| c.bytes_a_to_b as u64 + c.bytes_b_to_a as u64).sum(),
+            };
+            if let Some(writer) = &self.database_writer {
+                let _ = writer.send(snapshot);
+            }
+        }
+
+        /// Sends the expired flows to the collector, or all of them if `all`. Errors disable the export
+        fn export_flows(&mut self, all: bool){
+            if let Some(flows) = self.flows.as_mut() {
+                let res = if all { flows.flush(&self.connections) } else { flows.expire(&self.connections) };
+                if let Err(e) = res {
+                    eprintln!("Error sending the flows to the collector: {}", e);
+                    self.flows = None;
+                }
+            }
+        }
+
+        /// Parses a packet captured by device `interface` and accounts it to its connection, creating the connection if needed.
+        /// Returns the index of the connection, or `None` if the packet doesn't belong to a TCP/UDP flow
+        fn process_packet(&mut self, interface: usize, packet: &Packet) -> Option<usize> {
+            match PacketHeaders::from_ethernet_slice(packet.data) {
+                Err(value) => {
+                    self.counters.parse_errors += 1;
+                    //the full-screen interface would be overwritten
+                    if !self.tui {
+                        println!("Err {:?}", value);
+                    }
+                    None
+                },
+                Ok(value) => {
+                    let mut temp_l3: u8 = 6;
+                    #[allow(unused_assignments)]
+                    let mut temp_ip_1  = "".to_string();
+                    #[allow(unused_assignments)]
+                    let mut temp_ip_2 = "".to_string();
+                    let mut temp_l4: u8 = 0; 
+                    let mut payload = value.payload;
+                    #[allow(unused_assignments)]
+                    let mut ip_payload_len = 0;
+                    #[allow(unused_assignments)]
+                    let mut ip_len = 0;
+                    #[allow(unused_assignments)]
+                    let mut temp_port_1 = "".to_string();
+                    #[allow(unused_assignments)]
+                    let mut temp_port_2 = "".to_string();
+                    #[allow(unused_assignments)]
+                    let mut temp_prot = "".to_string();
+                    let mut tcp = None;
+                    let ip = match value.ip {
+                        Some(ip) => ip,
+                        None => {
+                            self.counters.non_ip += 1;
+                            return None;
+                        }
+                    };
+                    match ip {
+                        IpHeader::Version4(h, e) => {
+                            temp_l3 = 4;
+                            ip_payload_len = (h.payload_len as usize).saturating_sub(e.header_len());
+                            ip_len = h.header_len() + h.payload_len as usize;
+                            let dest = sprintf!(
+                                "%d.%d.%d.%d",
+                                h.destination[0],
+                                h.destination[1],
+                                h.destination[2],
+                                h.destination[3]
+                            );
+                            temp_ip_1 = dest.clone().unwrap();
+                            let sour = sprintf!(
+                                "%d.%d.%d.%d",
+                                h.source[0],
+                                h.source[1],
+                                h.source[2],
+                                h.source[3]
+                            );
+                            temp_ip_2 = sour.clone().unwrap();
+                        }
+                        IpHeader::Version6(h, e) => {
+                            ip_payload_len = (h.payload_length as usize).saturating_sub(e.header_len());
+                            ip_len = 40 + h.payload_length as usize;
+                            let dest = sprintf!("%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x", 
+                                h.destination[0],h.destination[1],h.destination[2],h.destination[3],
+                                h.destination[4],h.destination[5],h.destination[6],h.destination[7],
+                                h.destination[8],h.destination[9],h.destination[10],h.destination[11],
+                                h.destination[12],h.destination[13],h.destination[14],h.destination[15]);
+                                temp_ip_1 = dest.clone().unwrap();
+                            let sour = sprintf!("%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x:%02x%02x",
+                                h.source[0],h.source[1],h.source[2],h.source[3],
+                                h.source[4],h.source[5],h.source[6],h.source[7],
+                                h.source[8],h.source[9],h.source[10],h.source[11],
+                                h.source[12],h.source[13],h.source[14],h.source[15]);
+                                temp_ip_2 = sour.clone().unwrap();
+                        },
+                    }
+                    let transport = match value.transport {
+                        Some(transport) => transport,
+                        None => {
+                            self.counters.skipped_transports += 1;
+                            return None;
+                        }
+                    };
+                    match transport {
+                        TransportHeader::Tcp(h) => {
+                            temp_port_1= h.destination_port.to_string();
+                            temp_port_2 = h.source_port.to_string();
+                            //the ethernet trailer may follow the segment
+                            let tcp_len = ip_payload_len.saturating_sub(h.header_len() as usize);
+                            if tcp_len < payload.len() {
+                                payload = &payload[..tcp_len];
+                            }
+                            let wscale = h.options_iterator().find_map(|o| match o {
+                                Ok(TcpOptionElement::WindowScale(s)) => Some(s),
+                                _ => None,
+                            });
+                            tcp = Some(TcpPacket {
+                                seq: h.sequence_number,
+                                ack_num: h.acknowledgment_number,
+                                syn: h.syn,
+                                ack: h.ack,
+                                fin: h.fin,
+                                rst: h.rst,
+                                window: h.window_size,
+                                wscale,
+                                len: payload.len() as u32,
+                            });
+                        }
+                        TransportHeader::Udp(h) => {
+                            temp_l4 = 1;
+                            temp_port_1= h.destination_port.to_string();
+                            temp_port_2 = h.source_port.to_string();
+                            //the ethernet trailer may follow the datagram
+                            let udp_len = (h.length as usize).saturating_sub(8);
+                            if udp_len < payload.len() {
+                                payload = &payload[..udp_len];
+                            }
+                        }
+                        _ => {
+                            self.counters.skipped_transports += 1;
+                            return None;
+                        }
+                    }
+                    temp_prot = self.app_prot(temp_l4.clone(), temp_port_1.clone(), temp_port_2.clone());
+                    //salviamo il vettore di connection
+                    let temp_ts = packet_time(packet.header);
+        
+                    let temp_connection = Connection::new(temp_l3,temp_ip_1.clone(),temp_ip_2.clone(),temp_l4,temp_port_1,temp_port_2.clone(),temp_ts.clone(),temp_ts.clone(),
+                        packet.header.len,temp_prot);
+
+                    let mut found = false;
+                    let mut i: usize=0;
+                    while i < self.connections.len() {
+                        if self.connections[i] == temp_connection{
+                            self.connections[i].update(temp_ts, packet.header.len, temp_ip_1);
+                            found = true;
+                            break;
+                        }
+                        i+=1;
+                    }
+                    
+                    if !found {
+                        self.connections.push(temp_connection);
+                        self.link_ftp_data(i);
+                    }
+                    self.connections[i].add_interface(&self.devs[interface]);
+
+                    let con = &self.connections[i];
+                    //direction 0 is the one of the first packet, sent by ip_2
+                    let dir = if con.ip_2 == temp_ip_2 && con.port_2 == temp_port_2 { 0 } else { 1 };
+                    //the timeval field types differ between platforms
+                    #[allow(clippy::useless_conversion)]
+                    let ts = i64::from(packet.header.ts.tv_sec) * 1_000_000 + i64::from(packet.header.ts.tv_usec);
+                    if let Some(flows) = self.flows.as_mut() {
+                        let flags = tcp.as_ref().map_or(0, |t| t.fin as u8 | (t.syn as u8) << 1 | (t.rst as u8) << 2 | (t.ack as u8) << 4);
+                        flows.add(i, dir, packet.header.len, flags, ts);
+                    }
+                    if let Some(log) = self.conn_log.as_mut() {
+                        log.add(i, dir, ip_len as u64, payload.len() as u64, tcp.as_ref());
+                    }
+                    if let Some(tcp) = tcp {
+                        self.tcp_metrics.entry(i).or_default().update(dir, &tcp, ts);
+                        if let Some(export) = self.streams.as_mut() {
+                            export.select(i, interface, packet.data);
+                        }
+                        let segment = Segment { seq: tcp.seq, syn: tcp.syn, payload };
+                        self.reassemble(i, dir, &temp_ip_2, &temp_port_2, segment);
+                    } else {
+                        self.inspect_udp(i, &temp_ip_2, payload, value.link.as_ref(), temp_ts);
+                    }
+                    if let Some(series) = self.series.as_mut() {
+                        let outgoing = self.local_addrs.contains(&temp_ip_2);
+                        series.add(&self.devs[interface], &self.connections[i].app_prot, i, temp_ts.timestamp_micros(), packet.header.len, outgoing);
+                    }
+                    Some(i)
+                }
+            }
+        }
+
+        /// Looks inside the UDP payload of connection `index`, sent by `src_ip`, to decode the protocols the sniffer knows about
+        fn inspect_udp(&mut self, index: usize, src_ip: &str, payload: &[u8], link: Option<&Ethernet2Header>, ts: DateTime<Local>){
+            let details = match self.connections[index].app_prot.as_str() {
+                "mDNS" => self.services.inspect_mdns(src_ip, payload),
+                "SSDP" => self.services.inspect_ssdp(src_ip, payload),
+                "CoAP" => {
+                    self.coap.entry(index).or_default().update(payload);
+                    vec![]
+                }
+                _ => vec![],
+            };
+            for d in details {
+                self.connections[index].add_detail(d);
+            }
+
+            let msg = match self.connections[index].app_prot.as_str() {
+                "DHCP" => dhcp::parse_dhcpv4(payload),
+                "DHCPv6" => dhcp::parse_dhcpv6(payload),
+                _ => None,
+            };
+            if let Some(msg) = msg {
+                //the client is the sender, unless the server is answering
+                let mac = link.map(|l| dhcp::format_mac(if msg.from_server { &l.destination } else { &l.source }));
+                self.hosts.observe(&msg, mac, ts);
+                self.connections[index].add_detail(msg.summary());
+                return;
+            }
+
+            if let Some(info) = self.quic.inspect(index, payload) {
+                let con = &mut self.connections[index];
+                if con.app_prot != "HTTP3" {
+                    con.app_prot = info.label();
+                }
+                con.add_detail(format!("QUIC {}", info.version));
+                if let Some(hello) = &info.hello {
+                    con.app_prot = info.label();
+                    if let Some(sni) = &hello.server_name {
+                        con.add_detail(format!("SNI {}", sni));
+                    }
+                    if !hello.alpn.is_empty() {
+                        con.add_detail(format!("ALPN {}", hello.alpn.join(",")));
+                    }
+                }
+            }
+        }
+
+        /// Reassembles a TCP segment of connection `index`, sent by `src_ip`:`src_port` in direction `dir`,
+        /// then hands the in-order payload to the stream export and to the decoders.
+        /// Only the connections exported, or whose protocol is decoded from the stream, are reassembled
+        fn reassemble(&mut self, index: usize, dir: usize, src_ip: &str, src_port: &str, segment: Segment){
+            let exported = self.streams.as_ref().is_some_and(|s| s.is_selected(index));
+            if !exported && !stream_decoded(&self.connections[index].app_prot) {
+                return;
+            }
+            let data = self.reassembly.add(index, dir, &segment);
+            if data.is_empty() {
+                return;
+            }
+
+            if self.streams.is_some() {
+                let name = self.stream_file_name(index, dir);
+                if let Some(Err(e)) = self.streams.as_mut().map(|s| s.write(index, dir, &name, &data)) {
+                    eprintln!("Error writing the TCP stream files: {}", e);
+                    self.streams = None;
+                }
+            }
+
+            let mut unparsed = self.reassembly.take_unparsed(index, dir);
+            unparsed.extend_from_slice(&data);
+            let consumed = self.inspect_tcp(index, src_ip, src_port, &unparsed);
+            self.reassembly.keep_unparsed(index, dir, unparsed.split_off(consumed));
+        }
+
+        /// Name of the file holding the payload of connection `index` sent in direction `dir`
+        fn stream_file_name(&self, index: usize, dir: usize) -> String {
+            let con = &self.connections[index];
+            let (sender, receiver) = if dir == 0 {
+                ((&con.ip_2, &con.port_2), (&con.ip_1, &con.port_1))
+            } else {
+                ((&con.ip_1, &con.port_1), (&con.ip_2, &con.port_2))
+            };
+            //':' is not allowed in file names on every platform
+            format!("conn{}_{}_{}-{}_{}.bin", index + 1, sender.0.replace(':', "."), sender.1, receiver.0.replace(':', "."), receiver.1)
+        }
+
+        /// Looks inside the reassembled TCP payload of connection `index`, sent by `src_ip`:`src_port`.
+        /// Returns the number of bytes consumed: the rest is an incomplete message, given back with the next data
+        fn inspect_tcp(&mut self, index: usize, src_ip: &str, src_port: &str, payload: &[u8]) -> usize {
+            let app_prot = self.connections[index].app_prot.clone();
+            let from_server = self.app_prot(0, src_port.to_string(), String::new()) == app_prot;
+            match app_prot.as_str() {
+                "Modbus" => return self.modbus.entry(index).or_default().update(from_server, payload),
+                "MQTT" => return self.mqtt.entry(index).or_default().update(from_server, payload),
+                "SSH" | "SMTP" | "FTP" | "POP3" | "IMAP" => (),
+                _ => return payload.len(),
+            }
+            //only complete lines are inspected
+            let consumed = match payload.iter().rposition(|b| *b == b'\n') {
+                Some(pos) => pos + 1,
+                None => return 0,
+            };
+            for finding in self.banners.inspect(index, &app_prot, from_server, &payload[..consumed]) {
+                match finding {
+                    Finding::Detail(d) => self.connections[index].add_detail(d),
+                    Finding::FtpData { ip, port, passive } => {
+                        //EPSV only announces the port, on the server address
+                        let ip = match ip.map(|i| i.parse::<IpAddr>()) {
+                            Some(Ok(addr)) => format_ip(&addr),
+                            _ => src_ip.to_string(),
+                        };
+                        let cmd = if passive { "PASV" } else { "PORT" };
+                        self.connections[index].add_detail(format!("{} {}:{}", cmd, ip, port));
+                        self.banners.expect_data_channel(ip, port, index);
+                    }
+                }
+            }
+            consumed
+        }
+
+        /// Runs a command typed by the user that needs the capture state, then prints the prompt again
+        fn run_command(&mut self, cmd: Command, capture: &MultiCapture){
+            match cmd {
+                Command::Stats => {
+                    let tcp = self.connections.iter().filter(|c| c.l4 == Transport::TCP).count();
+                    let bytes: u64 = self.connections.iter().map(|c| c.bytes_a_to_b as u64 + c.bytes_b_to_a as u64).sum();
+                    println!("Device: {}", self.devs.join(", "));
+                    println!("Filter: {}", if self.filter.is_empty() { "-" } else { &self.filter });
+                    println!("Packets: {}", self.counters.packets);
+                    println!("Dropped: {} by the kernel, {} by the interface, of {} received", self.counters.dropped,
+                        self.counters.if_dropped, self.counters.received);
+                    println!("Bytes: {}", bytes);
+                    println!("Connections: {} (TCP {}, UDP {})", self.connections.len(), tcp, self.connections.len() - tcp);
+                    println!("Time left: {:.1} secs", self.waiter.remaining().max(0.0));
+                },
+                Command::Top(n) => {
+                    let bytes = |i: &usize| self.connections[*i].bytes_a_to_b as u64 + self.connections[*i].bytes_b_to_a as u64;
+                    let mut top: Vec<usize> = (0..self.connections.len()).collect();
+                    top.sort_by_key(|i| std::cmp::Reverse(bytes(i)));
+                    println!("| N°    | {0: <46} | {1: <46} | {2: <21} | Bytes", "Address A", "Address B", "Application Protocol");
+                    for i in top.into_iter().take(n) {
+                        let con = &self.connections[i];
+                        println!("| {0: <5} | {1: <46} | {2: <46} | {3: <21} | {4}", i + 1,
+                            format!("{} {}", con.ip_1, con.port_1), format!("{} {}", con.ip_2, con.port_2), con.app_prot, bytes(&i));
+                    }
+                },
+                Command::Dump(file) => self.print_report(file, |printed| if let Some(file) = printed {
+                    print!("{} printed\n> ", file);
+                    stdout().flush().unwrap();
+                }),
+                Command::Extract(connection, file) => match self.dump_connection(connection, &file) {
+                    Ok(n) => println!("{} packets of connection #{} written to {}", n, connection, file),
+                    Err(e) => eprintln!("{}", e),
+                },
+                Command::Filter(None) => println!("Filter: {}", if self.filter.is_empty() { "-" } else { &self.filter }),
+                Command::Filter(Some(filter)) => match self.change_filter(capture, filter) {
+                    Ok(_) => println!("Filter set to: {}", self.filter),
+                    Err(e) => eprintln!("{}", e),
+                },
+                _ => (),
+            }
+            print!("> ");
+            stdout().flush().unwrap();
+        }
+
+        /// Answers a request of the control socket that needs the capture state, on the reply channel of `query`
+        fn answer_query(&mut self, query: Query, paused: bool, capture: &MultiCapture) {
+            let reply = match query.request {
+                Request::Status => requests::ok(json!({
+                    "device": self.devs.join(","),
+                    "filter": self.filter,
+                    "paused": paused,
+                    "remaining": self.waiter.remaining().max(0.0),
+                    "packets": self.counters.packets,
+                    "received": self.counters.received,
+                    "dropped": self.counters.dropped,
+                    "if_dropped": self.counters.if_dropped,
+                    "connections": self.connections.len(),
+                    "bytes": self.connections.iter().map(|c| c.bytes_a_to_b as u64 + c.bytes_b_to_a as u64).sum::<u64>(),
+                })),
+                Request::Snapshot { file } => {
+                    //answered by the report writer thread once the file is written
+                    let name = file.clone().unwrap_or_else(|| self.file_name.clone());
+                    let reply = query.reply;
+                    self.print_report(file, move |printed| {
+                        let _ = reply.send(match printed {
+                            Some(file) => requests::ok(json!({ "file": file })),
+                            None => requests::error(&format!("Impossible to write {}", name)),
+                        });
+                    });
+                    return;
+                },
+                Request::Metrics => {
+                    if let Some(stats) = capture.stats() {
+                        self.update_stats(stats);
+                    }
+                    requests::ok(json!({ "metrics": metrics::render(&self.counters, &self.connections) }))
+                },
+                Request::Connections => requests::ok(json!({ "connections": self.connections_json() })),
+                Request::Filter { filter: None } => requests::ok(json!({ "filter": self.filter })),
+                Request::Filter { filter: Some(filter) } => match self.change_filter(capture, filter) {
+                    Ok(_) => requests::ok(json!({ "filter": self.filter })),
+                    Err(e) => requests::error(&e.to_string()),
+                },
+                Request::Extract { connection, file } => {
+                    let file = file.unwrap_or_else(|| format!("connection{}.pcap", connection));
+                    match self.dump_connection(connection, &file) {
+                        Ok(n) => requests::ok(json!({ "file": file, "packets": n })),
+                        Err(e) => requests::error(&e.to_string()),
+                    }
+                },
+                //handled by the control socket itself
+                Request::Pause | Request::Resume | Request::Stop => requests::ok(json!({})),
+            };
+            let _ = query.reply.send(reply);
+        }
+
+        /// Replaces the filter of the running capture, keeping the old one if the new one is invalid
+        fn change_filter(&mut self, capture: &MultiCapture, filter: String) -> std::result::Result<(), SnifferError> {
+            capture.set_filter(&filter)?;
+            self.filter = filter;
+            Ok(())
+        }
+
+        /// Labels connection `index` as an FTP data connection if its endpoint was negotiated
+        /// on an FTP control connection
+        fn link_ftp_data(&mut self, index: usize){
+            let con = &self.connections[index];
+            let control = self.banners.data_channel_control(&con.ip_1, &con.port_1)
+                .or_else(|| self.banners.data_channel_control(&con.ip_2, &con.port_2));
+            if let Some(control) = control {
+                self.connections[index].app_prot = String::from("FTP-DATA");
+                self.connections[index].add_detail(format!("Control connection #{}", control + 1));
+                self.connections[control].add_detail(format!("Data connection #{}", index + 1));
+            }
+        }
+
+        /// Returns the content of the "Details" column of connection `index`: the facts collected
+        /// from the payload followed by the per-protocol summaries
+        fn connection_details(&self, index: usize) -> String {
+            let mut details = self.connections[index].details.clone();
+            if let Some(s) = self.modbus.get(&index).filter(|s| !s.is_empty()) {
+                details.push(s.to_string());
+            }
+            if let Some(s) = self.mqtt.get(&index).filter(|s| !s.is_empty()) {
+                details.push(s.to_string());
+            }
+            if let Some(s) = self.coap.get(&index).filter(|s| !s.is_empty()) {
+                details.push(s.to_string());
+            }
+            //the devices are only worth showing when there is more than one
+            if self.devs.len() > 1 {
+                let interfaces = &self.connections[index].interfaces;
+                details.insert(0, format!("{} {}", if interfaces.len() > 1 { "Interfaces" } else { "Interface" }, interfaces.join(",")));
+            }
+            let gaps = self.reassembly.gaps(index);
+            if gaps > 0 {
+                details.push(format!("Missing TCP data x{}", gaps));
+            }
+            details.join("; ")
+        }
+
         fn app_prot(&self, transport_protocol: u8, port_a: String, port_b: String) -> String {
             if transport_protocol == 0 {
                 return match port_a.as_str() {
@@ -471,7 +1544,10 @@ pub mod packet_sniffer {
                     "465" => String::from("SMTPS"),
                     "546" | "547" => String::from("DHCPv6"),
                     "993" => String::from("IMAPS"),
+                    "502" => String::from("Modbus"),
                     "5353" => String::from("mDNS"),
+                    "1883" => String::from("MQTT"),
+                    "8883" => String::from("MQTTS"),
                     _=> { match port_b.as_str(){
                             "20"|"21" => String::from("FTP"),
                             "22" => String::from("SSH"),
@@ -484,7 +1560,10 @@ pub mod packet_sniffer {
                             "465" => String::from("SMTPS"),
                             "546" | "547" => String::from("DHCPv6"),
                             "993" => String::from("IMAPS"),
+                            "502" => String::from("Modbus"),
                             "5353" => String::from("mDNS"),
+                            "1883" => String::from("MQTT"),
+                            "8883" => String::from("MQTTS"),
                             _ => String::from("Unknown")
 
                     }}
@@ -501,6 +1580,8 @@ pub mod packet_sniffer {
                     "546" | "547" => String::from("DHCPv6"),
                     "1900" => String::from("SSDP"),
                     "5353" => String::from("mDNS"),
+                    "5683" => String::from("CoAP"),
+                    "5684" => String::from("CoAPS"),
                     _=> { 
                         match port_b.as_str(){
                             "53" => String::from("DNS"),
@@ -513,6 +1594,8 @@ pub mod packet_sniffer {
                             "546" | "547" => String::from("DHCPv6"),
                             "1900" => String::from("SSDP"),
                             "5353" => String::from("mDNS"),
+                            "5683" => String::from("CoAP"),
+                            "5684" => String::from("CoAPS"),
                             _ => String::from("Unknown")
                         }
                     }
@@ -521,21 +1604,522 @@ pub mod packet_sniffer {
             
         }
 
-        /// print_connection creates or overwrites a file writing the result of sniffing
-        fn print_connection(&self){
-            let mut writer= File::create(self.file_name.clone()).unwrap();
+        /// JSON form of the connection table, as in the JSON report
+        fn connections_json(&self) -> Vec<serde_json::Value> {
+            self.connections.iter().enumerate().map(|(i, con)| json!({
+                "number": i + 1,
+                "ip_protocol": con.l3.to_string(),
+                "address_a": con.ip_1,
+                "address_b": con.ip_2,
+                "transport_protocol": con.l4.to_string(),
+                "port_a": con.port_1,
+                "port_b": con.port_2,
+                "start": con.ts_start.to_rfc3339(),
+                "end": con.ts_end.to_rfc3339(),
+                "app_prot": con.app_prot,
+                "bytes_a_to_b": con.bytes_a_to_b,
+                "bytes_b_to_a": con.bytes_b_to_a,
+                "interfaces": con.interfaces,
+                "details": self.connection_details(i),
+            })).collect()
+        }
 
-         
-            let mut i = 1;
-            writeln!(writer, " WIRECATFISH packet capture\n").unwrap();
-            writeln!(writer, "| N°    | {0: <11} | {1: <40} | {2: <40} | {3: <18} | {4: <9} | {5: <9} | {6: <19} | {7: <19} | {8: <21} | {9: <13} | {10: <13} |",
-                "IP Protocol", "Address A", "Address B", "Transport Protocol", "Port A", "Port B",  "Connection Start", "Connection End ", "Application Protocol", "Bytes A->B", "Bytes B->A").unwrap();
+        /// print_report queues the report for the report writer thread, which writes it to the report file following the output
+        /// policy, or to `file`, then calls `done` with the name of the file written, or `None` if it cannot be written.
+        /// The other outputs are written right away
+        fn print_report(&self, file: Option<String>, done: impl FnOnce(Option<String>) + Send + 'static) {
+            let data = self.report_data(None);
+            let name = file.clone().unwrap_or_else(|| self.file_name.clone());
+            let job = ReportJob {
+                render: Box::new(move |w| data.write(w)),
+                file,
+                done: Box::new(move |res| match res {
+                    Ok(path) => done(Some(path.display().to_string())),
+                    Err(e) => {
+                        eprintln!("Error writing the report {}: {}", name, e);
+                        done(None);
+                    },
+                }),
+            };
+            //outside of the capture there is no writer thread
+            match &self.report_writer {
+                Some(writer) => { let _ = writer.send(job); },
+                None => report::run(job, &self.report),
+            }
+            self.write_outputs();
+        }
 
-            for con in self.connections.clone() {
-                writeln!(writer, "| {0: <5} | {1}        | {2: <40} | {3: <40} | {4}                | {5: <9} | {6: <9} | {7: <19} | {8: <19} | {9: <21} | {10: <13} | {11: <13} |",
-                    i, con.l3, con.ip_1, con.ip_2, con.l4, con.port_1, con.port_2, con.ts_start.format("%Y/%m/%d %H:%M:%S"), con.ts_end.format("%Y/%m/%d %H:%M:%S"), con.app_prot, con.bytes_a_to_b, con.bytes_b_to_a).unwrap();
-            i+=1;
+        /// Starts the report writer thread, the periodic reports count the traffic from now on
+        fn start_reports(&mut self) -> thread::JoinHandle<()> {
+            self.last_report = (vec![], Local::now());
+            //the reports are rendered and written by a thread of their own, the capture loop only copies the state
+            let (sender, handle) = report::spawn_writer(Arc::clone(&self.report));
+            self.report_writer = Some(sender);
+            handle
+        }
+
+        /// Queues the final report after the periodic ones, then waits for the report writer thread to write them all
+        fn finish_reports(&mut self, writer: thread::JoinHandle<()>) {
+            self.print_report(None, |_| ());
+            self.report_writer = None;
+            let _ = writer.join();
+        }
+
+        /// Queues a periodic report, cumulative or with the traffic since the previous one
+        fn periodic_report(&mut self) {
+            let bytes = self.connections.iter().map(|c| (c.bytes_a_to_b, c.bytes_b_to_a)).collect();
+            let last = std::mem::replace(&mut self.last_report, (bytes, Local::now()));
+            let data = self.report_data(Some(last).filter(|_| self.report_delta));
+            if let Some(writer) = &self.report_writer {
+                let _ = writer.send(ReportJob {
+                    render: Box::new(move |w| data.write(w)),
+                    file: None,
+                    done: Box::new(|res| if let Err(e) = res {
+                        eprintln!("Error writing the periodic report: {}", e);
+                    }),
+                });
             }
         }
+
+        /// Copy of the state shown in the text report, for the report writer thread
+        /// ## Parameters
+        /// * `since`: (OPTIONAL) Bytes of each connection at the previous report and its time, to report only the traffic since then
+        fn report_data(&self, since: Option<ReportBytes>) -> ReportData {
+            ReportData {
+                connections: self.connections.clone(),
+                details: (0..self.connections.len()).map(|i| self.connection_details(i)).collect(),
+                counters: self.counters.clone(),
+                drop_warning: self.drop_warning(),
+                hosts: self.hosts.clone(),
+                services: self.services.clone(),
+                tcp_metrics: self.tcp_metrics.iter().map(|(i, m)| (*i, m.clone())).collect(),
+                since,
+            }
+        }
+
+        /// Writes the outputs that accompany the report: JSON report, time series and conn.log
+        fn write_outputs(&self) {
+            if let Some(json_file) = &self.json_file {
+                let report = json!({
+                    "device": self.devs.join(","),
+                    "filter": self.filter,
+                    "counters": self.counters,
+                    "connections": self.connections_json(),
+                    "summary": Summary::new(&self.connections),
+                });
+                let res = File::create(json_file)
+                    .and_then(|f| serde_json::to_writer_pretty(f, &report).map_err(std::io::Error::from));
+                if let Err(e) = res {
+                    eprintln!("Error writing the JSON report: {}", e);
+                }
+            }
+
+            if let Some(series) = &self.series {
+                let res = File::create(&self.series_file).and_then(|mut f| {
+                    if self.series_file.ends_with(".json") {
+                        series.write_json(&mut f)
+                    } else {
+                        series.write_csv(&mut f)
+                    }
+                });
+                if let Err(e) = res {
+                    eprintln!("Error writing the time series file: {}", e);
+                }
+            }
+
+            if let Some(log) = &self.conn_log {
+                let res = File::create(&self.conn_log_file).and_then(|mut f| {
+                    if self.conn_log_file.ends_with(".json") {
+                        log.write_json(&self.connections, &self.local_addrs, &mut f)
+                    } else {
+                        log.write_tsv(&self.connections, &self.local_addrs, &mut f)
+                    }
+                });
+                if let Err(e) = res {
+                    eprintln!("Error writing the conn.log file: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Bytes A->B and B->A of each connection at a report, and its time
+    type ReportBytes = (Vec<(u32, u32)>, DateTime<Local>);
+
+    /// Copy of the state shown in the text report, rendered by the report writer thread while the capture goes on
+    /// ## Properties
+    /// * `connections`: The connections
+    /// * `details`: Content of the "Details" column of each connection
+    /// * `counters`: Packets, bytes, packets not accounted and statistics of pcap
+    /// * `drop_warning`: Warning about the packets dropped, if any
+    /// * `hosts`, `services`, `tcp_metrics`: Tables of the decoders
+    /// * `since`: Bytes of each connection at the previous report and its time, if only the traffic since then is reported
+    struct ReportData {
+        connections: Vec<Connection>,
+        details: Vec<String>,
+        counters: Counters,
+        drop_warning: Option<String>,
+        hosts: HostInventory,
+        services: ServiceCatalogue,
+        tcp_metrics: BTreeMap<usize, TcpMetrics>,
+        since: Option<ReportBytes>,
+    }
+
+    impl ReportData {
+        /// Writes the text report: the connection table, the summary and the tables of the decoders
+        fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+            if let Some((last, since)) = &self.since {
+                return self.write_delta(last, since, writer);
+            }
+            writeln!(writer, " WIRECATFISH packet capture\n")?;
+            self.write_counters(writer)?;
+            let rows: Vec<(usize, &Connection, String)> = self.connections.iter().enumerate()
+                .map(|(i, con)| (i + 1, con, self.details[i].clone()))
+                .collect();
+            write_table(&rows, writer)?;
+
+            Summary::new(&self.connections).write(writer)?;
+            if !self.hosts.is_empty() {
+                self.hosts.write(writer)?;
+            }
+            if !self.services.is_empty() {
+                self.services.write(writer)?;
+            }
+            if !self.tcp_metrics.is_empty() {
+                let metrics: BTreeMap<usize, &TcpMetrics> = self.tcp_metrics.iter().map(|(i, m)| (*i, m)).collect();
+                tcp_metrics::write(&metrics, writer)?;
+            }
+            Ok(())
+        }
+
+        /// Writes the counters of the report header: packets captured, statistics of pcap and packets not accounted
+        /// to any connection, with the warning about the packets dropped
+        fn write_counters(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+            let c = &self.counters;
+            writeln!(writer, " Packets captured: {} ({} bytes)", c.packets, c.bytes)?;
+            writeln!(writer, " pcap: {} received, {} dropped by the kernel, {} dropped by the interface", c.received, c.dropped, c.if_dropped)?;
+            writeln!(writer, " Not accounted: {} parse errors, {} non-IP frames, {} packets of other transports", c.parse_errors, c.non_ip,
+                c.skipped_transports)?;
+            if let Some(warning) = &self.drop_warning {
+                writeln!(writer, " {}", warning)?;
+            }
+            writeln!(writer)
+        }
+
+        /// Writes the connections with new bytes since the previous periodic report, counting only the new bytes,
+        /// and their summary
+        fn write_delta(&self, last: &[(u32, u32)], since: &DateTime<Local>, writer: &mut dyn Write) -> std::io::Result<()> {
+            writeln!(writer, " WIRECATFISH packet capture, traffic since {}\n", since.format("%Y/%m/%d %H:%M:%S"))?;
+            self.write_counters(writer)?;
+            let delta: Vec<(usize, Connection)> = self.connections.iter().enumerate().filter_map(|(i, con)| {
+                let (a_to_b, b_to_a) = last.get(i).copied().unwrap_or((0, 0));
+                if con.bytes_a_to_b == a_to_b && con.bytes_b_to_a == b_to_a {
+                    return None;
+                }
+                let mut con = con.clone();
+                con.bytes_a_to_b = con.bytes_a_to_b.saturating_sub(a_to_b);
+                con.bytes_b_to_a = con.bytes_b_to_a.saturating_sub(b_to_a);
+                Some((i, con))
+            }).collect();
+            let rows: Vec<(usize, &Connection, String)> = delta.iter()
+                .map(|(i, con)| (i + 1, con, self.details[*i].clone()))
+                .collect();
+            write_table(&rows, writer)?;
+            let connections: Vec<Connection> = delta.into_iter().map(|(_, con)| con).collect();
+            Summary::new(&connections).write(writer)
+        }
+    }
+
+    /// Writes the connection table: number, protocols, addresses, ports, times, application protocol, bytes and details
+    fn write_table(rows: &[(usize, &Connection, String)], writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "| N°    | {0: <11} | {1: <40} | {2: <40} | {3: <18} | {4: <9} | {5: <9} | {6: <19} | {7: <19} | {8: <21} | {9: <13} | {10: <13} | Details",
+            "IP Protocol", "Address A", "Address B", "Transport Protocol", "Port A", "Port B",  "Connection Start", "Connection End ", "Application Protocol", "Bytes A->B", "Bytes B->A")?;
+        for (i, con, details) in rows {
+            writeln!(writer, "| {0: <5} | {1}        | {2: <40} | {3: <40} | {4}                | {5: <9} | {6: <9} | {7: <19} | {8: <19} | {9: <21} | {10: <13} | {11: <13} | {12}",
+                i, con.l3, con.ip_1, con.ip_2, con.l4, con.port_1, con.port_2, con.ts_start.format("%Y/%m/%d %H:%M:%S"), con.ts_end.format("%Y/%m/%d %H:%M:%S"), con.app_prot, con.bytes_a_to_b, con.bytes_b_to_a, details)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the connections saved to a SQLite database (see `Sniffer::set_database`) matching `filter`,
+    /// in the format of the report, one table per capture session. Returns the number of connections found
+    /// ## Parameters
+    /// * `path`: The database file
+    /// * `filter`: Session, address, port and time range of the connections, all optional
+    /// * `writer`: Where the tables are printed, e.g. the standard output
+    /// ```no_run
+    /// use packet_sniffer::packet_sniffer::{query_history, HistoryFilter};
+    /// let filter = HistoryFilter { port: Some(443), since: Some(String::from("2024-05-01")), ..Default::default() };
+    /// query_history("./history.db", &filter, &mut std::io::stdout());
+    /// ```
+    pub fn query_history(path: &str, filter: &HistoryFilter, writer: &mut dyn Write) -> std::result::Result<usize, SnifferError> {
+        let sessions = database::query(path, filter).map_err(|_| SnifferError::DatabaseError)?;
+        let mut count = 0;
+        for (session, connections) in sessions {
+            writeln!(writer, " WIRECATFISH packet capture: session {}, device {}, filter \"{}\", from {} to {}\n",
+                session.id, session.device, session.filter, session.start, session.end.as_deref().unwrap_or("-")).map_err(|_| SnifferError::DatabaseError)?;
+            let rows: Vec<(usize, &Connection, String)> = connections.iter().map(|(i, con)| (*i, con, con.details.join("; "))).collect();
+            write_table(&rows, writer).and_then(|_| writeln!(writer)).map_err(|_| SnifferError::DatabaseError)?;
+            count += connections.len();
+        }
+        Ok(count)
+    }
+
+    /// Sends a request to the control socket of a running capture (see `Sniffer::set_control_socket`)
+    /// and returns its JSON reply
+    /// ## Parameters
+    /// * `path`: Path of the socket file
+    /// * `cmd`: One of "pause", "resume", "stop", "snapshot", "status", "filter", "extract"
+    /// * `arg`: (OPTIONAL) The file of "snapshot", the new filter of "filter", or the connection number of "extract"
+    ///   optionally followed by the file, e.g. "3 c3.pcap"
+    /// ```no_run
+    /// use packet_sniffer::packet_sniffer::send_control;
+    /// let reply = send_control("/tmp/sniffer.sock", "filter", Some("udp port 53"));
+    /// ```
+    #[cfg(unix)]
+    pub fn send_control(path: &str, cmd: &str, arg: Option<&str>) -> std::result::Result<String, SnifferError> {
+        let mut request = json!({ "cmd": cmd });
+        match (cmd, arg) {
+            ("snapshot", Some(file)) => request["file"] = json!(file),
+            ("filter", Some(filter)) => request["filter"] = json!(filter),
+            ("extract", Some(arg)) => {
+                let (connection, file) = match arg.split_once(char::is_whitespace) {
+                    Some((connection, file)) => (connection, Some(file.trim())),
+                    None => (arg, None),
+                };
+                request["connection"] = connection.parse::<usize>().map(|n| json!(n)).unwrap_or_else(|_| json!(connection));
+                if let Some(file) = file {
+                    request["file"] = json!(file);
+                }
+            },
+            _ => (),
+        }
+        control::send(path, &request).map_err(|_| SnifferError::ControlSocketError)
+    }
+
+    /// Prints the capture devices with their number, addresses, flags (up, running, loopback, wireless) and link type.
+    /// Returns the number of devices found
+    /// ## Parameters
+    /// * `format`: Text table, or JSON and TSV for scripts
+    /// * `writer`: Where the devices are printed, e.g. the standard output
+    /// ```no_run
+    /// use packet_sniffer::packet_sniffer::{list_devices, ListFormat};
+    /// list_devices(ListFormat::Json, &mut std::io::stdout());
+    /// ```
+    /// Please notice that the link type is only known for the devices that can be opened, which usually requires privileges
+    pub fn list_devices(format: ListFormat, writer: &mut dyn Write) -> std::result::Result<usize, SnifferError> {
+        let devices = devices::list()?;
+        devices::write(&devices, format, writer).map_err(|_| SnifferError::DevicesListImpossibleToGet)?;
+        Ok(devices.len())
+    }
+
+    /// Formats an address the same way the capture loop does, so that addresses found inside
+    /// payloads can be compared with the ones of the connections
+    fn format_ip(addr: &IpAddr) -> String {
+        match addr {
+            IpAddr::V4(a) => a.to_string(),
+            IpAddr::V6(a) => a.segments().iter().map(|s| format!("{:04x}", s)).collect::<Vec<_>>().join(":"),
+        }
+    }
+
+    /// Tells whether the decoders of `app_prot` work on the reassembled TCP stream, see `inspect_tcp`
+    fn stream_decoded(app_prot: &str) -> bool {
+        matches!(app_prot, "Modbus" | "MQTT" | "SSH" | "SMTP" | "FTP" | "POP3" | "IMAP")
+    }
+
+    /// Returns the time of a packet, whose header holds seconds and microseconds since the epoch
+    fn packet_time(header: &PacketHeader) -> DateTime<Local> {
+        DateTime::from_local(NaiveDateTime::from_timestamp(header.ts.tv_sec as i64, header.ts.tv_usec as u32 * 1000), *(chrono::Local::now().offset()))+*(chrono::Local::now().offset())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use etherparse::PacketBuilder;
+
+        fn sniffer() -> Sniffer {
+            Sniffer::with_device(String::from("results.txt"), String::from("eth0"), 60.0, String::new())
+        }
+
+        /// Ethernet frame of a TCP segment sent by 10.0.0.1:`src_port` to 10.0.0.2:`dst_port`
+        fn tcp_frame(src_port: u16, dst_port: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+            let mut frame = vec![];
+            PacketBuilder::ethernet2([1; 6], [2; 6]).ipv4([10, 0, 0, 1], [10, 0, 0, 2], 64).tcp(src_port, dst_port, seq, 65535)
+                .write(&mut frame, payload).unwrap();
+            frame
+        }
+
+        /// Processes a frame captured by the first device, returning its connection
+        fn capture(sniffer: &mut Sniffer, frame: &[u8]) -> Option<usize> {
+            // the timeval field types differ between platforms
+            let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+            header.caplen = frame.len() as u32;
+            header.len = frame.len() as u32;
+            sniffer.process_packet(0, &Packet::new(&header, frame))
+        }
+
+        fn connection() -> Connection {
+            let start = Local::now();
+            Connection::new(4, String::from("10.0.0.1"), String::from("10.0.0.2"), 0, String::from("80"), String::from("40000"),
+                start, start, 60, String::from("HTTP"))
+        }
+
+        #[test]
+        fn details_deduplicated() {
+            let mut con = connection();
+            con.add_detail(String::from("GET /"));
+            con.add_detail(String::from("Host example.com"));
+            con.add_detail(String::from("GET /"));
+            assert_eq!(con.details, vec![String::from("GET /"), String::from("Host example.com")]);
+        }
+
+        #[test]
+        fn addresses() {
+            assert_eq!(format_ip(&"192.168.1.10".parse().unwrap()), "192.168.1.10");
+            assert_eq!(format_ip(&"fe80::1".parse().unwrap()), "fe80:0000:0000:0000:0000:0000:0000:0001");
+        }
+
+        #[test]
+        fn connection_update() {
+            let mut con = connection();
+            let end = con.ts_start + chrono::Duration::seconds(2);
+            con.update(end, 100, String::from("10.0.0.1"));
+            con.update(end, 40, String::from("10.0.0.2"));
+            assert_eq!((con.bytes_a_to_b, con.bytes_b_to_a), (160, 40));
+            assert_eq!(con.ts_end, end);
+            assert_eq!(format!("{} {}", con.l3, con.l4), "IPv4 TCP");
+        }
+
+        #[test]
+        fn packet_times() {
+            // the timeval field types differ between platforms
+            let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+            header.ts.tv_sec = 1_600_000_000;
+            header.ts.tv_usec = 250_000;
+            let ts = packet_time(&header);
+            assert_eq!(ts.timestamp_subsec_micros(), 250_000);
+            header.ts.tv_usec = 750_000;
+            assert_eq!(packet_time(&header) - ts, chrono::Duration::milliseconds(500));
+        }
+
+        #[test]
+        fn capture_time() {
+            let state = ExecState { time_interval: 60.0, start_time: Instant::now(), pause_time: Instant::now(), pause: false };
+            let waiter = SyncStruct { state: Mutex::new(state), cv: Condvar::new() };
+            assert!(waiter.set_pause(true));
+            assert!(!waiter.set_pause(true));
+            // while paused the time left does not run
+            assert_eq!(waiter.remaining(), 60.0);
+            assert_eq!(waiter.change_time(&TimeChange::Add(30.0)), 90.0);
+            assert_eq!(waiter.change_time(&TimeChange::Add(-100.0)), 0.0);
+            assert_eq!(waiter.change_time(&TimeChange::Set(15.0)), 15.0);
+            assert_eq!(waiter.change_time(&TimeChange::Set(f64::NAN)), 15.0);
+            waiter.stop();
+            let state = waiter.state.lock().unwrap();
+            assert_eq!((state.time_interval, state.pause), (0.0, false));
+        }
+
+        #[test]
+        fn reassembled_connections() {
+            let dir = std::env::temp_dir().join(format!("packet_sniffer-lib-{}-reassembled", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let mut sniffer = sniffer();
+            // past a hole, more data than the reassembler keeps: the hole is given up
+            let ahead = vec![b'x'; 60_000];
+            let send = |sniffer: &mut Sniffer, src_port: u16, dst_port: u16| {
+                let i = capture(sniffer, &tcp_frame(src_port, dst_port, 1000, b"USER\r\n")).unwrap();
+                for n in 0..5 {
+                    capture(sniffer, &tcp_frame(src_port, dst_port, 1007 + n * 60_000, &ahead));
+                }
+                sniffer.connection_details(i).contains("Missing TCP data")
+            };
+            // HTTP is not decoded from the stream, FTP is
+            assert!(!send(&mut sniffer, 40000, 80));
+            assert!(send(&mut sniffer, 40001, 21));
+            // the connections exported are reassembled whatever their protocol
+            sniffer.set_stream_export(dir.to_string_lossy().into_owned(), String::new());
+            assert!(send(&mut sniffer, 40002, 80));
+            assert!(std::fs::read(dir.join("conn3_10.0.0.1_40002-10.0.0.2_80.bin")).unwrap().starts_with(b"USER\r\n"));
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        /// Total bytes of each section of an appended report file
+        fn report_totals(path: &std::path::Path) -> Vec<u64> {
+            std::fs::read_to_string(path).unwrap().lines()
+                .filter_map(|l| l.strip_prefix(" Summary: ")?.split(", ").nth(1)?.strip_suffix(" bytes")?.parse().ok())
+                .collect()
+        }
+
+        #[test]
+        fn periodic_reports() {
+            let dir = std::env::temp_dir().join(format!("packet_sniffer-lib-{}-periodic", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let first = tcp_frame(40000, 80, 1, &[0; 100]);
+            let second = tcp_frame(40000, 80, 101, &[0; 50]);
+            let last = tcp_frame(40001, 80, 1, &[0; 10]);
+            let total = (first.len() + second.len() + last.len()) as u64;
+            for (delta, expected) in [
+                // each periodic report holds the bytes since the previous one, the final report the totals
+                (true, vec![first.len() as u64, second.len() as u64, total]),
+                (false, vec![first.len() as u64, (first.len() + second.len()) as u64, total]),
+            ] {
+                let path = dir.join(format!("delta-{}.txt", delta));
+                let mut sniffer = Sniffer::with_device(path.to_string_lossy().into_owned(), String::from("eth0"), 60.0, String::new());
+                sniffer.set_report_policy(ReportPolicy { append: true, ..Default::default() }).unwrap();
+                sniffer.set_report_interval(1.0, delta);
+                let writer = sniffer.start_reports();
+                capture(&mut sniffer, &first);
+                sniffer.periodic_report();
+                capture(&mut sniffer, &second);
+                sniffer.periodic_report();
+                // captured after the last periodic report, written by the final one at the stop
+                capture(&mut sniffer, &last);
+                sniffer.finish_reports(writer);
+                assert_eq!(report_totals(&path), expected, "delta {}", delta);
+            }
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn devices_merged() {
+            let mut sniffer = sniffer();
+            sniffer.devs.push(String::from("eth1"));
+            // connection 40000 is seen on both devices, connection 40001 only on the second one
+            let first: Vec<Vec<u8>> = (0..50).map(|n| tcp_frame(40000, 80, 1 + n * 10, &[0; 10])).collect();
+            let second: Vec<Vec<u8>> = (0..50).map(|n: u32| tcp_frame(40000 + (n % 2) as u16, 80, 1 + n / 2 * 10, &[0; 10])).collect();
+            let capture = capture::tests::feed(vec![first, second]);
+            let mut pcap_out = None;
+            for _ in 0..10 {
+                if let Some(captured) = capture.next(Duration::from_millis(100)) {
+                    sniffer.handle_packet(captured, &mut pcap_out);
+                }
+            }
+            // the rest is still queued at the end of the capture
+            sniffer.drain_capture(capture, &mut pcap_out);
+            assert_eq!(sniffer.counters.packets, 100);
+            assert_eq!(sniffer.counters.received, 20);
+            let interfaces = |port: &str| {
+                let con = sniffer.connections.iter().find(|c| c.port_2 == port).unwrap();
+                let mut interfaces = con.interfaces.clone();
+                interfaces.sort();
+                interfaces
+            };
+            assert_eq!(interfaces("40000"), vec!["eth0", "eth1"]);
+            assert_eq!(interfaces("40001"), vec!["eth1"]);
+        }
+
+        #[test]
+        fn table() {
+            let con = connection();
+            let mut out = vec![];
+            write_table(&[(1, &con, String::from("GET /"))], &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            let lines: Vec<&str> = out.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with("| N°    | IP Protocol |"));
+            assert!(lines[1].starts_with("| 1     | IPv4        | 10.0.0.1 "));
+            assert!(lines[1].ends_with("| HTTP                  | 60            | 0             | GET /"));
+        }
     }
 }
@@ -1,30 +1,326 @@
-mod lib;
-use crate::lib::packet_sniffer::Sniffer;
-use clap::Parser;
+use packet_sniffer::packet_sniffer::{list_devices, query_history, CaptureOptions, Compression, Direction, HistoryFilter, ListFormat,
+    ReportPolicy, Sniffer, TimestampType};
+#[cfg(unix)]
+use packet_sniffer::packet_sniffer::send_control;
+use clap::{CommandFactory, ErrorKind, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 struct Args {
-    #[clap(short, long, value_parser, default_value_t = 0)]
-    adapter: usize,
+    /// Device to capture from: its name, "default", or its number in --list-devices; repeat it to capture from several devices at once
+    #[clap(short, long, value_parser, default_value = "0")]
+    adapter: Vec<String>,
+    /// Print the capture devices and exit
+    #[clap(long, value_parser)]
+    list_devices: bool,
+    /// Format of --list-devices: text, json or tsv
+    #[clap(long, value_parser, default_value = "text")]
+    list_format: ListFormat,
     #[clap(short, long, value_parser, default_value_t = 5.0)]
     timer: f64,
     #[clap(short, long, value_parser, default_value = "./results.txt")]
     file: String,
     #[clap(long, value_parser, default_value = "")]
     filter: String,
+    /// Do not put the devices in promiscuous mode
+    #[clap(long, value_parser)]
+    no_promisc: bool,
+    /// Bytes captured of each packet
+    #[clap(long, value_parser, default_value_t = 65535)]
+    snaplen: i32,
+    /// Kernel capture buffer size in MB (0 = default of pcap)
+    #[clap(long, value_parser, default_value_t = 0)]
+    buffer_size: i32,
+    /// Read timeout of the capture (ms)
+    #[clap(long, value_parser, default_value_t = 500)]
+    read_timeout: i32,
+    /// Deliver the packets as soon as they arrive instead of in batches
+    #[clap(long, value_parser)]
+    immediate: bool,
+    /// Source of the timestamps: default, host, host_lowprec, host_hiprec, adapter or adapter_unsynced
+    #[clap(long, value_parser, default_value = "default")]
+    tstamp_type: TimestampType,
+    /// Nanosecond timestamps (kept in the pcapng output)
+    #[clap(long, value_parser)]
+    nanos: bool,
+    /// Warn when more than this percentage of the packets is dropped (0 = never)
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    drop_warning: f64,
+    /// Packets captured: in, out or both
+    #[clap(long, value_parser, default_value = "both")]
+    direction: Direction,
+    /// Append a section to the report file on every pause instead of overwriting it
+    #[clap(long, value_parser)]
+    append: bool,
+    /// Replace the strftime fields of the report file name (e.g. results-%Y%m%d-%H%M%S.txt) by the time each file is started
+    #[clap(long, value_parser)]
+    template: bool,
+    /// Rotate the report file once it reaches this many MB (0 = never)
+    #[clap(long, value_parser, default_value_t = 0)]
+    rotate_size: u64,
+    /// Rotate the report file once it is this many seconds old (0 = never)
+    #[clap(long, value_parser, default_value_t = 0)]
+    rotate_time: u64,
+    /// Rotated report files kept (0 = all)
+    #[clap(long, value_parser, default_value_t = 0)]
+    keep: usize,
+    /// Write a report every this many seconds during the capture (0 = never)
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    report_interval: f64,
+    /// The periodic reports only hold the traffic since the previous one
+    #[clap(long, value_parser)]
+    report_delta: bool,
+    /// Compression of the rotated report files: none, gzip or zstd
+    #[clap(long, value_parser, default_value = "none")]
+    compress: Compression,
+    /// Show the live full-screen interface instead of the command prompt
+    #[clap(long, value_parser)]
+    tui: bool,
+    /// Write the report also as JSON to this file
+    #[clap(long, value_parser)]
+    json: Option<String>,
+    /// Save the captured packets to this pcapng file
+    #[clap(long, value_parser)]
+    pcap: Option<String>,
+    /// Save only the packets matching this filter to the pcapng file
+    #[clap(long, value_parser, default_value = "")]
+    pcap_filter: String,
+    /// Start a new pcapng file after this many MB (0 = never)
+    #[clap(long, value_parser, default_value_t = 0)]
+    pcap_max_size: u64,
+    /// Start a new pcapng file after this many seconds (0 = never)
+    #[clap(long, value_parser, default_value_t = 0)]
+    pcap_rotate: u64,
+    /// Write the reassembled TCP payload of each connection direction to this folder
+    #[clap(long, value_parser)]
+    streams: Option<String>,
+    /// Export only the TCP connections matching this filter
+    #[clap(long, value_parser, default_value = "")]
+    streams_filter: String,
+    /// Keep the first packets of each connection in memory, up to this many, so that the "extract" command can write
+    /// them to a pcap file during the capture (0 = disabled)
+    #[clap(long, value_parser, default_value_t = 0)]
+    flow_packets: usize,
+    /// Bytes kept for each connection with --flow-packets
+    #[clap(long, value_parser, default_value_t = 10_000_000)]
+    flow_bytes: usize,
+    /// Export time series of packets and bytes to this file (JSON if it ends with .json, CSV otherwise)
+    #[clap(long, value_parser)]
+    series: Option<String>,
+    /// Length of each time series bucket (secs)
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    series_bucket: f64,
+    /// Number of connections in the time series, the ones with the most bytes
+    #[clap(long, value_parser, default_value_t = 10)]
+    series_top: usize,
+    /// Accept pause, resume, stop, snapshot, status and filter requests on this Unix domain socket
+    #[cfg(unix)]
+    #[clap(long, value_parser)]
+    control: Option<String>,
+    /// Serve the REST API and the dashboard on http://127.0.0.1:<port>/
+    #[clap(long, value_parser)]
+    http: Option<u16>,
+    /// Save the sessions and snapshots of the connections to this SQLite database
+    #[clap(long, value_parser)]
+    database: Option<String>,
+    /// Time between two snapshots saved to the database (secs, 0 = only on pause and at the end)
+    #[clap(long, value_parser, default_value_t = 0.0)]
+    snapshot_interval: f64,
+    /// Write the connections also as a Zeek conn.log to this file (JSON if it ends with .json, TSV otherwise)
+    #[clap(long, value_parser)]
+    zeek: Option<String>,
+    /// Export the flows to this NetFlow/IPFIX collector (host:port)
+    #[clap(long, value_parser)]
+    netflow: Option<String>,
+    /// Flow export version: 5, 9, or 10 for IPFIX
+    #[clap(long, value_parser, default_value_t = 10)]
+    netflow_version: u16,
+    /// Observation domain ID of the flow export
+    #[clap(long, value_parser, default_value_t = 0)]
+    netflow_domain: u32,
+    /// Export the flows lasting longer than this (secs)
+    #[clap(long, value_parser, default_value_t = 60.0)]
+    netflow_active_timeout: f64,
+    /// Export the flows idle for longer than this (secs)
+    #[clap(long, value_parser, default_value_t = 15.0)]
+    netflow_idle_timeout: f64,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Capture, then write the first packets of a connection to a pcap file (see also --flow-packets and the "extract" command)
+    Dump {
+        /// Number of the connection, as shown in the report
+        #[clap(value_parser)]
+        connection: usize,
+        #[clap(short, long, value_parser, default_value = "./connection.pcap")]
+        output: String,
+        /// Packets kept for each connection
+        #[clap(long, value_parser, default_value_t = 1000)]
+        max_packets: usize,
+        /// Bytes kept for each connection
+        #[clap(long, value_parser, default_value_t = 10_000_000)]
+        max_bytes: usize,
+    },
+    /// Send a request to the control socket of a running capture and print the reply
+    #[cfg(unix)]
+    Control {
+        /// Path of the control socket
+        #[clap(value_parser)]
+        socket: String,
+        /// One of pause, resume, stop, snapshot, status, filter, extract
+        #[clap(value_parser)]
+        cmd: String,
+        /// The file of snapshot, the new filter of filter, or the connection number of extract optionally followed by the file
+        #[clap(value_parser)]
+        arg: Option<String>,
+    },
+    /// Print the connections saved to a SQLite database
+    Query {
+        /// Path of the database
+        #[clap(value_parser)]
+        database: String,
+        /// Only the connections of this session
+        #[clap(long, value_parser)]
+        session: Option<i64>,
+        /// Only the connections of this address
+        #[clap(long, value_parser)]
+        address: Option<String>,
+        /// Only the connections of this port
+        #[clap(long, value_parser)]
+        port: Option<u16>,
+        /// Only the connections active since this time (YYYY-MM-DD[ HH:MM:SS])
+        #[clap(long, value_parser)]
+        since: Option<String>,
+        /// Only the connections active until this time (YYYY-MM-DD[ HH:MM:SS])
+        #[clap(long, value_parser)]
+        until: Option<String>,
+    },
 }
 
 fn main() {
     let args: Args = Args::parse();  
+    let pcap_max_size = megabytes(args.pcap_max_size.checked_mul(1_000_000), "pcap-max-size");
+    let rotate_size = megabytes(args.rotate_size.checked_mul(1_000_000), "rotate-size");
+    let buffer_size = megabytes(args.buffer_size.checked_mul(1_000_000), "buffer-size");
+
+    if args.list_devices {
+        if let Err(e) = list_devices(args.list_format, &mut std::io::stdout()) {
+            eprintln!("{}", e);
+        }
+        return;
+    }
+    #[cfg(unix)]
+    if let Some(Command::Control { socket, cmd, arg }) = &args.command {
+        match send_control(socket, cmd, arg.as_deref()) {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+    if let Some(Command::Query { database, session, address, port, since, until }) = &args.command {
+        let filter = HistoryFilter { session: *session, address: address.clone(), port: *port, since: since.clone(), until: until.clone() };
+        match query_history(database, &filter, &mut std::io::stdout()) {
+            Ok(n) => println!("{} connections found", n),
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
     
     //doppio match perché sia Sniffer::new che Sniffer::start_capture possono ritornare un errore 
-    let s = Sniffer::new(args.file, args.adapter, args.timer, args.filter);
+    let s = Sniffer::new(args.file, &args.adapter[0], args.timer, args.filter);
     match s {
-        Ok(mut sniffer) => match sniffer.start_capture() {
-            Err(e) => eprintln!("{}", e),
-            _ => println!("Work done!"),
+        Ok(mut sniffer) => {
+            for adapter in &args.adapter[1..] {
+                if let Err(e) = sniffer.add_device(adapter) {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+            sniffer.set_capture_options(CaptureOptions {
+                promisc: !args.no_promisc,
+                snaplen: args.snaplen,
+                buffer_size,
+                timeout: args.read_timeout,
+                immediate: args.immediate,
+                tstamp_type: args.tstamp_type,
+                nanos: args.nanos,
+                direction: args.direction,
+            });
+            sniffer.set_drop_warning(args.drop_warning);
+            if let Some(pcap) = args.pcap {
+                sniffer.set_pcap_output(pcap, args.pcap_filter, pcap_max_size, args.pcap_rotate);
+            }
+            let policy = ReportPolicy {
+                append: args.append,
+                templated: args.template,
+                rotate_size,
+                rotate_time: args.rotate_time,
+                keep: args.keep,
+                compression: args.compress,
+            };
+            if let Err(e) = sniffer.set_report_policy(policy) {
+                eprintln!("{}", e);
+                return;
+            }
+            sniffer.set_report_interval(args.report_interval, args.report_delta);
+            sniffer.set_tui(args.tui);
+            #[cfg(unix)]
+            if let Some(control) = args.control {
+                sniffer.set_control_socket(control);
+            }
+            if let Some(port) = args.http {
+                sniffer.set_http_server(port);
+            }
+            if let Some(json) = args.json {
+                sniffer.set_json_report(json);
+            }
+            if let Some(database) = args.database {
+                if let Err(e) = sniffer.set_database(&database, args.snapshot_interval) {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+            if let Some(zeek) = args.zeek {
+                sniffer.set_zeek_log(zeek);
+            }
+            if let Some(series) = args.series {
+                sniffer.set_time_series(series, args.series_bucket, args.series_top);
+            }
+            if let Some(collector) = args.netflow {
+                let res = sniffer.set_flow_export(&collector, args.netflow_version, args.netflow_domain,
+                    args.netflow_active_timeout, args.netflow_idle_timeout);
+                if let Err(e) = res {
+                    eprintln!("{}", e);
+                    return;
+                }
+            }
+            if let Some(dir) = args.streams {
+                sniffer.set_stream_export(dir, args.streams_filter);
+            }
+            if let Some(Command::Dump { max_packets, max_bytes, .. }) = args.command {
+                sniffer.set_flow_buffer(max_packets, max_bytes);
+            } else if args.flow_packets > 0 {
+                sniffer.set_flow_buffer(args.flow_packets, args.flow_bytes);
+            }
+            match sniffer.start_capture() {
+                Err(e) => eprintln!("{}", e),
+                _ => println!("Work done!"),
+            }
+            if let Some(Command::Dump { connection, output, .. }) = args.command {
+                match sniffer.dump_connection(connection, &output) {
+                    Ok(n) => println!("{} packets of connection #{} written to {}", n, connection, output),
+                    Err(e) => eprintln!("{}", e),
+                }
+            }
         },
         Err(e) => eprintln!("{}", e)
     }
     
 }
+
+/// Returns a size converted from MB to bytes, exiting with an error for `--<arg>` if it overflowed
+fn megabytes<T>(bytes: Option<T>, arg: &str) -> T {
+    bytes.unwrap_or_else(|| Args::command().error(ErrorKind::ValueValidation, format!("--{} is too large", arg)).exit())
+}
@@ -0,0 +1,336 @@
+//! Banner and command extraction for the cleartext protocols: SSH, SMTP, FTP, POP3 and IMAP.
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+/// Longest banner kept in the report
+const MAX_BANNER_LEN: usize = 80;
+
+/// Server software recognised inside SMTP/FTP/POP3/IMAP greetings
+const KNOWN_SOFTWARE: [&str; 16] = [
+    "Postfix", "Exim", "Sendmail", "Microsoft ESMTP MAIL Service", "Microsoft FTP Service", "vsFTPd",
+    "ProFTPD", "Pure-FTPd", "FileZilla Server", "Serv-U", "Dovecot", "Courier", "Cyrus", "Zimbra",
+    "qpopper", "OpenSMTPD",
+];
+
+/// This enum provides what can be learnt from a line of a cleartext protocol
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Finding {
+    /// A fact to show in the report
+    Detail(String),
+    /// An FTP data connection announced on the control connection
+    /// * `ip`: Address the data connection will be opened towards, if different from the control connection's
+    /// * `port`: Port of the data connection
+    /// * `passive`: `true` for PASV/EPSV (the server listens), `false` for PORT/EPRT
+    FtpData { ip: Option<String>, port: u16, passive: bool },
+}
+
+/// Per-connection parsing state
+#[derive(Default)]
+struct BannerState {
+    server_banner: bool,
+    client_banner: bool,
+    starttls_pending: bool,
+    starttls_tag: String,
+    encrypted: bool,
+}
+
+/// This struct tracks the cleartext protocols of every connection, indexed by connection number,
+/// along with the FTP data connections expected from the PORT/PASV negotiation
+#[derive(Default)]
+pub(crate) struct BannerTracker {
+    flows: HashMap<usize, BannerState>,
+    data_channels: Vec<(String, u16, usize)>,
+}
+
+impl BannerTracker {
+    /// Inspects the complete lines received on connection `id`
+    /// ## Parameters
+    /// * `id`: Connection number
+    /// * `app_prot`: Application protocol of the connection
+    /// * `from_server`: `true` if the lines were sent by the server
+    /// * `payload`: Reassembled TCP payload
+    pub fn inspect(&mut self, id: usize, app_prot: &str, from_server: bool, payload: &[u8]) -> Vec<Finding> {
+        let mut findings = vec![];
+        let state = self.flows.entry(id).or_default();
+        if state.encrypted || payload.is_empty() {
+            return findings;
+        }
+        for line in payload.split(|b| *b == b'\n') {
+            let line = match printable(line) {
+                Some(l) => l,
+                None => continue,
+            };
+            if from_server {
+                server_line(state, app_prot, &line, &mut findings);
+            } else {
+                client_line(state, app_prot, &line, &mut findings);
+            }
+            if state.encrypted {
+                break;
+            }
+        }
+        findings
+    }
+
+    /// Remembers an FTP data connection announced on control connection `control`
+    pub fn expect_data_channel(&mut self, ip: String, port: u16, control: usize) {
+        if !self.data_channels.iter().any(|(i, p, _)| *i == ip && *p == port) {
+            self.data_channels.push((ip, port, control));
+        }
+    }
+
+    /// Returns the control connection that announced the endpoint `ip`:`port`, if any
+    pub fn data_channel_control(&self, ip: &str, port: &str) -> Option<usize> {
+        let port: u16 = port.parse().ok()?;
+        self.data_channels
+            .iter()
+            .find(|(i, p, _)| i == ip && *p == port)
+            .map(|(_, _, control)| *control)
+    }
+}
+
+fn server_line(state: &mut BannerState, app_prot: &str, line: &str, findings: &mut Vec<Finding>) {
+    // the reply to an IMAP command repeats its tag, the untagged responses before it are not the reply
+    let reply = app_prot != "IMAP" || line.split_whitespace().next() == Some(state.starttls_tag.as_str());
+    if state.starttls_pending && reply {
+        state.starttls_pending = false;
+        if positive_reply(app_prot, line) {
+            state.encrypted = true;
+            findings.push(Finding::Detail(String::from("STARTTLS upgrade")));
+            return;
+        }
+    }
+    if app_prot == "SSH" {
+        if !state.server_banner && line.starts_with("SSH-") {
+            state.server_banner = true;
+            findings.push(Finding::Detail(format!("Server {}", ssh_software(line))));
+        }
+        return;
+    }
+    if !state.server_banner && greeting(app_prot, line) {
+        state.server_banner = true;
+        findings.push(Finding::Detail(format!("Banner \"{}\"", truncate(line))));
+        if let Some(software) = known_software(line) {
+            findings.push(Finding::Detail(format!("Server {}", software)));
+        }
+        return;
+    }
+    if app_prot == "FTP" {
+        // 227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)
+        if let Some(rest) = line.strip_prefix("227") {
+            if let (Some(start), Some(end)) = (rest.find('('), rest.rfind(')')) {
+                if let Some((ip, port)) = host_port(&rest[start + 1..end]) {
+                    findings.push(Finding::FtpData { ip: Some(ip), port, passive: true });
+                }
+            }
+        }
+        // 229 Entering Extended Passive Mode (|||port|)
+        if let Some(rest) = line.strip_prefix("229") {
+            if let (Some(start), Some(end)) = (rest.find('('), rest.rfind(')')) {
+                let fields: Vec<&str> = rest[start + 1..end].split('|').collect();
+                if let Some(port) = fields.get(3).and_then(|p| p.parse().ok()) {
+                    findings.push(Finding::FtpData { ip: None, port, passive: true });
+                }
+            }
+        }
+    }
+}
+
+fn client_line(state: &mut BannerState, app_prot: &str, line: &str, findings: &mut Vec<Finding>) {
+    if app_prot == "SSH" {
+        if !state.client_banner && line.starts_with("SSH-") {
+            state.client_banner = true;
+            findings.push(Finding::Detail(format!("Client {}", ssh_software(line))));
+        }
+        return;
+    }
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or("");
+    let mut command = first.to_ascii_uppercase();
+    // IMAP commands are preceded by a tag
+    if app_prot == "IMAP" {
+        command = words.next().unwrap_or("").to_ascii_uppercase();
+    }
+    let argument = words.next().unwrap_or("");
+    match (app_prot, command.as_str()) {
+        ("SMTP", "STARTTLS") | ("POP3", "STLS") => state.starttls_pending = true,
+        ("IMAP", "STARTTLS") => {
+            state.starttls_pending = true;
+            state.starttls_tag = first.to_string();
+        }
+        ("FTP", "AUTH") if argument.eq_ignore_ascii_case("TLS") || argument.eq_ignore_ascii_case("SSL") => {
+            state.starttls_pending = true
+        }
+        ("SMTP", "EHLO") | ("SMTP", "HELO") => findings.push(Finding::Detail(format!("{} {}", command, argument))),
+        // PORT h1,h2,h3,h4,p1,p2
+        ("FTP", "PORT") => {
+            if let Some((ip, port)) = host_port(argument) {
+                findings.push(Finding::FtpData { ip: Some(ip), port, passive: false });
+            }
+        }
+        // EPRT |proto|address|port|
+        ("FTP", "EPRT") => {
+            let fields: Vec<&str> = argument.split('|').collect();
+            if let (Some(ip), Some(port)) = (fields.get(2), fields.get(3).and_then(|p| p.parse().ok())) {
+                findings.push(Finding::FtpData { ip: Some(ip.to_string()), port, passive: false });
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Tells whether a server line is the greeting of `app_prot`
+fn greeting(app_prot: &str, line: &str) -> bool {
+    match app_prot {
+        "SMTP" | "FTP" => line.starts_with("220"),
+        "POP3" => line.starts_with("+OK"),
+        "IMAP" => line.starts_with("* OK") || line.starts_with("* PREAUTH"),
+        _ => false,
+    }
+}
+
+/// Tells whether the server accepted the STARTTLS command
+fn positive_reply(app_prot: &str, line: &str) -> bool {
+    match app_prot {
+        "SMTP" => line.starts_with("220"),
+        "FTP" => line.starts_with("234"),
+        "POP3" => line.starts_with("+OK"),
+        "IMAP" => line.split_whitespace().nth(1).is_some_and(|s| s.eq_ignore_ascii_case("OK")),
+        _ => false,
+    }
+}
+
+/// Software and version from an SSH identification string, e.g. `SSH-2.0-OpenSSH_8.9p1 Ubuntu-3`
+fn ssh_software(line: &str) -> String {
+    let mut parts = line.splitn(3, '-');
+    let proto = parts.nth(1).unwrap_or("");
+    let software = parts.next().unwrap_or("");
+    format!("SSH-{} {}", proto, truncate(software))
+}
+
+fn known_software(line: &str) -> Option<String> {
+    let lower = line.to_ascii_lowercase();
+    for name in KNOWN_SOFTWARE {
+        if let Some(pos) = lower.find(&name.to_ascii_lowercase()) {
+            let after = line[pos + name.len()..].trim_start();
+            let version: String = after
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '-' || *c == '_')
+                .collect();
+            if version.starts_with(|c: char| c.is_ascii_digit()) {
+                return Some(format!("{} {}", name, version));
+            }
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// Parses the `h1,h2,h3,h4,p1,p2` notation of PORT and PASV
+fn host_port(text: &str) -> Option<(String, u16)> {
+    let numbers: Vec<u8> = text.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?;
+    if numbers.len() != 6 {
+        return None;
+    }
+    let ip = Ipv4Addr::new(numbers[0], numbers[1], numbers[2], numbers[3]);
+    Some((ip.to_string(), (numbers[4] as u16) << 8 | numbers[5] as u16))
+}
+
+/// Returns the line without its terminator if it only contains printable ASCII
+fn printable(line: &[u8]) -> Option<String> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.is_empty() || !line.iter().all(|b| (0x20..0x7f).contains(b) || *b == b'\t') {
+        return None;
+    }
+    Some(String::from_utf8_lossy(line).into_owned())
+}
+
+fn truncate(line: &str) -> String {
+    line.chars().take(MAX_BANNER_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detail(s: &str) -> Finding {
+        Finding::Detail(String::from(s))
+    }
+
+    #[test]
+    fn ssh_identification() {
+        let mut t = BannerTracker::default();
+        assert_eq!(t.inspect(0, "SSH", true, b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3\r\n"), vec![detail("Server SSH-2.0 OpenSSH_8.9p1 Ubuntu-3")]);
+        assert_eq!(t.inspect(0, "SSH", false, b"SSH-2.0-PuTTY_Release_0.78\r\n"), vec![detail("Client SSH-2.0 PuTTY_Release_0.78")]);
+        //only the first identification string
+        assert!(t.inspect(0, "SSH", true, b"SSH-2.0-other\r\n").is_empty());
+    }
+
+    #[test]
+    fn smtp_greeting_and_starttls() {
+        let mut t = BannerTracker::default();
+        assert_eq!(t.inspect(1, "SMTP", true, b"220 mail.example.com ESMTP Postfix 3.6.4\r\n"), vec![
+            detail("Banner \"220 mail.example.com ESMTP Postfix 3.6.4\""),
+            detail("Server Postfix 3.6.4"),
+        ]);
+        assert_eq!(t.inspect(1, "SMTP", false, b"EHLO client.example.com\r\nSTARTTLS\r\n"), vec![detail("EHLO client.example.com")]);
+        assert_eq!(t.inspect(1, "SMTP", true, b"220 2.0.0 Ready to start TLS\r\n"), vec![detail("STARTTLS upgrade")]);
+        //the rest is encrypted
+        assert!(t.inspect(1, "SMTP", false, b"EHLO again\r\n").is_empty());
+    }
+
+    #[test]
+    fn refused_starttls() {
+        let mut t = BannerTracker::default();
+        t.inspect(2, "IMAP", true, b"* OK [CAPABILITY IMAP4rev1] Dovecot ready.\r\n");
+        t.inspect(2, "IMAP", false, b"a1 STARTTLS\r\n");
+        assert!(t.inspect(2, "IMAP", true, b"a1 BAD not now\r\n").is_empty());
+        assert_eq!(t.inspect(2, "IMAP", false, b"a2 STARTTLS\r\n"), vec![]);
+        assert_eq!(t.inspect(2, "IMAP", true, b"a2 OK Begin TLS\r\n"), vec![detail("STARTTLS upgrade")]);
+    }
+
+    #[test]
+    fn imap_tagged_reply() {
+        let mut t = BannerTracker::default();
+        t.inspect(5, "IMAP", true, b"* OK IMAP4rev1 ready\r\n");
+        t.inspect(5, "IMAP", false, b"a1 STARTTLS\r\n");
+        //an untagged OK, or the reply to another command, is not the reply to STARTTLS
+        assert!(t.inspect(5, "IMAP", true, b"* OK still here\r\na0 OK done\r\n").is_empty());
+        assert_eq!(t.inspect(5, "IMAP", true, b"a1 OK Begin TLS\r\n"), vec![detail("STARTTLS upgrade")]);
+    }
+
+    #[test]
+    fn ftp_data_channels() {
+        let mut t = BannerTracker::default();
+        assert_eq!(t.inspect(3, "FTP", true, b"220 (vsFTPd 3.0.5)\r\n")[1], detail("Server vsFTPd 3.0.5"));
+        assert_eq!(t.inspect(3, "FTP", true, b"227 Entering Passive Mode (192,168,1,2,195,80).\r\n"),
+            vec![Finding::FtpData { ip: Some(String::from("192.168.1.2")), port: 50000, passive: true }]);
+        assert_eq!(t.inspect(3, "FTP", true, b"229 Entering Extended Passive Mode (|||50001|)\r\n"),
+            vec![Finding::FtpData { ip: None, port: 50001, passive: true }]);
+        assert_eq!(t.inspect(3, "FTP", false, b"PORT 10,0,0,5,4,1\r\n"),
+            vec![Finding::FtpData { ip: Some(String::from("10.0.0.5")), port: 1025, passive: false }]);
+        assert_eq!(t.inspect(3, "FTP", false, b"EPRT |2|2001:db8::5|1026|\r\n"),
+            vec![Finding::FtpData { ip: Some(String::from("2001:db8::5")), port: 1026, passive: false }]);
+
+        t.expect_data_channel(String::from("192.168.1.2"), 50000, 3);
+        t.expect_data_channel(String::from("192.168.1.2"), 50000, 4);
+        assert_eq!(t.data_channel_control("192.168.1.2", "50000"), Some(3));
+        assert_eq!(t.data_channel_control("192.168.1.2", "50001"), None);
+    }
+
+    #[test]
+    fn binary_lines_skipped() {
+        let mut t = BannerTracker::default();
+        assert!(t.inspect(4, "POP3", true, b"\x16\x03\x01\x02\x00\r\n").is_empty());
+        assert_eq!(t.inspect(4, "POP3", true, b"+OK POP3 ready\r\n"), vec![detail("Banner \"+OK POP3 ready\"")]);
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(host_port("1,2,3,4,0,21"), Some((String::from("1.2.3.4"), 21)));
+        assert_eq!(host_port("1,2,3,4,0"), None);
+        assert_eq!(host_port("1,2,3,256,0,21"), None);
+        assert_eq!(known_software("220 ProFTPD Server ready"), Some(String::from("ProFTPD")));
+        assert_eq!(truncate(&"x".repeat(200)).len(), MAX_BANNER_LEN);
+    }
+}
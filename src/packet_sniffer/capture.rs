@@ -0,0 +1,402 @@
+//! Capture from several devices at once: one thread per device, all feeding the packets to the capture loop.
+use super::SnifferError;
+use pcap::{Active, Capture, Linktype, PacketHeader, Precision, Stat};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Packets waiting for the capture loop: when the queue is full the threads wait, leaving the packets to the kernel buffer
+const QUEUE_LEN: usize = 10_000;
+
+/// This enum provides the directions of the packets captured
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Direction {
+    #[default]
+    Both,
+    In,
+    Out,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" | "inout" => Ok(Direction::Both),
+            "in" => Ok(Direction::In),
+            "out" => Ok(Direction::Out),
+            _ => Err(format!("Unknown direction \"{}\": use in, out or both", s)),
+        }
+    }
+}
+
+/// This enum provides the sources of the packet timestamps, named as in pcap-tstamp(7)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimestampType {
+    /// The default of the device
+    #[default]
+    Default,
+    Host,
+    HostLowPrec,
+    HostHighPrec,
+    Adapter,
+    AdapterUnsynced,
+}
+
+impl FromStr for TimestampType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(TimestampType::Default),
+            "host" => Ok(TimestampType::Host),
+            "host_lowprec" => Ok(TimestampType::HostLowPrec),
+            "host_hiprec" => Ok(TimestampType::HostHighPrec),
+            "adapter" => Ok(TimestampType::Adapter),
+            "adapter_unsynced" => Ok(TimestampType::AdapterUnsynced),
+            _ => Err(format!("Unknown timestamp type \"{}\": use default, host, host_lowprec, host_hiprec, adapter or adapter_unsynced", s)),
+        }
+    }
+}
+
+/// This struct holds the settings of the capture handles
+/// ## Properties
+/// * `promisc`: Promiscuous mode, capturing also the packets addressed to other hosts
+/// * `snaplen`: Bytes captured of each packet
+/// * `buffer_size`: Kernel buffer size (bytes), 0 for the default of pcap
+/// * `timeout`: Read timeout (ms), at least 1: between two reads the capture threads check whether the capture is over
+/// * `immediate`: Immediate mode, delivering the packets as soon as they arrive instead of in batches
+/// * `tstamp_type`: Source of the timestamps
+/// * `nanos`: Nanosecond timestamps instead of microsecond ones
+/// * `direction`: Packets captured: received, sent or both
+#[derive(Clone, Debug)]
+pub struct CaptureOptions {
+    pub promisc: bool,
+    pub snaplen: i32,
+    pub buffer_size: i32,
+    pub timeout: i32,
+    pub immediate: bool,
+    pub tstamp_type: TimestampType,
+    pub nanos: bool,
+    pub direction: Direction,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            promisc: true,
+            snaplen: 65535,
+            buffer_size: 0,
+            timeout: 500,
+            immediate: false,
+            tstamp_type: TimestampType::Default,
+            nanos: false,
+            direction: Direction::Both,
+        }
+    }
+}
+
+impl CaptureOptions {
+    /// Opens `device` with these settings
+    fn open(&self, device: &str) -> Result<Capture<Active>, pcap::Error> {
+        let mut cap = Capture::from_device(device)?
+            .promisc(self.promisc)
+            .snaplen(self.snaplen)
+            .timeout(self.timeout.max(1))
+            .immediate_mode(self.immediate);
+        if self.buffer_size > 0 {
+            cap = cap.buffer_size(self.buffer_size);
+        }
+        let tstamp_type = match self.tstamp_type {
+            TimestampType::Default => None,
+            TimestampType::Host => Some(pcap::TimestampType::Host),
+            TimestampType::HostLowPrec => Some(pcap::TimestampType::HostLowPrec),
+            TimestampType::HostHighPrec => Some(pcap::TimestampType::HostHighPrec),
+            TimestampType::Adapter => Some(pcap::TimestampType::Adapter),
+            TimestampType::AdapterUnsynced => Some(pcap::TimestampType::AdapterUnsynced),
+        };
+        if let Some(t) = tstamp_type {
+            cap = cap.tstamp_type(t);
+        }
+        if self.nanos {
+            cap = cap.precision(Precision::Nano);
+        }
+        let cap = cap.open()?;
+        match self.direction {
+            Direction::Both => (),
+            Direction::In => cap.direction(pcap::Direction::In)?,
+            Direction::Out => cap.direction(pcap::Direction::Out)?,
+        }
+        Ok(cap)
+    }
+}
+
+/// A packet captured by one of the devices
+/// ## Properties
+/// * `interface`: Index of the device that captured it
+/// * `header`: pcap header of the packet, with nanoseconds in place of the microseconds if `CaptureOptions::nanos`
+/// * `data`: Captured bytes
+pub(crate) struct CapturedPacket {
+    pub interface: usize,
+    pub header: PacketHeader,
+    pub data: Vec<u8>,
+}
+
+/// State shared by the capture loop and a capture thread
+/// ## Properties
+/// * `stop`: Set to end the thread
+/// * `filter`: New filter to apply, if any
+/// * `stats`: Latest statistics of pcap
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    filter: Mutex<Option<String>>,
+    stats: Mutex<Option<Stat>>,
+}
+
+/// This struct captures from several devices at once
+/// ## Properties
+/// * `threads`: Shared state and handle of the thread of each device, in the order of the devices
+/// * `linktypes`: Link-layer header type of each device
+/// * `receiver`: Packets captured by all the devices
+pub(crate) struct MultiCapture {
+    threads: Vec<(Arc<Shared>, Option<JoinHandle<()>>)>,
+    linktypes: Vec<Linktype>,
+    receiver: Receiver<CapturedPacket>,
+}
+
+impl MultiCapture {
+    /// Opens the devices with `filter` and `options` and starts their threads. Fails if any of them cannot be opened
+    pub fn open(devices: &[String], filter: &str, options: &CaptureOptions) -> Result<Self, SnifferError> {
+        let (sender, receiver) = sync_channel(QUEUE_LEN);
+        let mut capture = MultiCapture { threads: vec![], linktypes: vec![], receiver };
+        for (interface, device) in devices.iter().enumerate() {
+            let shared = Arc::new(Shared::default());
+            //the capture handle cannot be moved between threads: each thread opens its own and reports how it went
+            let (opened_sender, opened) = channel();
+            let handle = spawn(interface, device.clone(), filter.to_string(), options.clone(), Arc::clone(&shared), sender.clone(), opened_sender);
+            capture.threads.push((shared, Some(handle)));
+            let linktype = opened.recv().unwrap_or(Err(SnifferError::OpenErrorCapture))?;
+            capture.linktypes.push(linktype);
+        }
+        Ok(capture)
+    }
+
+    /// Link-layer header type of each device
+    pub fn linktypes(&self) -> &[Linktype] {
+        &self.linktypes
+    }
+
+    /// Waits up to `timeout` for the next packet of any device
+    pub fn next(&self, timeout: Duration) -> Option<CapturedPacket> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Replaces the filter of every device, keeping the old one if the new one is invalid for any of them
+    pub fn set_filter(&self, filter: &str) -> Result<(), SnifferError> {
+        for linktype in &self.linktypes {
+            let dead = Capture::dead(*linktype).map_err(|_| SnifferError::InvalidFilter)?;
+            dead.compile(filter, true).map_err(|_| SnifferError::InvalidFilter)?;
+        }
+        for (shared, _) in &self.threads {
+            *shared.filter.lock().unwrap() = Some(filter.to_string());
+        }
+        Ok(())
+    }
+
+    /// Statistics of pcap summed over the devices, `None` if none of them reported any
+    pub fn stats(&self) -> Option<Stat> {
+        let stats: Vec<Stat> = self.threads.iter().filter_map(|(shared, _)| *shared.stats.lock().unwrap()).collect();
+        if stats.is_empty() {
+            return None;
+        }
+        Some(stats.iter().fold(Stat { received: 0, dropped: 0, if_dropped: 0 }, |sum, s| Stat {
+            received: sum.received.wrapping_add(s.received),
+            dropped: sum.dropped.wrapping_add(s.dropped),
+            if_dropped: sum.if_dropped.wrapping_add(s.if_dropped),
+        }))
+    }
+
+    /// Tells the capture threads to end: the packets they already captured are still returned by `drain`
+    pub fn stop(&self) {
+        for (shared, _) in &self.threads {
+            shared.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits for the next packet captured before `stop`, `None` once every capture thread has ended
+    pub fn drain(&self) -> Option<CapturedPacket> {
+        self.receiver.recv().ok()
+    }
+
+    /// Ends the capture threads and returns the final statistics of pcap, summed over the devices.
+    /// The packets not received with `next` or `drain` are discarded
+    pub fn close(mut self) -> Option<Stat> {
+        self.join();
+        self.stats()
+    }
+
+    fn join(&mut self) {
+        self.stop();
+        //a thread waiting on a full queue sends one more packet before seeing `stop`
+        while self.receiver.recv().is_ok() {}
+        for (_, handle) in self.threads.iter_mut() {
+            if let Some(handle) = handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+impl Drop for MultiCapture {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// Starts the thread capturing from `device`, which sends the link-layer header type of the device on `opened`,
+/// or the error that prevented opening it
+fn spawn(interface: usize, device: String, filter: String, options: CaptureOptions, shared: Arc<Shared>,
+    sender: SyncSender<CapturedPacket>, opened: std::sync::mpsc::Sender<Result<Linktype, SnifferError>>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let cap = options.open(&device).map_err(|_| SnifferError::OpenErrorCapture);
+        let mut cap = match cap.and_then(|mut c| c.filter(&filter, true).map(|_| c).map_err(|_| SnifferError::InvalidFilter)) {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = opened.send(Err(e));
+                return;
+            },
+        };
+        let _ = opened.send(Ok(cap.get_datalink()));
+
+        let mut last_stats = Instant::now();
+        while !shared.stop.load(Ordering::Relaxed) {
+            if let Some(filter) = shared.filter.lock().unwrap().take() {
+                if let Err(e) = cap.filter(&filter, true) {
+                    eprintln!("Error setting the filter on {}: {}", device, e);
+                }
+            }
+            if last_stats.elapsed() >= Duration::from_secs(1) {
+                last_stats = Instant::now();
+                if let Ok(stats) = cap.stats() {
+                    *shared.stats.lock().unwrap() = Some(stats);
+                }
+            }
+            if let Ok(packet) = cap.next() {
+                let packet = CapturedPacket { interface, header: *packet.header, data: packet.data.to_vec() };
+                if sender.send(packet).is_err() {
+                    break;
+                }
+            }
+        }
+        if let Ok(stats) = cap.stats() {
+            *shared.stats.lock().unwrap() = Some(stats);
+        }
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A capture whose devices are threads sending the packets of `packets`, one list per device, instead of capturing them
+    pub(crate) fn feed(packets: Vec<Vec<Vec<u8>>>) -> MultiCapture {
+        let (sender, receiver) = sync_channel(QUEUE_LEN);
+        let mut capture = MultiCapture { threads: vec![], linktypes: vec![], receiver };
+        for (interface, packets) in packets.into_iter().enumerate() {
+            let shared = Arc::new(Shared::default());
+            *shared.stats.lock().unwrap() = Some(Stat { received: 10, dropped: interface as u32, if_dropped: 1 });
+            let sender = sender.clone();
+            let handle = thread::spawn(move || {
+                for data in packets {
+                    // the timeval field types differ between platforms
+                    let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+                    header.caplen = data.len() as u32;
+                    header.len = data.len() as u32;
+                    let _ = sender.send(CapturedPacket { interface, header, data });
+                }
+            });
+            capture.threads.push((shared, Some(handle)));
+            capture.linktypes.push(Linktype::ETHERNET);
+        }
+        capture
+    }
+
+    /// A capture whose devices send `packets` packets each, numbered from 0
+    fn capture(devices: usize, packets: u8) -> MultiCapture {
+        feed((0..devices).map(|_| (0..packets).map(|n| vec![n]).collect()).collect())
+    }
+
+    #[test]
+    fn merged_devices() {
+        let capture = capture(2, 100);
+        let mut received = [vec![], vec![]];
+        while let Some(packet) = capture.next(Duration::from_millis(100)) {
+            received[packet.interface].push(packet.data[0]);
+        }
+        // the packets of each device keep their order
+        let expected: Vec<u8> = (0..100).collect();
+        assert_eq!(received, [expected.clone(), expected]);
+        let stats = capture.stats().unwrap();
+        assert_eq!((stats.received, stats.dropped, stats.if_dropped), (20, 1, 2));
+    }
+
+    #[test]
+    fn drained_after_stop() {
+        let capture = capture(2, 100);
+        let mut received = 0;
+        while received < 10 {
+            received += capture.next(Duration::from_millis(100)).map_or(0, |_| 1);
+        }
+        // the packets queued before the stop are still returned
+        capture.stop();
+        while capture.drain().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 200);
+        assert_eq!(capture.close().map(|s| s.received), Some(20));
+    }
+
+    #[test]
+    fn no_stats() {
+        let capture = capture(0, 0);
+        assert!(capture.stats().is_none());
+        assert!(capture.next(Duration::from_millis(1)).is_none());
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!("both".parse(), Ok(Direction::Both));
+        assert_eq!("inout".parse(), Ok(Direction::Both));
+        assert_eq!("in".parse(), Ok(Direction::In));
+        assert_eq!("out".parse(), Ok(Direction::Out));
+        assert!("up".parse::<Direction>().is_err());
+    }
+
+    #[test]
+    fn timestamp_types() {
+        let cases = [
+            ("default", TimestampType::Default),
+            ("host", TimestampType::Host),
+            ("host_lowprec", TimestampType::HostLowPrec),
+            ("host_hiprec", TimestampType::HostHighPrec),
+            ("adapter", TimestampType::Adapter),
+            ("adapter_unsynced", TimestampType::AdapterUnsynced),
+        ];
+        for (name, tstamp_type) in cases {
+            assert_eq!(name.parse(), Ok(tstamp_type));
+        }
+        assert!("host_highprec".parse::<TimestampType>().unwrap_err().starts_with("Unknown timestamp type"));
+    }
+
+    #[test]
+    fn default_options() {
+        let options = CaptureOptions::default();
+        assert!(options.promisc && !options.immediate && !options.nanos);
+        assert_eq!((options.snaplen, options.buffer_size, options.timeout), (65535, 0, 500));
+        assert_eq!((options.tstamp_type, options.direction), (TimestampType::Default, Direction::Both));
+    }
+}
@@ -0,0 +1,222 @@
+//! CoAP (RFC 7252) decoding: request methods, URIs and response codes.
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result};
+
+/// Maximum number of distinct requests kept for each connection
+const MAX_REQUESTS: usize = 32;
+
+const OPTION_URI_HOST: u16 = 3;
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_URI_QUERY: u16 = 15;
+
+/// This struct summarises the CoAP exchanges of a connection
+/// ## Properties
+/// * `requests`: Number of requests for each method and URI
+/// * `responses`: Number of responses for each response code
+/// * `truncated`: `true` if some requests were dropped because of the `MAX_REQUESTS` limit
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CoapSummary {
+    requests: BTreeMap<(String, String), u32>,
+    responses: BTreeMap<String, u32>,
+    truncated: bool,
+}
+
+impl CoapSummary {
+    /// Returns `true` if nothing has been decoded yet
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.responses.is_empty()
+    }
+
+    /// Decodes a CoAP message. Returns `false` if the datagram is not CoAP
+    pub fn update(&mut self, datagram: &[u8]) -> bool {
+        if datagram.len() < 4 || datagram[0] >> 6 != 1 {
+            return false;
+        }
+        let token_len = (datagram[0] & 0x0f) as usize;
+        let code = datagram[1];
+        if token_len > 8 || datagram.len() < 4 + token_len {
+            return false;
+        }
+        let (class, detail) = (code >> 5, code & 0x1f);
+        // 0.00 is an empty message (ACK/RST/ping)
+        if code == 0 {
+            return true;
+        }
+        if class == 0 {
+            let method = match detail {
+                1 => "GET",
+                2 => "POST",
+                3 => "PUT",
+                4 => "DELETE",
+                5 => "FETCH",
+                6 => "PATCH",
+                7 => "iPATCH",
+                _ => return false,
+            };
+            let uri = match uri(&datagram[4 + token_len..]) {
+                Some(u) => u,
+                None => return false,
+            };
+            let key = (method.to_string(), uri);
+            if self.requests.len() >= MAX_REQUESTS && !self.requests.contains_key(&key) {
+                self.truncated = true;
+            } else {
+                *self.requests.entry(key).or_insert(0) += 1;
+            }
+        } else {
+            *self.responses.entry(format!("{}.{:02}", class, detail)).or_insert(0) += 1;
+        }
+        true
+    }
+}
+
+impl Display for CoapSummary {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let requests: Vec<String> = self.requests.iter()
+            .map(|((method, uri), n)| format!("{} {} x{}", method, uri, n))
+            .collect();
+        write!(f, "CoAP {}", requests.join(", "))?;
+        if self.truncated {
+            write!(f, ", ...")?;
+        }
+        let responses: Vec<String> = self.responses.iter().map(|(code, n)| format!("{} x{}", code, n)).collect();
+        if !responses.is_empty() {
+            write!(f, "; responses: {}", responses.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Rebuilds the request URI from the Uri-Host, Uri-Path and Uri-Query options
+fn uri(mut options: &[u8]) -> Option<String> {
+    let mut host = String::new();
+    let mut path = String::new();
+    let mut query: Vec<String> = vec![];
+    let mut number = 0u16;
+    while let Some((&first, rest)) = options.split_first() {
+        // payload marker
+        if first == 0xff {
+            break;
+        }
+        options = rest;
+        let delta = extended(first >> 4, &mut options)?;
+        let len = extended(first & 0x0f, &mut options)? as usize;
+        if options.len() < len {
+            return None;
+        }
+        number = number.checked_add(delta)?;
+        let value = String::from_utf8_lossy(&options[..len]).into_owned();
+        match number {
+            OPTION_URI_HOST => host = value,
+            OPTION_URI_PATH => {
+                path.push('/');
+                path.push_str(&value);
+            }
+            OPTION_URI_QUERY => query.push(value),
+            _ => (),
+        }
+        options = &options[len..];
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    let mut uri = host + &path;
+    if !query.is_empty() {
+        uri += &format!("?{}", query.join("&"));
+    }
+    Some(uri)
+}
+
+/// Decodes an option delta or length nibble, with its 1 or 2 byte extension
+fn extended(nibble: u8, options: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        13 => {
+            let (b, rest) = options.split_first()?;
+            *options = rest;
+            Some(*b as u16 + 13)
+        }
+        14 => {
+            if options.len() < 2 {
+                return None;
+            }
+            let v = u16::from_be_bytes([options[0], options[1]]);
+            *options = &options[2..];
+            v.checked_add(269)
+        }
+        15 => None,
+        n => Some(n as u16),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Confirmable GET /sensors/temp?unit=c with a one byte token
+    fn get() -> Vec<u8> {
+        let mut data = vec![0x41, 0x01, 0x12, 0x34, 0xaa];
+        // Uri-Path, Uri-Path, Uri-Query
+        data.push(0xb7);
+        data.extend_from_slice(b"sensors");
+        data.push(0x04);
+        data.extend_from_slice(b"temp");
+        data.push(0x46);
+        data.extend_from_slice(b"unit=c");
+        data.extend_from_slice(&[0xff, b'x']);
+        data
+    }
+
+    #[test]
+    fn requests_and_responses() {
+        let mut summary = CoapSummary::default();
+        assert!(summary.is_empty());
+        assert!(summary.update(&get()));
+        assert!(summary.update(&get()));
+        // ACK 2.05 Content
+        assert!(summary.update(&[0x61, 0x45, 0x12, 0x34, 0xaa, 0xff, b'2', b'1']));
+        // empty ACK
+        assert!(summary.update(&[0x60, 0x00, 0x12, 0x35]));
+        assert_eq!(summary.to_string(), "CoAP GET /sensors/temp?unit=c x2; responses: 2.05 x1");
+    }
+
+    #[test]
+    fn not_coap() {
+        let mut summary = CoapSummary::default();
+        // version 0
+        assert!(!summary.update(&[0x01, 0x01, 0, 0]));
+        // token longer than 8 bytes
+        assert!(!summary.update(&[0x49, 0x01, 0, 0]));
+        // unknown method
+        assert!(!summary.update(&[0x40, 0x1f, 0, 0]));
+        // option past the end
+        assert!(!summary.update(&[0x40, 0x01, 0, 0, 0xb7, b'a']));
+        assert!(!summary.update(&[0x40]));
+        assert!(summary.is_empty());
+    }
+
+    #[test]
+    fn extended_options() {
+        // Uri-Host with a 1 byte length extension, Uri-Path, then an empty option with a 2 byte delta extension
+        let host = "a-rather-long-host.example";
+        let mut options = vec![0x3d, (host.len() - 13) as u8];
+        options.extend_from_slice(host.as_bytes());
+        options.extend_from_slice(&[0x81, b'p']);
+        options.extend_from_slice(&[0xe0, 0x07, 0x04]);
+        assert_eq!(uri(&options), Some(format!("{}/p", host)));
+        assert_eq!(uri(&[]), Some(String::from("/")));
+        // reserved nibble
+        assert_eq!(uri(&[0xf0]), None);
+    }
+
+    #[test]
+    fn requests_limit() {
+        let mut summary = CoapSummary::default();
+        for i in 0..MAX_REQUESTS + 1 {
+            let mut data = vec![0x40, 0x01, 0, 0, 0xb0 | 2];
+            data.extend_from_slice(format!("{:02}", i).as_bytes());
+            assert!(summary.update(&data));
+        }
+        assert_eq!(summary.requests.len(), MAX_REQUESTS);
+        assert!(summary.to_string().ends_with(", ..."));
+    }
+}
@@ -0,0 +1,134 @@
+//! Commands accepted on the standard input during the capture.
+
+pub(crate) const HELP: &str = "Commands:
+  p, pause          pause the capture and print the report
+  r, resume         resume the capture
+  stats             show the capture counters
+  top [n]           show the n connections with the most bytes (default 10)
+  dump [file]       write the report now, to the report file or to `file`
+  extract n [file]  write the packets kept for connection n to a pcap file (default connection<n>.pcap)
+  filter [bpf]      show the filter, or replace it (syntax at https://biot.com/capstats/bpf.html)
+  time +n|-n|n      extend, shorten or set the capture time left (secs)
+  clear             clear the screen
+  help              show this help
+  quit              end the capture";
+
+/// Change of the capture time left
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum TimeChange {
+    Add(f64),
+    Set(f64),
+}
+
+/// This enum provides the commands of the interpreter
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Command {
+    Pause,
+    Resume,
+    Stats,
+    Top(usize),
+    Dump(Option<String>),
+    /// Number of a connection, as shown in the report, and pcap file
+    Extract(usize, String),
+    Filter(Option<String>),
+    Time(TimeChange),
+    Clear,
+    Help,
+    Quit,
+    /// An empty line
+    Nothing,
+}
+
+/// Parses a line typed by the user, returning an error message for invalid input
+pub(crate) fn parse(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let (name, arg) = match line.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, Some(arg.trim())),
+        None => (line, None),
+    };
+    let no_arg = |cmd: Command| match arg {
+        None => Ok(cmd),
+        Some(_) => Err(format!("\"{}\" takes no argument", name)),
+    };
+    match name {
+        "" => Ok(Command::Nothing),
+        "p" | "pause" => no_arg(Command::Pause),
+        "r" | "resume" => no_arg(Command::Resume),
+        "stats" => no_arg(Command::Stats),
+        "clear" => no_arg(Command::Clear),
+        "help" | "?" => no_arg(Command::Help),
+        "quit" | "q" | "exit" => no_arg(Command::Quit),
+        "top" => match arg {
+            None => Ok(Command::Top(10)),
+            Some(n) => n.parse().map(Command::Top).map_err(|_| format!("Invalid number of connections: {}", n)),
+        },
+        "dump" => Ok(Command::Dump(arg.map(String::from))),
+        "filter" => Ok(Command::Filter(arg.map(String::from))),
+        "extract" => {
+            let arg = arg.ok_or_else(|| String::from("Usage: extract n [file]"))?;
+            let (n, file) = match arg.split_once(char::is_whitespace) {
+                Some((n, file)) => (n, Some(file.trim())),
+                None => (arg, None),
+            };
+            let n: usize = n.parse().map_err(|_| format!("Invalid connection number: {}", n))?;
+            Ok(Command::Extract(n, file.map(String::from).unwrap_or_else(|| format!("connection{}.pcap", n))))
+        },
+        "time" => {
+            let arg = arg.ok_or_else(|| String::from("Usage: time +n|-n|n"))?;
+            let invalid = || format!("Invalid time: {}", arg);
+            if let Some(n) = arg.strip_prefix('+') {
+                n.parse().map(|n| Command::Time(TimeChange::Add(n))).map_err(|_| invalid())
+            } else if let Some(n) = arg.strip_prefix('-') {
+                n.parse::<f64>().map(|n| Command::Time(TimeChange::Add(-n))).map_err(|_| invalid())
+            } else {
+                arg.parse().map(|n| Command::Time(TimeChange::Set(n))).map_err(|_| invalid())
+            }
+        }
+        _ => Err(format!("Unknown command \"{}\", type \"help\" for the list of commands", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let cases = [
+            ("", Command::Nothing),
+            ("   ", Command::Nothing),
+            ("p", Command::Pause),
+            ("pause", Command::Pause),
+            (" r ", Command::Resume),
+            ("stats", Command::Stats),
+            ("clear", Command::Clear),
+            ("?", Command::Help),
+            ("exit", Command::Quit),
+            ("top", Command::Top(10)),
+            ("top  3", Command::Top(3)),
+            ("dump", Command::Dump(None)),
+            ("dump out/report.txt", Command::Dump(Some(String::from("out/report.txt")))),
+            ("filter", Command::Filter(None)),
+            ("filter tcp port 80", Command::Filter(Some(String::from("tcp port 80")))),
+            ("extract 4", Command::Extract(4, String::from("connection4.pcap"))),
+            ("extract 4   flow.pcap", Command::Extract(4, String::from("flow.pcap"))),
+            ("time +30", Command::Time(TimeChange::Add(30.0))),
+            ("time -2.5", Command::Time(TimeChange::Add(-2.5))),
+            ("time 60", Command::Time(TimeChange::Set(60.0))),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse(line), Ok(command), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("pause now"), Err(String::from("\"pause\" takes no argument")));
+        assert_eq!(parse("top many"), Err(String::from("Invalid number of connections: many")));
+        assert_eq!(parse("extract"), Err(String::from("Usage: extract n [file]")));
+        assert_eq!(parse("extract x flow.pcap"), Err(String::from("Invalid connection number: x")));
+        assert_eq!(parse("time"), Err(String::from("Usage: time +n|-n|n")));
+        assert_eq!(parse("time +x"), Err(String::from("Invalid time: +x")));
+        assert!(parse("jump").unwrap_err().starts_with("Unknown command \"jump\""));
+    }
+}
@@ -0,0 +1,162 @@
+//! Control socket: a Unix domain socket accepting JSON-lines requests to manage a running capture.
+//!
+//! Each request is a JSON object on its own line, e.g. `{"cmd":"pause"}`, `{"cmd":"snapshot","file":"now.txt"}`
+//! `{"cmd":"filter","filter":"tcp port 80"}` or `{"cmd":"extract","connection":3,"file":"c3.pcap"}`, and is answered by one JSON line with `"ok"` set to `true`,
+//! or to `false` along with an `"error"` message. The requests are the ones of `requests`, also served by the HTTP API.
+use super::requests::{answer, error, Query, Request};
+use super::SyncStruct;
+use serde_json::Value;
+use std::fs;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+/// This struct is the control socket of a capture, the socket file is removed when it is dropped
+pub(crate) struct ControlSocket {
+    path: String,
+}
+
+impl ControlSocket {
+    /// Listens on `path`, serving each client on its own thread
+    /// ## Parameters
+    /// * `path`: Path of the socket file, a stale one is replaced. Any other file there is an error, never removed
+    /// * `waiter`: Synchronization variable of the capture, used for pause, resume and stop
+    /// * `queries`: Channel towards the capture loop, for the other requests
+    pub fn bind(path: &str, waiter: Arc<SyncStruct>, queries: Sender<Query>) -> std::io::Result<Self> {
+        //a socket left by a previous run would make bind fail
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path)));
+            }
+            if UnixStream::connect(path).is_err() {
+                fs::remove_file(path)?;
+            }
+        }
+        //whoever reaches the socket can stop the capture and write files: only the owner may,
+        //from its creation on, so the umask is restricted while binding
+        let previous = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(previous) };
+        let listener = listener?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let waiter = Arc::clone(&waiter);
+                let queries = queries.clone();
+                thread::spawn(move || serve(stream, waiter, queries));
+            }
+        });
+        Ok(ControlSocket { path: path.to_string() })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Answers the requests of a client until it disconnects
+fn serve(stream: UnixStream, waiter: Arc<SyncStruct>, queries: Sender<Query>) {
+    let mut writer = match stream.try_clone() {
+        Ok(w) => w,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Err(e) => error(&format!("Invalid request: {}", e)),
+            Ok(request) => answer(request, &waiter, &queries),
+        };
+        if writeln!(writer, "{}", reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends a request to the control socket at `path` and returns the reply line
+pub(crate) fn send(path: &str, request: &Value) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", request)?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    Ok(reply.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::requests::ok;
+    use super::super::requests::tests::{capture_loop, waiter};
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::mpsc::channel;
+
+    fn socket_path(test: &str) -> String {
+        let path = std::env::temp_dir().join(format!("packet_sniffer-control-{}-{}.sock", std::process::id(), test));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn answers() {
+        let path = socket_path("answers");
+        let waiter = waiter();
+        let socket = ControlSocket::bind(&path, Arc::clone(&waiter), capture_loop()).unwrap();
+        let answer = |cmd: &str| serde_json::from_str::<Value>(&send(&path, &json!({ "cmd": cmd })).unwrap()).unwrap();
+        assert_eq!(answer("pause"), ok(json!({})));
+        assert_eq!(answer("pause"), error("The capture is already paused"));
+        assert_eq!(answer("resume"), ok(json!({})));
+        assert_eq!(answer("resume"), error("The capture is not paused"));
+        assert_eq!(answer("status"), ok(json!({ "request": "Status" })));
+        assert_eq!(answer("stop"), ok(json!({})));
+        assert_eq!(waiter.state.lock().unwrap().time_interval, 0.0);
+        drop(socket);
+
+        let (queries, received) = channel();
+        drop(received);
+        let _socket = ControlSocket::bind(&path, waiter, queries).unwrap();
+        assert_eq!(answer("status"), error("The capture is over"));
+    }
+
+    #[test]
+    fn socket() {
+        let path = socket_path("socket");
+        let socket = ControlSocket::bind(&path, waiter(), capture_loop()).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let reply: Value = serde_json::from_str(&send(&path, &json!({ "cmd": "snapshot" })).unwrap()).unwrap();
+        assert_eq!(reply, ok(json!({ "request": "Snapshot { file: None }" })));
+        let reply: Value = serde_json::from_str(&send(&path, &json!({ "cmd": "jump" })).unwrap()).unwrap();
+        assert_eq!(reply["ok"], json!(false));
+        assert!(reply["error"].as_str().unwrap().starts_with("Invalid request"));
+        drop(socket);
+        assert!(fs::symlink_metadata(&path).is_err());
+    }
+
+    #[test]
+    fn stale_socket_replaced() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(fs::symlink_metadata(&path).is_ok());
+        let _socket = ControlSocket::bind(&path, waiter(), capture_loop()).unwrap();
+        assert!(send(&path, &json!({ "cmd": "status" })).is_ok());
+    }
+
+    #[test]
+    fn other_files_kept() {
+        let path = socket_path("file");
+        fs::write(&path, "data").unwrap();
+        let err = ControlSocket::bind(&path, waiter(), capture_loop()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+//! SQLite storage of the capture history: sessions, snapshots and connections.
+//!
+//! A session is a capture (device, filter, start and end). Each snapshot records the totals of the session at that
+//! time in the snapshots table, and updates the connections changed since the previous snapshot: the connections table
+//! only keeps the latest state of every connection, not its state at each snapshot.
+use super::{format_ip, Connection, IpV, Transport};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use rusqlite::{params, Connection as Db, Result};
+use std::net::IpAddr;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Format of the times stored, sortable as text
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        device TEXT NOT NULL,
+        filter TEXT NOT NULL,
+        start TEXT NOT NULL,
+        end TEXT
+    );
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions(id),
+        time TEXT NOT NULL,
+        connections INTEGER NOT NULL,
+        bytes INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS connections (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions(id),
+        number INTEGER NOT NULL,
+        ip_protocol TEXT NOT NULL,
+        address_a TEXT NOT NULL,
+        address_b TEXT NOT NULL,
+        transport_protocol TEXT NOT NULL,
+        port_a INTEGER NOT NULL,
+        port_b INTEGER NOT NULL,
+        start TEXT NOT NULL,
+        end TEXT NOT NULL,
+        app_prot TEXT NOT NULL,
+        bytes_a_to_b INTEGER NOT NULL,
+        bytes_b_to_a INTEGER NOT NULL,
+        details TEXT NOT NULL,
+        UNIQUE (session_id, number)
+    );
+    CREATE INDEX IF NOT EXISTS sessions_start ON sessions(start);
+    CREATE INDEX IF NOT EXISTS snapshots_session ON snapshots(session_id, time);
+    CREATE INDEX IF NOT EXISTS connections_address_a ON connections(address_a);
+    CREATE INDEX IF NOT EXISTS connections_address_b ON connections(address_b);
+    CREATE INDEX IF NOT EXISTS connections_port_a ON connections(port_a);
+    CREATE INDEX IF NOT EXISTS connections_port_b ON connections(port_b);
+    CREATE INDEX IF NOT EXISTS connections_start ON connections(start);
+    CREATE INDEX IF NOT EXISTS connections_end ON connections(end);
+";
+
+/// This struct selects the connections of a history query, every field is optional
+/// ## Properties
+/// * `session`: Session ID
+/// * `address`: Address A or address B
+/// * `port`: Port A or port B
+/// * `since`, `until`: Time range, as `YYYY-MM-DD[ HH:MM:SS]` in local time, matching the connections active in it
+#[derive(Clone, Debug, Default)]
+pub struct HistoryFilter {
+    pub session: Option<i64>,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+/// A snapshot to save
+/// ## Properties
+/// * `changed`: Number, state and details of the connections changed since the previous snapshot
+/// * `connections`, `bytes`: Totals of the session
+pub(crate) struct Snapshot {
+    pub changed: Vec<(usize, Connection, String)>,
+    pub connections: usize,
+    pub bytes: u64,
+}
+
+/// A session of the history
+pub(crate) struct Session {
+    pub id: i64,
+    pub device: String,
+    pub filter: String,
+    pub start: String,
+    pub end: Option<String>,
+}
+
+/// This struct is the database of a capture
+/// ## Properties
+/// * `db`: SQLite connection
+/// * `session`: ID of the current session, once started
+pub(crate) struct Database {
+    db: Db,
+    session: Option<i64>,
+}
+
+impl Database {
+    /// Opens or creates the database at `path`
+    pub fn open(path: &str) -> Result<Self> {
+        let db = Db::open(path)?;
+        db.execute_batch(SCHEMA)?;
+        Ok(Database { db, session: None })
+    }
+
+    /// Starts a new session
+    pub fn start_session(&mut self, device: &str, filter: &str) -> Result<()> {
+        self.db.execute(
+            "INSERT INTO sessions (device, filter, start) VALUES (?1, ?2, ?3)",
+            params![device, filter, now()],
+        )?;
+        self.session = Some(self.db.last_insert_rowid());
+        Ok(())
+    }
+
+    /// Records the totals of a snapshot of the session and stores its changed connections, replacing their previous state
+    pub fn snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let session = match self.session {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let time = now();
+        let tx = self.db.transaction()?;
+        {
+            let mut upsert = tx.prepare_cached(
+                "INSERT INTO connections (session_id, number, ip_protocol, address_a, address_b, transport_protocol, port_a, port_b,
+                    start, end, app_prot, bytes_a_to_b, bytes_b_to_a, details)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT (session_id, number) DO UPDATE SET
+                    end = excluded.end, app_prot = excluded.app_prot, bytes_a_to_b = excluded.bytes_a_to_b,
+                    bytes_b_to_a = excluded.bytes_b_to_a, details = excluded.details",
+            )?;
+            for (number, con, details) in &snapshot.changed {
+                upsert.execute(params![
+                    session,
+                    number,
+                    con.l3.to_string(),
+                    con.ip_1,
+                    con.ip_2,
+                    con.l4.to_string(),
+                    con.port_1.parse::<u16>().unwrap_or(0),
+                    con.port_2.parse::<u16>().unwrap_or(0),
+                    con.ts_start.format(TIME_FORMAT).to_string(),
+                    con.ts_end.format(TIME_FORMAT).to_string(),
+                    con.app_prot,
+                    con.bytes_a_to_b,
+                    con.bytes_b_to_a,
+                    details,
+                ])?;
+            }
+            tx.execute(
+                "INSERT INTO snapshots (session_id, time, connections, bytes) VALUES (?1, ?2, ?3, ?4)",
+                params![session, time, snapshot.connections, snapshot.bytes],
+            )?;
+            tx.execute("UPDATE sessions SET end = ?1 WHERE id = ?2", params![time, session])?;
+        }
+        tx.commit()
+    }
+}
+
+/// Saves the snapshots sent on the returned channel from a thread of its own, so that the capture never waits for the
+/// database. The thread ends when the channel is dropped, giving the database back
+pub(crate) fn spawn_writer(mut db: Database) -> (Sender<Snapshot>, JoinHandle<Database>) {
+    let (sender, receiver) = channel::<Snapshot>();
+    let handle = thread::spawn(move || {
+        for snapshot in receiver {
+            if let Err(e) = db.snapshot(&snapshot) {
+                eprintln!("Error writing the database: {}", e);
+            }
+        }
+        db
+    });
+    (sender, handle)
+}
+
+/// A session with its connections, numbered as in its report
+pub(crate) type SessionHistory = (Session, Vec<(usize, Connection)>);
+
+/// Loads the sessions and the connections matching `filter`, grouped by session
+pub(crate) fn query(path: &str, filter: &HistoryFilter) -> Result<Vec<SessionHistory>> {
+    let db = Db::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut sql = String::from(
+        "SELECT c.session_id, c.number, c.ip_protocol, c.address_a, c.address_b, c.transport_protocol, c.port_a, c.port_b,
+            c.start, c.end, c.app_prot, c.bytes_a_to_b, c.bytes_b_to_a, c.details,
+            s.device, s.filter, s.start, s.end
+         FROM connections c JOIN sessions s ON s.id = c.session_id WHERE 1 = 1",
+    );
+    let mut args: Vec<Box<dyn rusqlite::ToSql>> = vec![];
+    if let Some(session) = filter.session {
+        args.push(Box::new(session));
+        sql.push_str(&format!(" AND c.session_id = ?{}", args.len()));
+    }
+    if let Some(address) = &filter.address {
+        //addresses are stored as the capture formats them
+        let address = address.parse::<IpAddr>().map(|a| format_ip(&a)).unwrap_or(address.clone());
+        args.push(Box::new(address));
+        sql.push_str(&format!(" AND (c.address_a = ?{0} OR c.address_b = ?{0})", args.len()));
+    }
+    if let Some(port) = filter.port {
+        args.push(Box::new(port));
+        sql.push_str(&format!(" AND (c.port_a = ?{0} OR c.port_b = ?{0})", args.len()));
+    }
+    if let Some(since) = &filter.since {
+        args.push(Box::new(since.clone()));
+        sql.push_str(&format!(" AND c.end >= ?{}", args.len()));
+    }
+    if let Some(until) = &filter.until {
+        //a bare date includes the whole day
+        let until = if until.len() <= 10 { format!("{} 24", until) } else { until.clone() };
+        args.push(Box::new(until));
+        sql.push_str(&format!(" AND c.start <= ?{}", args.len()));
+    }
+    sql.push_str(" ORDER BY c.session_id, c.number");
+
+    let mut stmt = db.prepare(&sql)?;
+    let mut rows = stmt.query(rusqlite::params_from_iter(args.iter()))?;
+    let mut result: Vec<SessionHistory> = vec![];
+    while let Some(row) = rows.next()? {
+        let session: i64 = row.get(0)?;
+        if result.last().is_none_or(|(s, _)| s.id != session) {
+            result.push((Session { id: session, device: row.get(14)?, filter: row.get(15)?, start: row.get(16)?, end: row.get(17)? }, vec![]));
+        }
+        let details: String = row.get(13)?;
+        let con = Connection {
+            l3: if row.get::<_, String>(2)? == "IPv6" { IpV::V6 } else { IpV::V4 },
+            ip_1: row.get(3)?,
+            ip_2: row.get(4)?,
+            l4: if row.get::<_, String>(5)? == "UDP" { Transport::UDP } else { Transport::TCP },
+            port_1: row.get::<_, u16>(6)?.to_string(),
+            port_2: row.get::<_, u16>(7)?.to_string(),
+            ts_start: time(&row.get::<_, String>(8)?),
+            ts_end: time(&row.get::<_, String>(9)?),
+            app_prot: row.get(10)?,
+            bytes_a_to_b: row.get(11)?,
+            bytes_b_to_a: row.get(12)?,
+            details: if details.is_empty() { vec![] } else { vec![details] },
+            interfaces: vec![],
+        };
+        if let Some((_, connections)) = result.last_mut() {
+            connections.push((row.get(1)?, con));
+        }
+    }
+    Ok(result)
+}
+
+fn now() -> String {
+    Local::now().format(TIME_FORMAT).to_string()
+}
+
+fn time(s: &str) -> DateTime<Local> {
+    NaiveDateTime::parse_from_str(s, TIME_FORMAT).ok()
+        .and_then(|t| Local.from_local_datetime(&t).earliest())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_path(test: &str) -> String {
+        let path = std::env::temp_dir().join(format!("packet_sniffer-database-{}-{}.db", std::process::id(), test));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 250_000).unwrap()
+    }
+
+    /// Connection from `b` to `a` on TCP, lasting from `start` to `end` (secs)
+    fn connection(a: &str, port_a: &str, b: &str, start: i64, end: i64, bytes: u32) -> Connection {
+        let l3 = if a.contains(':') { 6 } else { 4 };
+        Connection::new(l3, a.to_string(), b.to_string(), 0, port_a.to_string(), String::from("40000"), at(start), at(end), bytes,
+            String::from("HTTP"))
+    }
+
+    fn numbers(history: &[SessionHistory]) -> Vec<(i64, Vec<usize>)> {
+        history.iter().map(|(s, c)| (s.id, c.iter().map(|(n, _)| *n).collect())).collect()
+    }
+
+    /// Two sessions: the first with connections 1 (web, updated by a second snapshot) and 2 (DNS over IPv6),
+    /// the second with connection 1 to 10.0.0.9
+    fn history(path: &str) {
+        let mut db = Database::open(path).unwrap();
+        // no session yet
+        db.snapshot(&Snapshot { changed: vec![], connections: 0, bytes: 0 }).unwrap();
+        db.start_session("eth0", "tcp").unwrap();
+        let mut web = connection("10.0.0.1", "80", "10.0.0.2", 0, 10, 100);
+        let dns = connection(&format_ip(&"fe80::1".parse().unwrap()), "53", &format_ip(&"fe80::2".parse().unwrap()), 100, 101, 60);
+        db.snapshot(&Snapshot { changed: vec![(1, web.clone(), String::new()), (2, dns, String::from("Query a.example"))],
+            connections: 2, bytes: 160 }).unwrap();
+        web.update(at(20), 400, String::from("10.0.0.2"));
+        db.snapshot(&Snapshot { changed: vec![(1, web, String::from("GET /"))], connections: 2, bytes: 560 }).unwrap();
+
+        // the second session is written by the writer thread
+        let mut db = Database::open(path).unwrap();
+        db.start_session("eth1", "").unwrap();
+        let (writer, handle) = spawn_writer(db);
+        writer.send(Snapshot { changed: vec![(1, connection("10.0.0.9", "443", "10.0.0.2", 200, 210, 10), String::new())],
+            connections: 1, bytes: 10 }).unwrap();
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn latest_state() {
+        let path = db_path("latest");
+        history(&path);
+        let all = query(&path, &HistoryFilter::default()).unwrap();
+        assert_eq!(numbers(&all), vec![(1, vec![1, 2]), (2, vec![1])]);
+        let (session, connections) = &all[0];
+        assert_eq!((session.device.as_str(), session.filter.as_str()), ("eth0", "tcp"));
+        assert!(session.end.as_deref().is_some_and(|end| end.as_bytes() > session.start.as_bytes()));
+        let web = &connections[0].1;
+        assert_eq!((web.bytes_a_to_b, web.bytes_b_to_a), (100, 400));
+        assert_eq!((web.ts_start, web.ts_end), (at(0), at(20)));
+        assert_eq!(web.details, vec![String::from("GET /")]);
+        assert_eq!(format!("{} {} {}", web.l3, web.l4, web.app_prot), "IPv4 TCP HTTP");
+        let dns = &connections[1].1;
+        assert_eq!(format!("{} {} {}", dns.l3, dns.port_1, dns.port_2), "IPv6 53 40000");
+
+        let db = Db::open(&path).unwrap();
+        let snapshots: i64 = db.query_row("SELECT COUNT(*) FROM snapshots WHERE session_id = 1", [], |r| r.get(0)).unwrap();
+        assert_eq!(snapshots, 2);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn filters() {
+        let path = db_path("filters");
+        history(&path);
+        let select = |filter: HistoryFilter| numbers(&query(&path, &filter).unwrap());
+        assert_eq!(select(HistoryFilter { session: Some(2), ..Default::default() }), vec![(2, vec![1])]);
+        assert_eq!(select(HistoryFilter { address: Some(String::from("10.0.0.2")), ..Default::default() }),
+            vec![(1, vec![1]), (2, vec![1])]);
+        // addresses are matched whatever their notation
+        assert_eq!(select(HistoryFilter { address: Some(String::from("fe80:0::2")), ..Default::default() }), vec![(1, vec![2])]);
+        assert_eq!(select(HistoryFilter { port: Some(53), ..Default::default() }), vec![(1, vec![2])]);
+        assert_eq!(select(HistoryFilter { port: Some(40000), session: Some(1), ..Default::default() }), vec![(1, vec![1, 2])]);
+        let time = |secs: i64| at(secs).format("%Y-%m-%d %H:%M:%S").to_string();
+        assert_eq!(select(HistoryFilter { since: Some(time(15)), until: Some(time(150)), ..Default::default() }),
+            vec![(1, vec![1, 2])]);
+        assert_eq!(select(HistoryFilter { since: Some(time(102)), ..Default::default() }), vec![(2, vec![1])]);
+        // a bare date covers the whole day
+        let day = at(0).format("%Y-%m-%d").to_string();
+        assert_eq!(select(HistoryFilter { until: Some(day), session: Some(1), ..Default::default() }), vec![(1, vec![1, 2])]);
+        assert!(select(HistoryFilter { address: Some(String::from("192.0.2.1")), ..Default::default() }).is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(time(&at(5).format(TIME_FORMAT).to_string()), at(5));
+        assert_eq!(time("yesterday"), DateTime::<Local>::default());
+    }
+}
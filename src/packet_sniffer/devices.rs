@@ -0,0 +1,237 @@
+//! Capture devices: selection by index, name or "default", and the listing with addresses, flags and link type.
+use super::{format_ip, SnifferError};
+use pcap::{Capture, Device};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io::{Result, Write};
+use std::str::FromStr;
+
+/// This enum provides the formats of the devices listing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ListFormat {
+    #[default]
+    Text,
+    Json,
+    Tsv,
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "json" => Ok(ListFormat::Json),
+            "tsv" => Ok(ListFormat::Tsv),
+            _ => Err(format!("Unknown format \"{}\": use text, json or tsv", s)),
+        }
+    }
+}
+
+/// A capture device as listed
+/// ## Properties
+/// * `index`: Position in the devices list, usable in place of the name
+/// * `name`, `description`: Name and description of the device
+/// * `addresses`: Addresses of the device
+/// * `up`, `running`, `loopback`, `wireless`: Flags of the device
+/// * `link_type`: Link-layer header type, `None` if the device cannot be opened (e.g. without privileges)
+/// * `default`: `true` for the device chosen by "default"
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub description: Option<String>,
+    pub addresses: Vec<String>,
+    pub up: bool,
+    pub running: bool,
+    pub loopback: bool,
+    pub wireless: bool,
+    pub link_type: Option<String>,
+    pub default: bool,
+}
+
+impl DeviceInfo {
+    /// Flags as a comma separated list
+    fn flags(&self) -> String {
+        let flags = [(self.up, "up"), (self.running, "running"), (self.loopback, "loopback"), (self.wireless, "wireless")];
+        flags.iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect::<Vec<_>>().join(",")
+    }
+}
+
+/// Returns the name of a device given as its name, its index in the devices list, or "default" for the device
+/// pcap would choose
+pub(crate) fn resolve(dev: &str) -> std::result::Result<String, SnifferError> {
+    if dev == "default" {
+        return Device::lookup().map(|d| d.name).map_err(|_| SnifferError::DeviceNotFound);
+    }
+    let devs = Device::list().map_err(|_| SnifferError::DevicesListImpossibleToGet)?;
+    match dev.parse::<usize>() {
+        Ok(index) => devs.into_iter().nth(index),
+        Err(_) => devs.into_iter().find(|d| d.name == dev),
+    }.map(|d| d.name).ok_or(SnifferError::DeviceNotFound)
+}
+
+/// Lists the devices along with their flags and link type
+pub(crate) fn list() -> std::result::Result<Vec<DeviceInfo>, SnifferError> {
+    let devs = Device::list().map_err(|_| SnifferError::DevicesListImpossibleToGet)?;
+    let default = Device::lookup().map(|d| d.name).ok();
+    let flags = interface_flags();
+    Ok(devs.into_iter().enumerate().map(|(index, d)| {
+        let f = flags.get(&d.name).copied().unwrap_or_default();
+        let link_type = Capture::from_device(d.name.as_str())
+            .and_then(|c| c.open())
+            .ok()
+            .map(|c| {
+                let linktype = c.get_datalink();
+                linktype.get_name().unwrap_or_else(|_| linktype.0.to_string())
+            });
+        DeviceInfo {
+            index,
+            addresses: d.addresses.iter().map(|a| format_ip(&a.addr)).collect(),
+            up: f.up,
+            running: f.running,
+            loopback: f.loopback,
+            wireless: is_wireless(&d.name),
+            link_type,
+            default: default.as_ref() == Some(&d.name),
+            name: d.name,
+            description: d.desc,
+        }
+    }).collect())
+}
+
+/// Writes the devices in `format`
+pub(crate) fn write(devices: &[DeviceInfo], format: ListFormat, writer: &mut dyn Write) -> Result<()> {
+    match format {
+        ListFormat::Json => {
+            serde_json::to_writer_pretty(&mut *writer, devices)?;
+            writeln!(writer)
+        },
+        ListFormat::Tsv => {
+            writeln!(writer, "index\tname\tdescription\taddresses\tflags\tlink_type\tdefault")?;
+            for d in devices {
+                writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}", d.index, d.name, d.description.as_deref().unwrap_or("-"),
+                    d.addresses.join(","), d.flags(), d.link_type.as_deref().unwrap_or("-"), d.default)?;
+            }
+            Ok(())
+        },
+        ListFormat::Text => {
+            writeln!(writer, "| N°  | {0: <16} | {1: <10} | {2: <27} | {3: <40} | Description", "Name", "Link Type", "Flags", "Addresses")?;
+            for d in devices {
+                let name = if d.default { format!("{} *", d.name) } else { d.name.clone() };
+                writeln!(writer, "| {0: <3} | {1: <16} | {2: <10} | {3: <27} | {4: <40} | {5}", d.index, name,
+                    d.link_type.as_deref().unwrap_or("-"), d.flags(), d.addresses.join(", "), d.description.as_deref().unwrap_or(""))?;
+            }
+            writeln!(writer, "* default device")
+        },
+    }
+}
+
+/// Flags of a network interface
+#[derive(Clone, Copy, Debug, Default)]
+struct Flags {
+    up: bool,
+    running: bool,
+    loopback: bool,
+}
+
+/// Flags of the network interfaces, by name
+#[cfg(unix)]
+fn interface_flags() -> HashMap<String, Flags> {
+    let mut flags: HashMap<String, Flags> = HashMap::new();
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    unsafe {
+        if libc::getifaddrs(&mut addrs) != 0 {
+            return flags;
+        }
+        //one entry for each address of the interface, all with the same flags
+        let mut cur = addrs;
+        while !cur.is_null() {
+            let name = std::ffi::CStr::from_ptr((*cur).ifa_name).to_string_lossy().into_owned();
+            let f = (*cur).ifa_flags as libc::c_int;
+            flags.insert(name, Flags {
+                up: f & libc::IFF_UP != 0,
+                running: f & libc::IFF_RUNNING != 0,
+                loopback: f & libc::IFF_LOOPBACK != 0,
+            });
+            cur = (*cur).ifa_next;
+        }
+        libc::freeifaddrs(addrs);
+    }
+    flags
+}
+
+#[cfg(not(unix))]
+fn interface_flags() -> HashMap<String, Flags> {
+    HashMap::new()
+}
+
+/// `true` if the interface is a wireless one, only known on Linux
+fn is_wireless(name: &str) -> bool {
+    let dir = std::path::Path::new("/sys/class/net").join(name);
+    cfg!(target_os = "linux") && (dir.join("wireless").exists() || dir.join("phy80211").exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            DeviceInfo { index: 0, name: String::from("eth0"), description: None, addresses: vec![String::from("10.0.0.1"),
+                String::from("fe80:0000:0000:0000:0000:0000:0000:0001")], up: true, running: true, loopback: false, wireless: false,
+                link_type: Some(String::from("EN10MB")), default: true },
+            DeviceInfo { index: 1, name: String::from("lo"), description: Some(String::from("Loopback")), addresses: vec![],
+                up: true, running: false, loopback: true, wireless: false, link_type: None, default: false },
+        ]
+    }
+
+    fn written(format: ListFormat) -> String {
+        let mut out = vec![];
+        write(&devices(), format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("text".parse(), Ok(ListFormat::Text));
+        assert_eq!("json".parse(), Ok(ListFormat::Json));
+        assert_eq!("tsv".parse(), Ok(ListFormat::Tsv));
+        assert!("csv".parse::<ListFormat>().is_err());
+    }
+
+    #[test]
+    fn flags() {
+        let devices = devices();
+        assert_eq!(devices[0].flags(), "up,running");
+        assert_eq!(devices[1].flags(), "up,loopback");
+    }
+
+    #[test]
+    fn tsv() {
+        assert_eq!(written(ListFormat::Tsv), "index\tname\tdescription\taddresses\tflags\tlink_type\tdefault\n\
+            0\teth0\t-\t10.0.0.1,fe80:0000:0000:0000:0000:0000:0000:0001\tup,running\tEN10MB\ttrue\n\
+            1\tlo\tLoopback\t\tup,loopback\t-\tfalse\n");
+    }
+
+    #[test]
+    fn json() {
+        let listed: serde_json::Value = serde_json::from_str(&written(ListFormat::Json)).unwrap();
+        assert_eq!(listed[0]["name"], "eth0");
+        assert_eq!(listed[0]["default"], true);
+        assert_eq!(listed[1]["description"], "Loopback");
+        assert_eq!(listed[1]["link_type"], serde_json::Value::Null);
+        assert_eq!(listed[1]["addresses"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn text() {
+        let out = written(ListFormat::Text);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("| 0   | eth0 *           | EN10MB     | up,running "));
+        assert!(lines[2].starts_with("| 1   | lo               | -          | up,loopback "));
+        assert!(lines[2].ends_with("| Loopback"));
+        assert_eq!(lines[3], "* default device");
+    }
+}
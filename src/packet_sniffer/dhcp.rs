@@ -0,0 +1,463 @@
+//! DHCP (RFC 2131) and DHCPv6 (RFC 8415) message decoding and the host inventory built from it.
+use super::format_ip;
+use super::reader::Reader;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const DHCP_MAGIC_COOKIE: u32 = 0x6382_5363;
+
+/// This struct contains the fields of a DHCP or DHCPv6 message relevant to the host inventory
+/// ## Properties
+/// * `kind`: Message type, e.g. `DISCOVER`, `ACK`, `SOLICIT`, `REPLY`
+/// * `client_mac`: Hardware address of the client, when the message carries it
+/// * `hostname`: Client host name (option 12 / client FQDN)
+/// * `vendor_class`: Vendor class identifier (option 60 / DHCPv6 option 16)
+/// * `address`: Address assigned (or requested) by the message
+/// * `lease_time`: Lease duration in seconds
+/// * `from_server`: `true` if the message was sent by the server
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DhcpMessage {
+    pub kind: String,
+    pub client_mac: Option<String>,
+    pub hostname: Option<String>,
+    pub vendor_class: Option<String>,
+    pub address: Option<String>,
+    pub lease_time: Option<u32>,
+    pub from_server: bool,
+}
+
+impl DhcpMessage {
+    /// Short description used in the "Details" column of the report
+    pub fn summary(&self) -> String {
+        let mut s = self.kind.clone();
+        if let Some(address) = &self.address {
+            s += &format!(" {}", address);
+        }
+        if let Some(hostname) = &self.hostname {
+            s += &format!(" ({})", hostname);
+        }
+        s
+    }
+}
+
+/// Formats a hardware address as `aa:bb:cc:dd:ee:ff`
+pub(crate) fn format_mac(mac: &[u8]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Decodes a DHCP (BOOTP) message carried by UDP ports 67/68
+pub(crate) fn parse_dhcpv4(data: &[u8]) -> Option<DhcpMessage> {
+    let mut r = Reader::new(data);
+    let op = r.u8()?;
+    let htype = r.u8()?;
+    let hlen = r.u8()? as usize;
+    // hops, xid, secs, flags, ciaddr
+    r.skip(1 + 4 + 2 + 2 + 4)?;
+    let yiaddr = Ipv4Addr::from(r.u32()?);
+    // siaddr, giaddr
+    r.skip(8)?;
+    let chaddr = r.take(16)?;
+    // sname, file
+    r.skip(64 + 128)?;
+    if r.u32()? != DHCP_MAGIC_COOKIE {
+        return None;
+    }
+
+    let mut msg = DhcpMessage {
+        from_server: op == 2,
+        ..Default::default()
+    };
+    // htype 1 is Ethernet
+    if htype == 1 && hlen == 6 {
+        msg.client_mac = Some(format_mac(&chaddr[..6]));
+    }
+    let mut requested = None;
+    while r.remaining() > 0 {
+        let code = r.u8()?;
+        match code {
+            0 => continue,
+            255 => break,
+            _ => (),
+        }
+        let len = r.u8()? as usize;
+        let value = r.take(len)?;
+        match code {
+            12 => msg.hostname = Some(text(value)),
+            50 if len == 4 => requested = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3])),
+            51 if len == 4 => msg.lease_time = Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]])),
+            53 if len == 1 => msg.kind = dhcpv4_kind(value[0]),
+            60 => msg.vendor_class = Some(text(value)),
+            // client FQDN: flags, rcode1, rcode2, name
+            81 if len > 3 && msg.hostname.is_none() => {
+                let name = &value[3..];
+                msg.hostname = Some(if value[0] & 0x04 != 0 {
+                    dns_name(name).unwrap_or_default()
+                } else {
+                    text(name)
+                });
+            }
+            _ => (),
+        }
+    }
+    if msg.kind.is_empty() {
+        // plain BOOTP
+        msg.kind = String::from(if op == 1 { "BOOTREQUEST" } else { "BOOTREPLY" });
+    }
+    if !yiaddr.is_unspecified() {
+        msg.address = Some(yiaddr.to_string());
+    } else if let Some(requested) = requested {
+        msg.address = Some(requested.to_string());
+    }
+    Some(msg)
+}
+
+fn dhcpv4_kind(code: u8) -> String {
+    match code {
+        1 => String::from("DISCOVER"),
+        2 => String::from("OFFER"),
+        3 => String::from("REQUEST"),
+        4 => String::from("DECLINE"),
+        5 => String::from("ACK"),
+        6 => String::from("NAK"),
+        7 => String::from("RELEASE"),
+        8 => String::from("INFORM"),
+        c => format!("TYPE {}", c),
+    }
+}
+
+/// Decodes a DHCPv6 message carried by UDP ports 546/547
+pub(crate) fn parse_dhcpv6(data: &[u8]) -> Option<DhcpMessage> {
+    let mut r = Reader::new(data);
+    let msg_type = r.u8()?;
+    // RELAY-FORW / RELAY-REPL: hop count, link address, peer address, then options
+    if msg_type == 12 || msg_type == 13 {
+        r.skip(1 + 16 + 16)?;
+        while r.remaining() >= 4 {
+            let code = r.u16()?;
+            let len = r.u16()? as usize;
+            let value = r.take(len)?;
+            // RELAY_MSG
+            if code == 9 {
+                return parse_dhcpv6(value);
+            }
+        }
+        return None;
+    }
+    // transaction id
+    r.skip(3)?;
+
+    let kind = match msg_type {
+        1 => "SOLICIT",
+        2 => "ADVERTISE",
+        3 => "REQUEST",
+        4 => "CONFIRM",
+        5 => "RENEW",
+        6 => "REBIND",
+        7 => "REPLY",
+        8 => "RELEASE",
+        9 => "DECLINE",
+        10 => "RECONFIGURE",
+        11 => "INFORMATION-REQUEST",
+        _ => return None,
+    };
+    let mut msg = DhcpMessage {
+        kind: String::from(kind),
+        from_server: matches!(msg_type, 2 | 7 | 10),
+        ..Default::default()
+    };
+    while r.remaining() >= 4 {
+        let code = r.u16()?;
+        let len = r.u16()? as usize;
+        let value = r.take(len)?;
+        match code {
+            // CLIENTID
+            1 => msg.client_mac = duid_mac(value),
+            // IA_NA: IAID, T1, T2, then IA options
+            3 if len >= 12 => parse_ia_na(&value[12..], &mut msg),
+            // VENDOR_CLASS: enterprise number, then length-prefixed strings
+            16 if len > 6 => {
+                let mut v = Reader::new(&value[4..]);
+                if let Some(l) = v.u16() {
+                    if let Some(class) = v.take(l as usize) {
+                        msg.vendor_class = Some(text(class));
+                    }
+                }
+            }
+            // CLIENT_FQDN: flags, then DNS encoded name
+            39 if len > 1 => msg.hostname = dns_name(&value[1..]),
+            _ => (),
+        }
+    }
+    Some(msg)
+}
+
+fn parse_ia_na(mut options: &[u8], msg: &mut DhcpMessage) {
+    while options.len() >= 4 {
+        let code = u16::from_be_bytes([options[0], options[1]]);
+        let len = u16::from_be_bytes([options[2], options[3]]) as usize;
+        if options.len() < 4 + len {
+            return;
+        }
+        let value = &options[4..4 + len];
+        // IAADDR: address, preferred lifetime, valid lifetime
+        if code == 5 && len >= 24 {
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(&value[..16]);
+            msg.address = Some(format_ip(&IpAddr::V6(Ipv6Addr::from(addr))));
+            msg.lease_time = Some(u32::from_be_bytes([value[20], value[21], value[22], value[23]]));
+        }
+        options = &options[4 + len..];
+    }
+}
+
+/// Extracts the link-layer address from a DUID-LLT or DUID-LL
+fn duid_mac(duid: &[u8]) -> Option<String> {
+    let mut r = Reader::new(duid);
+    let duid_type = r.u16()?;
+    let hw_type = r.u16()?;
+    if hw_type != 1 {
+        return None;
+    }
+    match duid_type {
+        1 => {
+            r.skip(4)?;
+            Some(format_mac(r.take(6)?))
+        }
+        3 => Some(format_mac(r.take(6)?)),
+        _ => None,
+    }
+}
+
+/// Decodes an uncompressed DNS wire-format name
+fn dns_name(data: &[u8]) -> Option<String> {
+    let mut r = Reader::new(data);
+    let mut labels = vec![];
+    while r.remaining() > 0 {
+        let len = r.u8()? as usize;
+        if len == 0 {
+            break;
+        }
+        labels.push(text(r.take(len)?));
+    }
+    Some(labels.join("."))
+}
+
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// This struct describes a host learnt from DHCP traffic
+/// ## Properties
+/// * `ip`: Last address assigned to the host
+/// * `hostname`: Host name announced by the client
+/// * `vendor_class`: Vendor class announced by the client
+/// * `lease_time`: Lease duration in seconds
+/// * `last_message`: Last DHCP message type seen for the host
+/// * `last_seen`: Timestamp of the last DHCP message
+#[derive(Clone, Debug)]
+pub(crate) struct HostEntry {
+    pub ip: Option<String>,
+    pub hostname: Option<String>,
+    pub vendor_class: Option<String>,
+    pub lease_time: Option<u32>,
+    pub last_message: String,
+    pub last_seen: DateTime<Local>,
+}
+
+/// This struct maps the MAC address of each DHCP client to its address and host name
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HostInventory {
+    hosts: BTreeMap<String, HostEntry>,
+}
+
+impl HostInventory {
+    /// Updates the inventory with a decoded message
+    /// ## Parameters
+    /// * `msg`: The decoded DHCP/DHCPv6 message
+    /// * `mac`: Client MAC address, used when the message does not carry one
+    /// * `ts`: Timestamp of the packet
+    pub fn observe(&mut self, msg: &DhcpMessage, mac: Option<String>, ts: DateTime<Local>) {
+        let mac = match msg.client_mac.clone().or(mac) {
+            Some(m) => m,
+            None => return,
+        };
+        let entry = self.hosts.entry(mac).or_insert_with(|| HostEntry {
+            ip: None,
+            hostname: None,
+            vendor_class: None,
+            lease_time: None,
+            last_message: String::new(),
+            last_seen: ts,
+        });
+        // only the server confirms the address actually in use
+        if msg.kind == "ACK" || msg.kind == "REPLY" {
+            if msg.address.is_some() {
+                entry.ip = msg.address.clone();
+            }
+            if msg.lease_time.is_some() {
+                entry.lease_time = msg.lease_time;
+            }
+        }
+        if msg.hostname.is_some() {
+            entry.hostname = msg.hostname.clone();
+        }
+        if msg.vendor_class.is_some() {
+            entry.vendor_class = msg.vendor_class.clone();
+        }
+        entry.last_message = msg.kind.clone();
+        entry.last_seen = ts;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Writes the inventory as a table, in the same layout as the connection report
+    pub fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "\n Host inventory (DHCP)\n")?;
+        writeln!(writer, "| {0: <17} | {1: <39} | {2: <32} | {3: <32} | {4: <10} | {5: <19} | {6: <19} |",
+            "MAC Address", "IP Address", "Hostname", "Vendor Class", "Lease (s)", "Last Message", "Last Seen")?;
+        for (mac, host) in &self.hosts {
+            writeln!(writer, "| {0: <17} | {1: <39} | {2: <32} | {3: <32} | {4: <10} | {5: <19} | {6: <19} |",
+                mac,
+                host.ip.as_deref().unwrap_or("-"),
+                host.hostname.as_deref().unwrap_or("-"),
+                host.vendor_class.as_deref().unwrap_or("-"),
+                host.lease_time.map_or(String::from("-"), |l| l.to_string()),
+                host.last_message,
+                host.last_seen.format("%Y/%m/%d %H:%M:%S"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e];
+
+    /// DHCP message from the client with MAC, assigning `yiaddr`, followed by `options` and the end option
+    fn dhcpv4(op: u8, yiaddr: [u8; 4], options: &[u8]) -> Vec<u8> {
+        let mut data = vec![op, 1, 6, 0];
+        data.extend_from_slice(&[0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&yiaddr);
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&MAC);
+        data.extend_from_slice(&[0; 10 + 64 + 128]);
+        data.extend_from_slice(&DHCP_MAGIC_COOKIE.to_be_bytes());
+        data.extend_from_slice(options);
+        data.push(255);
+        data
+    }
+
+    /// DHCPv6 option
+    fn option(code: u16, value: &[u8]) -> Vec<u8> {
+        [&code.to_be_bytes()[..], &(value.len() as u16).to_be_bytes(), value].concat()
+    }
+
+    #[test]
+    fn dhcpv4_ack() {
+        let options = [&[53, 1, 5, 51, 4, 0, 0, 0x0e, 0x10, 0, 12, 6][..], b"laptop", &[60, 8], b"MSFT 5.0"].concat();
+        let msg = parse_dhcpv4(&dhcpv4(2, [192, 168, 1, 50], &options)).unwrap();
+        assert_eq!(msg.kind, "ACK");
+        assert!(msg.from_server);
+        assert_eq!(msg.client_mac.as_deref(), Some("00:1a:2b:3c:4d:5e"));
+        assert_eq!(msg.address.as_deref(), Some("192.168.1.50"));
+        assert_eq!(msg.lease_time, Some(3600));
+        assert_eq!(msg.hostname.as_deref(), Some("laptop"));
+        assert_eq!(msg.vendor_class.as_deref(), Some("MSFT 5.0"));
+        assert_eq!(msg.summary(), "ACK 192.168.1.50 (laptop)");
+    }
+
+    #[test]
+    fn dhcpv4_request() {
+        //requested address and client FQDN in DNS encoding
+        let options = [&[53, 1, 3, 50, 4, 10, 0, 0, 7, 81, 16, 0x05, 0, 0, 3][..], b"pc1", &[7], b"example", &[0]].concat();
+        let msg = parse_dhcpv4(&dhcpv4(1, [0; 4], &options)).unwrap();
+        assert_eq!(msg.kind, "REQUEST");
+        assert!(!msg.from_server);
+        assert_eq!(msg.address.as_deref(), Some("10.0.0.7"));
+        assert_eq!(msg.hostname.as_deref(), Some("pc1.example"));
+    }
+
+    #[test]
+    fn bootp_and_invalid() {
+        let msg = parse_dhcpv4(&dhcpv4(1, [0; 4], &[])).unwrap();
+        assert_eq!(msg.kind, "BOOTREQUEST");
+        let mut data = dhcpv4(1, [0; 4], &[]);
+        data[236] = 0;
+        assert!(parse_dhcpv4(&data).is_none());
+        assert!(parse_dhcpv4(&data[..100]).is_none());
+    }
+
+    #[test]
+    fn dhcpv6_reply() {
+        let mut iaaddr = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x42];
+        iaaddr.extend_from_slice(&[0, 0, 0x0e, 0x10, 0, 0, 0x1c, 0x20]);
+        let ia_na = [&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0][..], &option(5, &iaaddr)].concat();
+        let data = [
+            &[7, 0xaa, 0xbb, 0xcc][..],
+            &option(1, &[&[0, 3, 0, 1][..], &MAC].concat()),
+            &option(3, &ia_na),
+            &option(39, &[&[0x01, 4][..], b"host", &[0]].concat()),
+            &option(16, &[&[0, 0, 0x01, 0x37, 0, 4][..], b"dhcp"].concat()),
+        ].concat();
+        let msg = parse_dhcpv6(&data).unwrap();
+        assert_eq!(msg.kind, "REPLY");
+        assert!(msg.from_server);
+        assert_eq!(msg.client_mac.as_deref(), Some("00:1a:2b:3c:4d:5e"));
+        // formatted like the addresses of the connections
+        assert_eq!(msg.address.as_deref(), Some("2001:0db8:0000:0000:0000:0000:0000:0042"));
+        assert_eq!(msg.lease_time, Some(7200));
+        assert_eq!(msg.hostname.as_deref(), Some("host"));
+        assert_eq!(msg.vendor_class.as_deref(), Some("dhcp"));
+    }
+
+    #[test]
+    fn dhcpv6_relayed() {
+        //DUID-LLT: type, hardware type, time, address
+        let solicit = [&[1, 0, 0, 1][..], &option(1, &[&[0, 1, 0, 1, 0x2a, 0x2b, 0x2c, 0x2d][..], &MAC].concat())].concat();
+        let relay = [&[12, 0][..], &[0; 32], &option(9, &solicit)].concat();
+        let msg = parse_dhcpv6(&relay).unwrap();
+        assert_eq!(msg.kind, "SOLICIT");
+        assert_eq!(msg.client_mac.as_deref(), Some("00:1a:2b:3c:4d:5e"));
+        assert!(parse_dhcpv6(&[42, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn inventory() {
+        let mut hosts = HostInventory::default();
+        let request = DhcpMessage {
+            kind: String::from("REQUEST"),
+            address: Some(String::from("10.0.0.7")),
+            hostname: Some(String::from("pc1")),
+            ..Default::default()
+        };
+        hosts.observe(&request, Some(String::from("aa:aa:aa:aa:aa:aa")), Local::now());
+        //only the server assigns the address
+        assert_eq!(hosts.hosts["aa:aa:aa:aa:aa:aa"].ip, None);
+        let ack = DhcpMessage {
+            kind: String::from("ACK"),
+            client_mac: Some(String::from("aa:aa:aa:aa:aa:aa")),
+            address: Some(String::from("10.0.0.7")),
+            lease_time: Some(600),
+            ..Default::default()
+        };
+        hosts.observe(&ack, None, Local::now());
+        let host = &hosts.hosts["aa:aa:aa:aa:aa:aa"];
+        assert_eq!(host.ip.as_deref(), Some("10.0.0.7"));
+        assert_eq!(host.hostname.as_deref(), Some("pc1"));
+        assert_eq!(host.last_message, "ACK");
+        //without a MAC address there is no host to update
+        hosts.observe(&DhcpMessage::default(), None, Local::now());
+        assert_eq!(hosts.hosts.len(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+//! Service discovery decoding (mDNS/DNS-SD and SSDP) and the per-host catalogue built from it.
+use super::dns::{self, RData};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+/// Service type announced through DNS-SD service type enumeration, not a real service
+const SERVICE_ENUMERATION: &str = "_services._dns-sd._udp.local";
+/// Longest TXT/SERVER text kept for each service
+const MAX_INFO_LEN: usize = 120;
+
+/// This struct describes a service announced by a host
+/// ## Properties
+/// * `protocol`: Discovery protocol the service was learnt from (`mDNS` or `SSDP`)
+/// * `service_type`: DNS-SD service type (`_ipp._tcp.local`) or SSDP notification type
+/// * `instance`: DNS-SD instance name or SSDP unique service name
+/// * `endpoint`: `target:port` for DNS-SD, description URL for SSDP
+/// * `info`: TXT record strings for DNS-SD, SERVER header for SSDP
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServiceEntry {
+    pub protocol: String,
+    pub service_type: String,
+    pub instance: String,
+    pub endpoint: String,
+    pub info: String,
+}
+
+/// Services and names announced by a single host
+#[derive(Clone, Debug, Default)]
+struct HostServices {
+    names: BTreeSet<String>,
+    services: BTreeMap<(String, String), ServiceEntry>,
+}
+
+/// This struct keeps the services discovered on the segment, grouped by announcing host
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ServiceCatalogue {
+    hosts: BTreeMap<String, HostServices>,
+}
+
+impl ServiceCatalogue {
+    /// Decodes an mDNS message sent by `host`, returning the details to attach to the connection
+    pub fn inspect_mdns(&mut self, host: &str, payload: &[u8]) -> Vec<String> {
+        let msg = match dns::parse_message(payload) {
+            Some(m) => m,
+            None => return vec![],
+        };
+        let mut details = vec![];
+        if !msg.response {
+            for (name, _) in &msg.questions {
+                details.push(format!("Query {}", name));
+            }
+        }
+
+        let entry = self.hosts.entry(host.to_string()).or_default();
+        for record in &msg.records {
+            match &record.data {
+                RData::Ptr(instance) if is_service_type(&record.name) => {
+                    let service = service(entry, "mDNS", instance);
+                    service.service_type = record.name.clone();
+                    details.push(format!("Service {}", instance));
+                }
+                RData::Srv { port, target } => {
+                    let service = service(entry, "mDNS", &record.name);
+                    service.endpoint = format!("{}:{}", target, port);
+                    entry.names.insert(target.clone());
+                }
+                RData::Txt(strings) if instance_type(&record.name).is_some() => {
+                    let service = service(entry, "mDNS", &record.name);
+                    service.info = truncate(&strings.join(" "));
+                }
+                RData::A(_) | RData::Aaaa(_) => {
+                    entry.names.insert(record.name.clone());
+                }
+                _ => (),
+            }
+        }
+        details
+    }
+
+    /// Decodes an SSDP message sent by `host`, returning the details to attach to the connection
+    pub fn inspect_ssdp(&mut self, host: &str, payload: &[u8]) -> Vec<String> {
+        let text = String::from_utf8_lossy(payload);
+        let mut lines = text.split("\r\n");
+        let start = lines.next().unwrap_or("");
+        let mut headers: BTreeMap<String, String> = BTreeMap::new();
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_ascii_uppercase(), value.trim().to_string());
+            }
+        }
+        let header = |name: &str| headers.get(name).cloned().unwrap_or_default();
+
+        if start.starts_with("M-SEARCH") {
+            return vec![format!("M-SEARCH {}", header("ST"))];
+        }
+        // NOTIFY announcements carry NT, search responses carry ST
+        let service_type = if start.starts_with("NOTIFY") {
+            header("NT")
+        } else if start.starts_with("HTTP/") {
+            header("ST")
+        } else {
+            return vec![];
+        };
+        let usn = header("USN");
+        if usn.is_empty() && service_type.is_empty() {
+            return vec![];
+        }
+        let entry = self.hosts.entry(host.to_string()).or_default();
+        let service = entry
+            .services
+            .entry((String::from("SSDP"), usn.clone()))
+            .or_insert_with(|| ServiceEntry {
+                protocol: String::from("SSDP"),
+                instance: usn.clone(),
+                ..Default::default()
+            });
+        service.service_type = service_type.clone();
+        if !header("LOCATION").is_empty() {
+            service.endpoint = header("LOCATION");
+        }
+        if !header("SERVER").is_empty() {
+            service.info = truncate(&header("SERVER"));
+        }
+        let nts = header("NTS");
+        if nts == "ssdp:byebye" {
+            service.info = truncate(&format!("{} (byebye)", service.info));
+        }
+        vec![format!("{} {}", start.split_whitespace().next().unwrap_or(""), service_type)]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.values().all(|h| h.services.is_empty())
+    }
+
+    /// Writes the catalogue as a table, in the same layout as the connection report
+    pub fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "\n Discovered services (mDNS/SSDP)\n")?;
+        writeln!(writer, "| {0: <39} | {1: <32} | {2: <8} | {3: <40} | {4: <48} | {5: <40} | Info",
+            "Host", "Names", "Protocol", "Service Type", "Instance", "Endpoint")?;
+        for (host, services) in &self.hosts {
+            let names = services.names.iter().cloned().collect::<Vec<_>>().join(",");
+            for service in services.services.values() {
+                writeln!(writer, "| {0: <39} | {1: <32} | {2: <8} | {3: <40} | {4: <48} | {5: <40} | {6}",
+                    host, names, service.protocol, service.service_type, service.instance, service.endpoint, service.info)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn service<'a>(entry: &'a mut HostServices, protocol: &str, instance: &str) -> &'a mut ServiceEntry {
+    entry
+        .services
+        .entry((protocol.to_string(), instance.to_string()))
+        .or_insert_with(|| ServiceEntry {
+            protocol: protocol.to_string(),
+            service_type: instance_type(instance).unwrap_or_default(),
+            instance: instance.to_string(),
+            ..Default::default()
+        })
+}
+
+/// Tells whether `name` is a DNS-SD service type such as `_ipp._tcp.local`
+fn is_service_type(name: &str) -> bool {
+    name != SERVICE_ENUMERATION
+        && name.starts_with('_')
+        && (name.contains("._tcp.") || name.contains("._udp."))
+}
+
+/// Returns the service type of an instance name, e.g. `_ipp._tcp.local` for `Printer._ipp._tcp.local`
+fn instance_type(instance: &str) -> Option<String> {
+    let pos = instance.find("._")?;
+    let service_type = &instance[pos + 1..];
+    if is_service_type(service_type) {
+        Some(service_type.to_string())
+    } else {
+        None
+    }
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_INFO_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::dns::tests::{message, name, record};
+    use super::super::dns::{TYPE_A, TYPE_PTR, TYPE_SRV, TYPE_TXT};
+
+    fn services(catalogue: &ServiceCatalogue, host: &str) -> Vec<ServiceEntry> {
+        catalogue.hosts[host].services.values().cloned().collect()
+    }
+
+    #[test]
+    fn mdns_announcement() {
+        let instance = "Printer._ipp._tcp.local";
+        let mut srv = vec![0, 0, 0, 0, 0x02, 0x77];
+        srv.extend(name("printer.local"));
+        let answers = [
+            record(&name("_services._dns-sd._udp.local"), TYPE_PTR, &name("_ipp._tcp.local")),
+            record(&name("_ipp._tcp.local"), TYPE_PTR, &name(instance)),
+            record(&name(instance), TYPE_SRV, &srv),
+            record(&name(instance), TYPE_TXT, b"\x06rp=ipp\x06ty=Lab"),
+            record(&name("printer.local"), TYPE_A, &[192, 168, 1, 20]),
+        ];
+        let mut catalogue = ServiceCatalogue::default();
+        assert!(catalogue.is_empty());
+        let details = catalogue.inspect_mdns("192.168.1.20", &message(true, &[], &answers));
+        assert_eq!(details, vec![format!("Service {}", instance)]);
+        let found = services(&catalogue, "192.168.1.20");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].protocol, "mDNS");
+        assert_eq!(found[0].service_type, "_ipp._tcp.local");
+        assert_eq!(found[0].instance, instance);
+        assert_eq!(found[0].endpoint, "printer.local:631");
+        assert_eq!(found[0].info, "rp=ipp ty=Lab");
+        assert!(catalogue.hosts["192.168.1.20"].names.contains("printer.local"));
+        assert!(!catalogue.is_empty());
+
+        let mut out = vec![];
+        catalogue.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Discovered services"));
+        assert!(out.contains("printer.local:631"));
+    }
+
+    #[test]
+    fn mdns_query() {
+        let mut catalogue = ServiceCatalogue::default();
+        let details = catalogue.inspect_mdns("10.0.0.1", &message(false, &[("_airplay._tcp.local", TYPE_PTR)], &[]));
+        assert_eq!(details, vec![String::from("Query _airplay._tcp.local")]);
+        assert!(catalogue.is_empty());
+        assert!(catalogue.inspect_mdns("10.0.0.1", b"not dns").is_empty());
+    }
+
+    #[test]
+    fn ssdp() {
+        let mut catalogue = ServiceCatalogue::default();
+        let search = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nST: ssdp:all\r\n\r\n";
+        assert_eq!(catalogue.inspect_ssdp("10.0.0.2", search), vec![String::from("M-SEARCH ssdp:all")]);
+        assert!(catalogue.is_empty());
+
+        let notify = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\nUSN: uuid:1::upnp:rootdevice\r\n\
+            Location: http://10.0.0.3:80/desc.xml\r\nServer: Linux UPnP/1.0\r\n\r\n";
+        assert_eq!(catalogue.inspect_ssdp("10.0.0.3", notify), vec![String::from("NOTIFY upnp:rootdevice")]);
+        let found = services(&catalogue, "10.0.0.3");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].protocol, "SSDP");
+        assert_eq!(found[0].service_type, "upnp:rootdevice");
+        assert_eq!(found[0].instance, "uuid:1::upnp:rootdevice");
+        assert_eq!(found[0].endpoint, "http://10.0.0.3:80/desc.xml");
+        assert_eq!(found[0].info, "Linux UPnP/1.0");
+
+        // the same service is updated in place
+        let byebye = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:byebye\r\nUSN: uuid:1::upnp:rootdevice\r\n\r\n";
+        catalogue.inspect_ssdp("10.0.0.3", byebye);
+        let found = services(&catalogue, "10.0.0.3");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].info, "Linux UPnP/1.0 (byebye)");
+
+        assert!(catalogue.inspect_ssdp("10.0.0.4", b"GET / HTTP/1.1\r\n\r\n").is_empty());
+    }
+
+    #[test]
+    fn helpers() {
+        assert!(is_service_type("_ipp._tcp.local"));
+        assert!(is_service_type("_sonos._udp.local"));
+        assert!(!is_service_type(SERVICE_ENUMERATION));
+        assert!(!is_service_type("printer.local"));
+        assert_eq!(instance_type("My Printer._ipp._tcp.local"), Some(String::from("_ipp._tcp.local")));
+        assert_eq!(instance_type("printer.local"), None);
+        assert_eq!(truncate(&"x".repeat(200)).len(), MAX_INFO_LEN);
+    }
+}
@@ -0,0 +1,249 @@
+//! DNS message decoding (RFC 1035), shared by the DNS based protocols such as mDNS.
+use super::reader::Reader;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_PTR: u16 = 12;
+pub(crate) const TYPE_TXT: u16 = 16;
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_SRV: u16 = 33;
+
+/// Maximum number of compression pointers followed while decoding a name
+const MAX_POINTERS: usize = 16;
+
+/// This enum provides the record data the sniffer understands
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv { port: u16, target: String },
+    Txt(Vec<String>),
+    Other,
+}
+
+/// This struct contains a resource record of any section of the message
+#[derive(Clone, Debug)]
+pub(crate) struct DnsRecord {
+    pub name: String,
+    pub data: RData,
+}
+
+/// This struct contains a decoded DNS message
+/// ## Properties
+/// * `response`: QR flag
+/// * `questions`: Names and types asked in the question section
+/// * `records`: Answer, authority and additional records
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DnsMessage {
+    pub response: bool,
+    pub questions: Vec<(String, u16)>,
+    pub records: Vec<DnsRecord>,
+}
+
+/// Decodes a DNS message, returning `None` if it is malformed
+pub(crate) fn parse_message(data: &[u8]) -> Option<DnsMessage> {
+    let mut r = Reader::new(data);
+    // id
+    r.skip(2)?;
+    let flags = r.u16()?;
+    let qdcount = r.u16()?;
+    let ancount = r.u16()? as usize;
+    let nscount = r.u16()? as usize;
+    let arcount = r.u16()? as usize;
+
+    let mut msg = DnsMessage {
+        response: flags & 0x8000 != 0,
+        ..Default::default()
+    };
+    let mut pos = r.position();
+    for _ in 0..qdcount {
+        let (name, next) = read_name(data, pos)?;
+        let mut q = Reader::new(&data[next..]);
+        let qtype = q.u16()?;
+        q.skip(2)?;
+        msg.questions.push((name, qtype));
+        pos = next + 4;
+    }
+    for _ in 0..ancount + nscount + arcount {
+        let (name, next) = read_name(data, pos)?;
+        let mut rr = Reader::new(&data[next..]);
+        let rtype = rr.u16()?;
+        // class, ttl
+        rr.skip(2 + 4)?;
+        let rdlen = rr.u16()? as usize;
+        let rdata_pos = next + rr.position();
+        rr.skip(rdlen)?;
+        let rdata = &data[rdata_pos..rdata_pos + rdlen];
+        let parsed = match rtype {
+            TYPE_A if rdlen == 4 => RData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            TYPE_AAAA if rdlen == 16 => {
+                let mut a = [0u8; 16];
+                a.copy_from_slice(rdata);
+                RData::Aaaa(Ipv6Addr::from(a))
+            }
+            TYPE_PTR => RData::Ptr(read_name(data, rdata_pos)?.0),
+            // priority, weight, port, target
+            TYPE_SRV if rdlen > 6 => RData::Srv {
+                port: u16::from_be_bytes([rdata[4], rdata[5]]),
+                target: read_name(data, rdata_pos + 6)?.0,
+            },
+            TYPE_TXT => RData::Txt(read_txt(rdata)),
+            _ => RData::Other,
+        };
+        msg.records.push(DnsRecord { name, data: parsed });
+        pos = rdata_pos + rdlen;
+    }
+    Some(msg)
+}
+
+/// Decodes the (possibly compressed) name starting at `pos`.
+/// Returns the name and the position following it
+pub(crate) fn read_name(data: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *data.get(pos)? as usize;
+        match len & 0xc0 {
+            0x00 => {
+                if len == 0 {
+                    pos += 1;
+                    break;
+                }
+                let label = data.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let target = ((len & 0x3f) << 8) | *data.get(pos + 1)? as usize;
+                if end.is_none() {
+                    end = Some(pos + 2);
+                }
+                pos = target;
+            }
+            _ => return None,
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(pos)))
+}
+
+fn read_txt(mut rdata: &[u8]) -> Vec<String> {
+    let mut strings = vec![];
+    while let Some((&len, rest)) = rdata.split_first() {
+        let len = (len as usize).min(rest.len());
+        if len > 0 {
+            strings.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+        }
+        rdata = &rest[len..];
+    }
+    strings
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encodes `name` as uncompressed labels
+    pub(crate) fn name(name: &str) -> Vec<u8> {
+        let mut out = vec![];
+        for label in name.split('.').filter(|l| !l.is_empty()) {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    /// Encodes a resource record of class IN
+    pub(crate) fn record(owner: &[u8], rtype: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut out = owner.to_vec();
+        out.extend_from_slice(&rtype.to_be_bytes());
+        out.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(rdata);
+        out
+    }
+
+    /// Encodes a message with the given questions and answer records
+    pub(crate) fn message(response: bool, questions: &[(&str, u16)], answers: &[Vec<u8>]) -> Vec<u8> {
+        let flags: u16 = if response { 0x8400 } else { 0 };
+        let mut out = vec![0, 0];
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        for (qname, qtype) in questions {
+            out.extend(name(qname));
+            out.extend_from_slice(&qtype.to_be_bytes());
+            out.extend_from_slice(&[0, 1]);
+        }
+        for answer in answers {
+            out.extend_from_slice(answer);
+        }
+        out
+    }
+
+    #[test]
+    fn question() {
+        let msg = parse_message(&message(false, &[("example.com", TYPE_A), ("example.com", TYPE_AAAA)], &[])).unwrap();
+        assert!(!msg.response);
+        assert_eq!(msg.questions, vec![(String::from("example.com"), TYPE_A), (String::from("example.com"), TYPE_AAAA)]);
+        assert!(msg.records.is_empty());
+    }
+
+    #[test]
+    fn records() {
+        // the question name starts at offset 12, the answers point back to it
+        let pointer = [0xc0, 12];
+        let mut srv = vec![0, 0, 0, 0, 0x1f, 0x90];
+        srv.extend(name("host.local"));
+        let answers = [
+            record(&pointer, TYPE_A, &[192, 168, 1, 10]),
+            record(&pointer, TYPE_AAAA, &Ipv6Addr::LOCALHOST.octets()),
+            record(&pointer, TYPE_PTR, &pointer),
+            record(&pointer, TYPE_SRV, &srv),
+            record(&pointer, TYPE_TXT, b"\x05a=one\x00\x05b=two"),
+            record(&pointer, 99, &[1, 2, 3]),
+        ];
+        let msg = parse_message(&message(true, &[("printer.local", TYPE_A)], &answers)).unwrap();
+        assert!(msg.response);
+        let data: Vec<RData> = msg.records.iter().map(|r| r.data.clone()).collect();
+        assert_eq!(data, vec![
+            RData::A(Ipv4Addr::new(192, 168, 1, 10)),
+            RData::Aaaa(Ipv6Addr::LOCALHOST),
+            RData::Ptr(String::from("printer.local")),
+            RData::Srv { port: 8080, target: String::from("host.local") },
+            RData::Txt(vec![String::from("a=one"), String::from("b=two")]),
+            RData::Other,
+        ]);
+        assert!(msg.records.iter().all(|r| r.name == "printer.local"));
+    }
+
+    #[test]
+    fn compressed_names() {
+        // "www" followed by a pointer to "example.com" at offset 0
+        let mut data = name("example.com");
+        data.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 0]);
+        assert_eq!(read_name(&data, 0), Some((String::from("example.com"), 13)));
+        assert_eq!(read_name(&data, 13), Some((String::from("www.example.com"), 19)));
+        // a pointer to itself
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+        // reserved label type
+        assert_eq!(read_name(&[0x40, 0], 0), None);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse_message(&[0; 11]).is_none());
+        let mut data = message(true, &[], &[record(&[0], TYPE_A, &[10, 0, 0, 1])]);
+        data.truncate(data.len() - 1);
+        assert!(parse_message(&data).is_none());
+        // label running past the end
+        assert!(parse_message(&message(false, &[("a", TYPE_A)], &[])[..14]).is_none());
+    }
+}
@@ -0,0 +1,183 @@
+//! Embedded HTTP server on localhost: a REST API over the control requests and a static dashboard polling it.
+//!
+//! * `GET /`: the dashboard, a single self-contained page
+//! * `GET /connections`: the connection table, in the same JSON form as the JSON report
+//! * `GET /stats`: device, filter, counters and time left
+//! * `GET /metrics`: the metrics in the Prometheus text format
+//! * `POST /control/pause`, `/control/resume`, `/control/stop`, `/control/snapshot`
+//! * `POST /control/filter`: replaces the capture filter with the one of the body, `{"filter":"tcp port 80"}`
+//!
+//! Binding to localhost does not keep the web pages the operator visits away: every request must name the server
+//! in its `Host` header (against DNS rebinding) and come from no other `Origin`, and the control requests must be
+//! sent as `application/json`, which a cross-origin page cannot do without a preflight the server never allows.
+use super::requests::{self, Query, Request};
+use super::SyncStruct;
+use serde_json::Value;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request as HttpRequest, Response, Server};
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// This struct is the HTTP server of a capture, it stops when it is dropped
+pub(crate) struct HttpServer {
+    server: Arc<Server>,
+}
+
+impl HttpServer {
+    /// Listens on `127.0.0.1:port`, serving the requests on its own thread
+    /// ## Parameters
+    /// * `port`: TCP port
+    /// * `waiter`: Synchronization variable of the capture, used for pause, resume and stop
+    /// * `queries`: Channel towards the capture loop, for the other requests
+    pub fn start(port: u16, waiter: Arc<SyncStruct>, queries: Sender<Query>) -> std::io::Result<Self> {
+        let server = Server::http(("127.0.0.1", port)).map_err(std::io::Error::other)?;
+        let server = Arc::new(server);
+        let s = Arc::clone(&server);
+        thread::spawn(move || {
+            for mut request in s.incoming_requests() {
+                if let Err(e) = check(&request, port) {
+                    let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+                    let _ = request.respond(Response::from_string(requests::error(e).to_string()).with_status_code(403).with_header(json));
+                    continue;
+                }
+                let route = match (request.method(), request.url()) {
+                    (Method::Get, "/" | "/index.html") => {
+                        let html = Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+                        let _ = request.respond(Response::from_string(DASHBOARD).with_header(html));
+                        continue;
+                    },
+                    (Method::Get, "/connections") => Some(Request::Connections),
+                    (Method::Get, "/stats") => Some(Request::Status),
+                    (Method::Get, "/metrics") => {
+                        let reply = requests::answer(Request::Metrics, &waiter, &queries);
+                        let text = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+                        let response = match reply["metrics"].as_str() {
+                            Some(metrics) => Response::from_string(metrics).with_header(text),
+                            None => Response::from_string(reply.to_string()).with_status_code(503),
+                        };
+                        let _ = request.respond(response);
+                        continue;
+                    },
+                    (Method::Post, "/control/pause") => Some(Request::Pause),
+                    (Method::Post, "/control/resume") => Some(Request::Resume),
+                    (Method::Post, "/control/stop") => Some(Request::Stop),
+                    (Method::Post, "/control/snapshot") => Some(Request::Snapshot { file: None }),
+                    (Method::Post, "/control/filter") => {
+                        let mut body = String::new();
+                        let _ = request.as_reader().read_to_string(&mut body);
+                        let filter = serde_json::from_str::<Value>(&body).ok()
+                            .and_then(|v| v["filter"].as_str().map(|f| f.trim().to_string()));
+                        Some(Request::Filter { filter })
+                    },
+                    _ => None,
+                };
+                let (status, reply) = match route {
+                    Some(r) => {
+                        let reply = requests::answer(r, &waiter, &queries);
+                        (if reply["ok"] == Value::Bool(true) { 200 } else { 400 }, reply)
+                    },
+                    None => (404, requests::error("Not found")),
+                };
+                let json = Header::from_bytes("Content-Type", "application/json").unwrap();
+                let _ = request.respond(Response::from_string(reply.to_string()).with_status_code(status).with_header(json));
+            }
+        });
+        Ok(HttpServer { server })
+    }
+}
+
+/// Accepts only the requests addressed to this server by itself or by a client outside the browser:
+/// `Host` must be the server, `Origin`, if any, must be the server too, and the control requests must be JSON
+fn check(request: &HttpRequest, port: u16) -> Result<(), &'static str> {
+    let header = |name: &'static str| request.headers().iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_ascii_lowercase());
+    let hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !header("Host").is_some_and(|h| hosts.contains(&h)) {
+        return Err("Invalid Host header");
+    }
+    if let Some(origin) = header("Origin") {
+        if !hosts.iter().any(|h| origin == format!("http://{}", h)) {
+            return Err("Cross-origin requests are not allowed");
+        }
+    }
+    let json = header("Content-Type").is_some_and(|t| t.split(';').next().is_some_and(|t| t.trim() == "application/json"));
+    if request.url().starts_with("/control/") && !json {
+        return Err("Control requests must be sent as application/json");
+    }
+    Ok(())
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::requests::tests::{capture_loop, waiter};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    fn server() -> (HttpServer, u16) {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        (HttpServer::start(port, waiter(), capture_loop()).unwrap(), port)
+    }
+
+    /// Sends a request with `headers` (`Host` excluded) and returns the status code and the body
+    fn request(port: u16, method: &str, url: &str, host: &str, headers: &[&str], body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut text = format!("{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n", method, url, host, body.len());
+        for h in headers {
+            text += &format!("{}\r\n", h);
+        }
+        text += &format!("\r\n{}", body);
+        stream.write_all(text.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").map(|(_, b)| b.to_string()).unwrap_or_default();
+        (status, body)
+    }
+
+    #[test]
+    fn routes() {
+        let (_server, port) = server();
+        let host = format!("127.0.0.1:{}", port);
+        let (status, body) = request(port, "GET", "/", &host, &[], "");
+        assert_eq!(status, 200);
+        assert_eq!(body, DASHBOARD);
+        assert_eq!(request(port, "GET", "/stats", &host, &[], ""), (200, String::from(r#"{"ok":true,"request":"Status"}"#)));
+        let json = ["Content-Type: application/json; charset=utf-8"];
+        assert_eq!(request(port, "POST", "/control/pause", &host, &json, "").0, 200);
+        // already paused
+        assert_eq!(request(port, "POST", "/control/pause", &host, &json, "").0, 400);
+        let (status, body) = request(port, "POST", "/control/filter", &host, &json, r#"{"filter":" udp "}"#);
+        assert_eq!(status, 200);
+        assert!(body.contains(r#"Some(\"udp\")"#));
+        assert_eq!(request(port, "GET", "/control/pause", &host, &json, "").0, 404);
+        // the capture loop of the test has no metrics to return
+        assert_eq!(request(port, "GET", "/metrics", &host, &[], "").0, 503);
+    }
+
+    #[test]
+    fn rejected_requests() {
+        let (_server, port) = server();
+        let host = format!("localhost:{}", port);
+        let json = "Content-Type: application/json";
+        let rejected = |host: &str, headers: &[&str]| {
+            let (status, body) = request(port, "POST", "/control/stop", host, headers, "");
+            status == 403 && body.contains(r#""ok":false"#)
+        };
+        assert!(rejected("attacker.example", &[json]));
+        assert!(rejected(&format!("127.0.0.1:{}", port + 1), &[json]));
+        assert!(rejected(&host, &[json, "Origin: http://attacker.example"]));
+        assert!(rejected(&host, &["Content-Type: text/plain"]));
+        assert!(rejected(&host, &[]));
+        assert_eq!(request(port, "POST", "/control/resume", &host, &[json, &format!("Origin: http://{}", host)], "").0, 400);
+    }
+}
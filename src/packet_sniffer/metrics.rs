@@ -0,0 +1,165 @@
+//! Prometheus metrics of the capture, in the text exposition format.
+use super::Connection;
+use chrono::{Duration, Local};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Most label values exported for a label, the others are summed up as "other"
+pub(crate) const MAX_LABELS: usize = 50;
+/// A connection is active if it carried a packet in the last `ACTIVE_SECS` seconds
+const ACTIVE_SECS: i64 = 60;
+
+/// This struct holds the capture counters that are not derived from the connections
+/// ## Properties
+/// * `packets`, `bytes`: Packets captured and their length
+/// * `parse_errors`: Packets whose headers could not be parsed
+/// * `non_ip`: Frames not carrying an IP packet
+/// * `skipped_transports`: IP packets not carrying TCP or UDP (e.g. ICMP), or fragments
+/// * `received`, `dropped`, `if_dropped`: Statistics of pcap: packets received, dropped by the kernel and by the interface
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct Counters {
+    pub packets: u64,
+    pub bytes: u64,
+    pub parse_errors: u64,
+    pub non_ip: u64,
+    pub skipped_transports: u64,
+    pub received: u64,
+    pub dropped: u64,
+    pub if_dropped: u64,
+}
+
+impl Counters {
+    /// Percentage of the packets received by pcap that were dropped by the kernel or by the interface,
+    /// `None` until pcap reports any
+    pub fn drop_rate(&self) -> Option<f64> {
+        if self.received == 0 {
+            return None;
+        }
+        Some((self.dropped + self.if_dropped) as f64 * 100.0 / self.received as f64)
+    }
+}
+
+/// Renders the metrics
+pub(crate) fn render(counters: &Counters, connections: &[Connection]) -> String {
+    let mut out = String::new();
+    let mut counter = |name: &str, help: &str, kind: &str, value: u64| {
+        let _ = writeln!(out, "# HELP packet_sniffer_{} {}\n# TYPE packet_sniffer_{} {}\npacket_sniffer_{} {}", name, help, name, kind, name, value);
+    };
+    counter("packets_total", "Packets captured", "counter", counters.packets);
+    counter("bytes_total", "Bytes captured", "counter", counters.bytes);
+    counter("parse_errors_total", "Packets whose headers could not be parsed", "counter", counters.parse_errors);
+    counter("non_ip_total", "Frames not carrying an IP packet", "counter", counters.non_ip);
+    counter("skipped_transports_total", "IP packets not carrying TCP or UDP", "counter", counters.skipped_transports);
+    counter("pcap_received_total", "Packets received by pcap", "counter", counters.received);
+    counter("pcap_dropped_total", "Packets dropped by the kernel because of full buffers", "counter", counters.dropped);
+    counter("pcap_if_dropped_total", "Packets dropped by the network interface", "counter", counters.if_dropped);
+    let since = Local::now() - Duration::seconds(ACTIVE_SECS);
+    counter("connections_active", "Connections with a packet in the last minute", "gauge",
+        connections.iter().filter(|c| c.ts_end >= since).count() as u64);
+    counter("connections_total", "Connections seen", "counter", connections.len() as u64);
+
+    let mut app_prots: HashMap<String, u64> = HashMap::new();
+    let mut transports: HashMap<String, u64> = HashMap::new();
+    for con in connections {
+        let bytes = con.bytes_a_to_b as u64 + con.bytes_b_to_a as u64;
+        // "other" is left to the protocols beyond the label limit
+        let prot = if con.app_prot.is_empty() { String::from("unknown") } else { con.app_prot.clone() };
+        *app_prots.entry(prot).or_default() += bytes;
+        *transports.entry(con.l4.to_string()).or_default() += bytes;
+    }
+    labelled(&mut out, "app_prot_bytes_total", "Bytes of the connections by application protocol", "app_prot", app_prots);
+    labelled(&mut out, "transport_bytes_total", "Bytes of the connections by transport protocol", "transport", transports);
+    out
+}
+
+/// Renders a counter with a label, keeping the `MAX_LABELS` values with the most bytes
+fn labelled(out: &mut String, name: &str, help: &str, label: &str, values: HashMap<String, u64>) {
+    let mut values: Vec<(String, u64)> = values.into_iter().collect();
+    values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    if values.len() > MAX_LABELS {
+        let other: u64 = values.drain(MAX_LABELS - 1..).map(|(_, v)| v).sum();
+        match values.iter_mut().find(|(k, _)| k == "other") {
+            Some((_, v)) => *v += other,
+            None => values.push((String::from("other"), other)),
+        }
+    }
+    let _ = writeln!(out, "# HELP packet_sniffer_{} {}\n# TYPE packet_sniffer_{} counter", name, help, name);
+    for (key, value) in values {
+        let key = key.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let _ = writeln!(out, "packet_sniffer_{}{{{}=\"{}\"}} {}", name, label, key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(l4: u8, bytes: u32, app_prot: &str, age: i64) -> Connection {
+        let end = Local::now() - Duration::seconds(age);
+        Connection::new(4, String::from("10.0.0.1"), String::from("10.0.0.2"), l4, String::from("80"), String::from("40000"),
+            end, end, bytes, String::from(app_prot))
+    }
+
+    /// Value of the sample line `sample`
+    fn value(out: &str, sample: &str) -> Option<u64> {
+        out.lines().find_map(|l| l.strip_prefix(sample)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    #[test]
+    fn counters() {
+        let counters = Counters { packets: 10, bytes: 1500, parse_errors: 1, non_ip: 2, skipped_transports: 3, received: 12, dropped: 4,
+            if_dropped: 5 };
+        let out = render(&counters, &[]);
+        assert!(out.starts_with("# HELP packet_sniffer_packets_total Packets captured\n# TYPE packet_sniffer_packets_total counter\n\
+            packet_sniffer_packets_total 10\n"));
+        for (sample, expected) in [("packets_total", 10), ("bytes_total", 1500), ("parse_errors_total", 1), ("non_ip_total", 2),
+            ("skipped_transports_total", 3), ("pcap_received_total", 12), ("pcap_dropped_total", 4), ("pcap_if_dropped_total", 5),
+            ("connections_active", 0), ("connections_total", 0)] {
+            assert_eq!(value(&out, &format!("packet_sniffer_{}", sample)), Some(expected), "{}", sample);
+        }
+        assert!(out.contains("# TYPE packet_sniffer_connections_active gauge\n"));
+    }
+
+    #[test]
+    fn drop_rate() {
+        assert_eq!(Counters::default().drop_rate(), None);
+        let counters = Counters { received: 200, dropped: 3, if_dropped: 1, ..Default::default() };
+        assert_eq!(counters.drop_rate(), Some(2.0));
+        let counters = Counters { received: 10, ..Default::default() };
+        assert_eq!(counters.drop_rate(), Some(0.0));
+    }
+
+    #[test]
+    fn connections() {
+        let connections = [
+            connection(0, 100, "HTTP", 0),
+            connection(0, 50, "HTTP", ACTIVE_SECS * 2),
+            connection(1, 30, "", 0),
+            connection(1, 5, "say \"hi\"", 0),
+        ];
+        let out = render(&Counters::default(), &connections);
+        assert_eq!(value(&out, "packet_sniffer_connections_active"), Some(3));
+        assert_eq!(value(&out, "packet_sniffer_connections_total"), Some(4));
+        assert_eq!(value(&out, "packet_sniffer_app_prot_bytes_total{app_prot=\"HTTP\"}"), Some(150));
+        assert_eq!(value(&out, "packet_sniffer_app_prot_bytes_total{app_prot=\"unknown\"}"), Some(30));
+        assert_eq!(value(&out, "packet_sniffer_app_prot_bytes_total{app_prot=\"other\"}"), None);
+        assert_eq!(value(&out, "packet_sniffer_app_prot_bytes_total{app_prot=\"say \\\"hi\\\"\"}"), Some(5));
+        assert_eq!(value(&out, "packet_sniffer_transport_bytes_total{transport=\"TCP\"}"), Some(150));
+        assert_eq!(value(&out, "packet_sniffer_transport_bytes_total{transport=\"UDP\"}"), Some(35));
+    }
+
+    #[test]
+    fn label_limit() {
+        let mut values: HashMap<String, u64> = (0..MAX_LABELS as u64 + 10).map(|i| (format!("p{}", i), 1000 - i)).collect();
+        values.insert(String::from("other"), 1);
+        let mut out = String::new();
+        labelled(&mut out, "test_total", "Test", "l", values);
+        let samples: Vec<&str> = out.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(samples.len(), MAX_LABELS);
+        assert_eq!(samples[0], "packet_sniffer_test_total{l=\"p0\"} 1000");
+        // p49 to p59 and "other"
+        let other: u64 = (MAX_LABELS as u64 - 1..MAX_LABELS as u64 + 10).map(|i| 1000 - i).sum::<u64>() + 1;
+        assert_eq!(samples[MAX_LABELS - 1], format!("packet_sniffer_test_total{{l=\"other\"}} {}", other));
+    }
+}
@@ -0,0 +1,165 @@
+//! Modbus/TCP decoding: MBAP header, function codes and exception responses.
+use super::reader::Reader;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter, Result};
+
+/// This struct summarises the Modbus traffic of a connection
+/// ## Properties
+/// * `units`: Unit identifiers addressed by the client
+/// * `functions`: Number of requests for each function code
+/// * `exceptions`: Number of exception responses for each exception code
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ModbusSummary {
+    units: BTreeSet<u8>,
+    functions: BTreeMap<u8, u32>,
+    exceptions: BTreeMap<u8, u32>,
+}
+
+impl ModbusSummary {
+    /// Returns `true` if nothing has been decoded yet
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.exceptions.is_empty()
+    }
+
+    /// Decodes the Modbus ADUs contained in a TCP payload
+    /// ## Parameters
+    /// * `from_server`: `true` if the payload was sent by the server (port 502)
+    /// * `data`: Reassembled TCP payload
+    ///
+    /// Returns the number of bytes consumed: a trailing incomplete ADU is left for the caller,
+    /// data that is not Modbus/TCP is consumed entirely
+    pub fn update(&mut self, from_server: bool, data: &[u8]) -> usize {
+        let mut payload = data;
+        while payload.len() >= 8 {
+            let mut r = Reader::new(payload);
+            let header = (r.u16(), r.u16(), r.u16(), r.u8(), r.u8());
+            let (protocol, length, unit, function) = match header {
+                (Some(_), Some(p), Some(l), Some(u), Some(f)) => (p, l as usize, u, f),
+                _ => break,
+            };
+            // the length field counts the unit identifier and the PDU
+            if protocol != 0 || length < 2 {
+                return data.len();
+            }
+            if payload.len() < 6 + length {
+                break;
+            }
+            if from_server {
+                if function & 0x80 != 0 {
+                    if let Some(code) = r.u8() {
+                        *self.exceptions.entry(code).or_insert(0) += 1;
+                    }
+                }
+            } else {
+                self.units.insert(unit);
+                *self.functions.entry(function).or_insert(0) += 1;
+            }
+            payload = &payload[6 + length..];
+        }
+        data.len() - payload.len()
+    }
+}
+
+impl Display for ModbusSummary {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let units: Vec<String> = self.units.iter().map(|u| u.to_string()).collect();
+        write!(f, "Modbus units {}", units.join(","))?;
+        let functions: Vec<String> = self.functions.iter()
+            .map(|(code, n)| format!("{} x{}", function_name(*code), n))
+            .collect();
+        if !functions.is_empty() {
+            write!(f, "; {}", functions.join(", "))?;
+        }
+        let exceptions: Vec<String> = self.exceptions.iter()
+            .map(|(code, n)| format!("{} x{}", exception_name(*code), n))
+            .collect();
+        if !exceptions.is_empty() {
+            write!(f, "; exceptions: {}", exceptions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn function_name(code: u8) -> String {
+    match code {
+        1 => String::from("Read Coils"),
+        2 => String::from("Read Discrete Inputs"),
+        3 => String::from("Read Holding Registers"),
+        4 => String::from("Read Input Registers"),
+        5 => String::from("Write Single Coil"),
+        6 => String::from("Write Single Register"),
+        7 => String::from("Read Exception Status"),
+        8 => String::from("Diagnostics"),
+        11 => String::from("Get Comm Event Counter"),
+        12 => String::from("Get Comm Event Log"),
+        15 => String::from("Write Multiple Coils"),
+        16 => String::from("Write Multiple Registers"),
+        17 => String::from("Report Server ID"),
+        20 => String::from("Read File Record"),
+        21 => String::from("Write File Record"),
+        22 => String::from("Mask Write Register"),
+        23 => String::from("Read/Write Multiple Registers"),
+        24 => String::from("Read FIFO Queue"),
+        43 => String::from("Encapsulated Interface Transport"),
+        c => format!("Function {}", c),
+    }
+}
+
+fn exception_name(code: u8) -> String {
+    match code {
+        1 => String::from("Illegal Function"),
+        2 => String::from("Illegal Data Address"),
+        3 => String::from("Illegal Data Value"),
+        4 => String::from("Server Device Failure"),
+        5 => String::from("Acknowledge"),
+        6 => String::from("Server Device Busy"),
+        8 => String::from("Memory Parity Error"),
+        10 => String::from("Gateway Path Unavailable"),
+        11 => String::from("Gateway Target Failed To Respond"),
+        c => format!("Exception {}", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read Holding Registers request for 10 registers of `unit`
+    fn read_request(unit: u8) -> Vec<u8> {
+        vec![0, 1, 0, 0, 0, 6, unit, 3, 0, 0, 0, 10]
+    }
+
+    #[test]
+    fn requests_and_exceptions() {
+        let mut summary = ModbusSummary::default();
+        assert!(summary.is_empty());
+        let mut data = read_request(1);
+        data.extend(read_request(2));
+        // the first bytes of a third request
+        data.extend_from_slice(&read_request(1)[..5]);
+        assert_eq!(summary.update(false, &data), 24);
+        // Illegal Data Address
+        assert_eq!(summary.update(true, &[0, 1, 0, 0, 0, 3, 1, 0x83, 2]), 9);
+        assert!(!summary.is_empty());
+        assert_eq!(summary.to_string(), "Modbus units 1,2; Read Holding Registers x2; exceptions: Illegal Data Address x1");
+    }
+
+    #[test]
+    fn not_modbus() {
+        let mut summary = ModbusSummary::default();
+        // non zero protocol identifier
+        assert_eq!(summary.update(false, &[0, 1, 0, 5, 0, 6, 1, 3, 0, 0, 0, 10]), 12);
+        // length not counting the function code
+        assert_eq!(summary.update(false, &[0, 1, 0, 0, 0, 1, 1, 3]), 8);
+        assert_eq!(summary.update(false, &[0, 1, 0]), 0);
+        assert!(summary.is_empty());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(function_name(16), "Write Multiple Registers");
+        assert_eq!(function_name(100), "Function 100");
+        assert_eq!(exception_name(11), "Gateway Target Failed To Respond");
+        assert_eq!(exception_name(7), "Exception 7");
+    }
+}
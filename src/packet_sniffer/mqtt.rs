@@ -0,0 +1,293 @@
+//! MQTT 3.1/3.1.1/5.0 decoding: client identifier, published and subscribed topics, QoS.
+use super::reader::Reader;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result};
+
+/// Maximum number of distinct topics kept per direction for each connection
+const MAX_TOPICS: usize = 32;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+
+/// This struct summarises an MQTT session
+/// ## Properties
+/// * `client_id`: Client identifier sent in CONNECT
+/// * `version`: Protocol level (3 = 3.1, 4 = 3.1.1, 5 = 5.0)
+/// * `username`: `true` if the client authenticated with a user name
+/// * `connack`: Return/reason code of CONNACK
+/// * `published`: Topics published by the client, with the highest QoS used and the message count
+/// * `received`: Topics delivered by the broker, with the highest QoS used and the message count
+/// * `subscribed`: Topic filters subscribed by the client, with the requested QoS
+/// * `truncated`: `true` if some topics were dropped because of the `MAX_TOPICS` limit
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MqttSummary {
+    client_id: Option<String>,
+    version: u8,
+    username: bool,
+    connack: Option<u8>,
+    published: BTreeMap<String, (u8, u32)>,
+    received: BTreeMap<String, (u8, u32)>,
+    subscribed: BTreeMap<String, u8>,
+    truncated: bool,
+}
+
+impl MqttSummary {
+    /// Returns `true` if nothing has been decoded yet
+    pub fn is_empty(&self) -> bool {
+        self.client_id.is_none() && self.published.is_empty() && self.received.is_empty() && self.subscribed.is_empty()
+    }
+
+    /// Decodes the MQTT control packets contained in `data`
+    /// ## Parameters
+    /// * `from_server`: `true` if the data was sent by the broker
+    /// * `data`: Reassembled TCP payload
+    ///
+    /// Returns the number of bytes consumed: a trailing incomplete packet is left for the caller
+    pub fn update(&mut self, from_server: bool, data: &[u8]) -> usize {
+        let mut consumed = 0;
+        while let Some((packet_type, flags, body, len)) = split_packet(&data[consumed..]) {
+            match packet_type {
+                CONNECT if !from_server => self.connect(body),
+                CONNACK if from_server && body.len() >= 2 => self.connack = Some(body[1]),
+                PUBLISH => self.publish(from_server, flags, body),
+                SUBSCRIBE if !from_server => self.subscribe(body),
+                _ => (),
+            }
+            consumed += len;
+        }
+        consumed
+    }
+
+    fn connect(&mut self, body: &[u8]) {
+        let mut r = Reader::new(body);
+        let fields = (string(&mut r), r.u8(), r.u8(), r.u16());
+        let (name, level, flags) = match fields {
+            (Some(n), Some(l), Some(f), Some(_keep_alive)) => (n, l, f),
+            _ => return,
+        };
+        if name != "MQTT" && name != "MQIsdp" {
+            return;
+        }
+        self.version = level;
+        self.username = flags & 0x80 != 0;
+        if level >= 5 && skip_properties(&mut r).is_none() {
+            return;
+        }
+        self.client_id = string(&mut r);
+    }
+
+    fn publish(&mut self, from_server: bool, flags: u8, body: &[u8]) {
+        let qos = (flags >> 1) & 0x03;
+        let mut r = Reader::new(body);
+        let topic = match string(&mut r) {
+            Some(t) => t,
+            None => return,
+        };
+        let topics = if from_server { &mut self.received } else { &mut self.published };
+        if topics.len() >= MAX_TOPICS && !topics.contains_key(&topic) {
+            self.truncated = true;
+            return;
+        }
+        let entry = topics.entry(topic).or_insert((qos, 0));
+        entry.0 = entry.0.max(qos);
+        entry.1 += 1;
+    }
+
+    fn subscribe(&mut self, body: &[u8]) {
+        let mut r = Reader::new(body);
+        // packet identifier
+        if r.u16().is_none() {
+            return;
+        }
+        if self.version >= 5 && skip_properties(&mut r).is_none() {
+            return;
+        }
+        while r.remaining() > 0 {
+            let (filter, options) = match (string(&mut r), r.u8()) {
+                (Some(f), Some(o)) => (f, o),
+                _ => return,
+            };
+            if self.subscribed.len() >= MAX_TOPICS && !self.subscribed.contains_key(&filter) {
+                self.truncated = true;
+                continue;
+            }
+            self.subscribed.insert(filter, options & 0x03);
+        }
+    }
+}
+
+impl Display for MqttSummary {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let version = match self.version {
+            3 => "3.1",
+            4 => "3.1.1",
+            5 => "5.0",
+            _ => "?",
+        };
+        write!(f, "MQTT {} client \"{}\"", version, self.client_id.as_deref().unwrap_or(""))?;
+        if self.username {
+            write!(f, " (user)")?;
+        }
+        if let Some(code) = self.connack {
+            write!(f, "; CONNACK {}", code)?;
+        }
+        let topics = |t: &BTreeMap<String, (u8, u32)>| -> String {
+            t.iter().map(|(name, (qos, n))| format!("{} (QoS {}) x{}", name, qos, n)).collect::<Vec<_>>().join(", ")
+        };
+        if !self.published.is_empty() {
+            write!(f, "; published: {}", topics(&self.published))?;
+        }
+        if !self.subscribed.is_empty() {
+            let filters: Vec<String> = self.subscribed.iter().map(|(name, qos)| format!("{} (QoS {})", name, qos)).collect();
+            write!(f, "; subscribed: {}", filters.join(", "))?;
+        }
+        if !self.received.is_empty() {
+            write!(f, "; received: {}", topics(&self.received))?;
+        }
+        if self.truncated {
+            write!(f, "; ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits the first complete control packet off `data`.
+/// Returns its type, flags, body and total length
+fn split_packet(data: &[u8]) -> Option<(u8, u8, &[u8], usize)> {
+    let first = *data.first()?;
+    // packet type 0 is reserved
+    if first >> 4 == 0 {
+        return None;
+    }
+    // remaining length: up to 4 bytes, 7 bits each
+    let mut len = 0usize;
+    let mut pos = 1;
+    loop {
+        let b = *data.get(pos)?;
+        len |= ((b & 0x7f) as usize) << (7 * (pos - 1));
+        pos += 1;
+        if b & 0x80 == 0 {
+            break;
+        }
+        if pos > 4 {
+            return None;
+        }
+    }
+    let body = data.get(pos..pos + len)?;
+    Some((first >> 4, first & 0x0f, body, pos + len))
+}
+
+fn string(r: &mut Reader) -> Option<String> {
+    let len = r.u16()? as usize;
+    Some(String::from_utf8_lossy(r.take(len)?).into_owned())
+}
+
+/// Skips the MQTT 5 property block
+fn skip_properties(r: &mut Reader) -> Option<()> {
+    let mut len = 0usize;
+    for i in 0..4 {
+        let b = r.u8()?;
+        len |= ((b & 0x7f) as usize) << (7 * i);
+        if b & 0x80 == 0 {
+            return r.skip(len);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a control packet with a one byte remaining length
+    fn packet(first: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![first, body.len() as u8];
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn mqtt_string(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    fn connect(level: u8, client_id: &str) -> Vec<u8> {
+        let mut body = mqtt_string("MQTT");
+        // user name and clean session flags, 60 s keep alive
+        body.extend_from_slice(&[level, 0x82, 0, 60]);
+        if level >= 5 {
+            body.push(0);
+        }
+        body.extend(mqtt_string(client_id));
+        body.extend(mqtt_string("user"));
+        packet(CONNECT << 4, &body)
+    }
+
+    #[test]
+    fn session() {
+        let mut client = connect(4, "abc");
+        let mut publish = mqtt_string("a/b/c");
+        publish.extend_from_slice(&[0, 1]);
+        publish.extend_from_slice(b"hi");
+        // QoS 1
+        client.extend(packet(PUBLISH << 4 | 0x02, &publish));
+        let mut subscribe = vec![0, 2];
+        subscribe.extend(mqtt_string("a/#"));
+        subscribe.push(1);
+        client.extend(packet(SUBSCRIBE << 4 | 0x02, &subscribe));
+
+        let mut server = packet(CONNACK << 4, &[0, 0]);
+        let mut delivered = mqtt_string("a/x");
+        delivered.push(b'z');
+        server.extend(packet(PUBLISH << 4, &delivered));
+
+        let mut summary = MqttSummary::default();
+        assert!(summary.is_empty());
+        assert_eq!(summary.update(false, &client), client.len());
+        assert_eq!(summary.update(true, &server), server.len());
+        assert_eq!(summary.to_string(),
+            "MQTT 3.1.1 client \"abc\" (user); CONNACK 0; published: a/b/c (QoS 1) x1; subscribed: a/# (QoS 1); received: a/x (QoS 0) x1");
+    }
+
+    #[test]
+    fn mqtt5_connect() {
+        let mut summary = MqttSummary::default();
+        summary.update(false, &connect(5, "sensor-1"));
+        assert_eq!(summary.to_string(), "MQTT 5.0 client \"sensor-1\" (user)");
+    }
+
+    #[test]
+    fn incomplete_packets() {
+        let mut summary = MqttSummary::default();
+        let data = connect(4, "abc");
+        assert_eq!(summary.update(false, &data[..data.len() - 1]), 0);
+        assert!(summary.is_empty());
+        // reserved packet type
+        assert_eq!(summary.update(false, &[0x00, 0x00]), 0);
+    }
+
+    #[test]
+    fn topics_limit() {
+        let mut summary = MqttSummary::default();
+        for i in 0..MAX_TOPICS + 1 {
+            summary.update(false, &packet(PUBLISH << 4, &mqtt_string(&format!("t/{}", i))));
+        }
+        assert_eq!(summary.published.len(), MAX_TOPICS);
+        assert!(summary.to_string().ends_with("; ..."));
+    }
+
+    #[test]
+    fn remaining_length() {
+        // 0x41 + 2 * 128
+        let mut data = vec![PUBLISH << 4, 0xc1, 0x02];
+        data.extend(vec![0; 321]);
+        let (packet_type, flags, body, len) = split_packet(&data).unwrap();
+        assert_eq!((packet_type, flags, body.len(), len), (PUBLISH, 0, 321, 324));
+        assert!(split_packet(&data[..100]).is_none());
+        // more than 4 length bytes
+        assert!(split_packet(&[PUBLISH << 4, 0x80, 0x80, 0x80, 0x80, 0x01]).is_none());
+    }
+}
@@ -0,0 +1,484 @@
+//! NetFlow v5, NetFlow v9 and IPFIX export of the connections over UDP.
+//!
+//! Each direction of a connection is a unidirectional flow, exported when it has been idle for the idle timeout
+//! or has lasted for the active timeout, and at the end of the capture. Counters are deltas since the previous export.
+use super::{Connection, Transport};
+use chrono::Local;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+
+/// Records per NetFlow v5 datagram
+const V5_MAX_RECORDS: usize = 30;
+/// Records per NetFlow v9/IPFIX datagram, keeping IPv6 datagrams within an Ethernet MTU
+const MAX_RECORDS: usize = 20;
+/// Templates are sent with the first datagram and then every `TEMPLATE_REFRESH` datagrams
+const TEMPLATE_REFRESH: u64 = 20;
+const TEMPLATE_V4: u16 = 256;
+const TEMPLATE_V6: u16 = 257;
+
+/// Template fields (type, length) of NetFlow v9: addresses, ports, protocol, TCP flags, packets, bytes, first and last switched
+const V9_FIELDS_V4: [(u16, u16); 10] = [(8, 4), (12, 4), (7, 2), (11, 2), (4, 1), (6, 1), (2, 8), (1, 8), (22, 4), (21, 4)];
+const V9_FIELDS_V6: [(u16, u16); 10] = [(27, 16), (28, 16), (7, 2), (11, 2), (4, 1), (6, 1), (2, 8), (1, 8), (22, 4), (21, 4)];
+/// Template fields of IPFIX, with flowStartMilliseconds and flowEndMilliseconds
+const IPFIX_FIELDS_V4: [(u16, u16); 10] = [(8, 4), (12, 4), (7, 2), (11, 2), (4, 1), (6, 1), (2, 8), (1, 8), (152, 8), (153, 8)];
+const IPFIX_FIELDS_V6: [(u16, u16); 10] = [(27, 16), (28, 16), (7, 2), (11, 2), (4, 1), (6, 1), (2, 8), (1, 8), (152, 8), (153, 8)];
+
+/// Counters of a flow since its previous export
+#[derive(Clone, Copy, Debug)]
+struct Flow {
+    packets: u64,
+    bytes: u64,
+    /// Timestamps (us since the epoch) of the first and last packets
+    first: i64,
+    last: i64,
+    /// TCP flags seen, ORed
+    flags: u8,
+}
+
+/// A flow ready to be encoded
+struct Record {
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    protocol: u8,
+    flow: Flow,
+}
+
+/// This struct exports the flows to a collector
+/// ## Properties
+/// * `socket`: UDP socket connected to the collector
+/// * `version`: 5, 9 or 10 (IPFIX)
+/// * `domain`: Observation domain (IPFIX), source ID (v9), engine type and ID (v5)
+/// * `active_timeout`, `idle_timeout`: Timeouts (us)
+/// * `boot`: Time (us since the epoch) the exporter was created, the origin of the uptime
+/// * `sequence`: Flows (v5), datagrams (v9) or data records (IPFIX) sent so far
+/// * `datagrams`: Datagrams sent so far
+/// * `flows`: Flows not exported yet, by connection index and direction
+pub(crate) struct FlowExport {
+    socket: UdpSocket,
+    version: u16,
+    domain: u32,
+    active_timeout: i64,
+    idle_timeout: i64,
+    boot: i64,
+    sequence: u32,
+    datagrams: u64,
+    flows: HashMap<(usize, usize), Flow>,
+}
+
+impl FlowExport {
+    /// ## Parameters
+    /// * `collector`: Address of the collector, as `host:port`
+    /// * `version`: 5, 9 or 10 (IPFIX)
+    /// * `domain`: Observation domain
+    /// * `active_timeout`, `idle_timeout`: Timeouts (secs)
+    pub fn new(collector: &str, version: u16, domain: u32, active_timeout: f64, idle_timeout: f64) -> Result<Self> {
+        if ![5, 9, 10].contains(&version) {
+            return Err(Error::new(ErrorKind::InvalidInput, "unsupported flow export version"));
+        }
+        let addr = collector.to_socket_addrs()?.next().ok_or_else(|| Error::new(ErrorKind::NotFound, collector.to_string()))?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(addr)?;
+        Ok(FlowExport {
+            socket,
+            version,
+            domain,
+            active_timeout: (active_timeout * 1_000_000.0) as i64,
+            idle_timeout: (idle_timeout * 1_000_000.0) as i64,
+            boot: Local::now().timestamp_micros(),
+            sequence: 0,
+            datagrams: 0,
+            flows: HashMap::new(),
+        })
+    }
+
+    /// Accounts a packet
+    /// ## Parameters
+    /// * `index`: Connection index
+    /// * `dir`: Direction, 0 if sent by address B (the sender of the first packet), 1 otherwise
+    /// * `bytes`: Packet length
+    /// * `flags`: TCP flags of the packet, 0 for UDP
+    /// * `ts`: Packet timestamp (us since the epoch)
+    pub fn add(&mut self, index: usize, dir: usize, bytes: u32, flags: u8, ts: i64) {
+        let flow = self.flows.entry((index, dir)).or_insert(Flow { packets: 0, bytes: 0, first: ts, last: ts, flags: 0 });
+        flow.packets += 1;
+        flow.bytes += bytes as u64;
+        flow.last = flow.last.max(ts);
+        flow.flags |= flags;
+    }
+
+    /// Exports the flows that reached the idle or the active timeout
+    pub fn expire(&mut self, connections: &[Connection]) -> Result<()> {
+        let now = Local::now().timestamp_micros();
+        let (active, idle) = (self.active_timeout, self.idle_timeout);
+        let expired: Vec<(usize, usize)> = self.flows.iter()
+            .filter(|(_, f)| now - f.last >= idle || now - f.first >= active)
+            .map(|(k, _)| *k)
+            .collect();
+        self.export(connections, expired)
+    }
+
+    /// Exports all the flows
+    pub fn flush(&mut self, connections: &[Connection]) -> Result<()> {
+        let all: Vec<(usize, usize)> = self.flows.keys().copied().collect();
+        self.export(connections, all)
+    }
+
+    fn export(&mut self, connections: &[Connection], keys: Vec<(usize, usize)>) -> Result<()> {
+        let mut records = vec![];
+        for key in keys {
+            let flow = match self.flows.remove(&key) {
+                Some(f) => f,
+                None => continue,
+            };
+            if let Some(r) = record(&connections[key.0], key.1, flow) {
+                //NetFlow v5 only carries IPv4 flows
+                if self.version != 5 || r.src.is_ipv4() {
+                    records.push(r);
+                }
+            }
+        }
+        records.sort_by_key(|r| r.flow.first);
+        let max = if self.version == 5 { V5_MAX_RECORDS } else { MAX_RECORDS };
+        for chunk in records.chunks(max) {
+            let datagram = match self.version {
+                5 => self.v5(chunk),
+                9 => self.v9(chunk),
+                _ => self.ipfix(chunk),
+            };
+            self.socket.send(&datagram)?;
+            self.datagrams += 1;
+        }
+        Ok(())
+    }
+
+    /// Milliseconds since the exporter was created
+    fn uptime(&self, ts: i64) -> u32 {
+        ((ts - self.boot).max(0) / 1000) as u32
+    }
+
+    fn v5(&mut self, records: &[Record]) -> Vec<u8> {
+        let now = Local::now();
+        let mut buf = Vec::with_capacity(24 + 48 * records.len());
+        buf.extend(5u16.to_be_bytes());
+        buf.extend((records.len() as u16).to_be_bytes());
+        buf.extend(self.uptime(now.timestamp_micros()).to_be_bytes());
+        buf.extend((now.timestamp() as u32).to_be_bytes());
+        buf.extend(now.timestamp_subsec_nanos().to_be_bytes());
+        buf.extend(self.sequence.to_be_bytes());
+        buf.push((self.domain >> 8) as u8);
+        buf.push(self.domain as u8);
+        buf.extend(0u16.to_be_bytes());
+        for r in records {
+            buf.extend(octets(&r.src));
+            buf.extend(octets(&r.dst));
+            buf.extend([0; 4]); //next hop
+            buf.extend([0; 4]); //input and output interfaces
+            buf.extend((r.flow.packets.min(u32::MAX as u64) as u32).to_be_bytes());
+            buf.extend((r.flow.bytes.min(u32::MAX as u64) as u32).to_be_bytes());
+            buf.extend(self.uptime(r.flow.first).to_be_bytes());
+            buf.extend(self.uptime(r.flow.last).to_be_bytes());
+            buf.extend(r.src_port.to_be_bytes());
+            buf.extend(r.dst_port.to_be_bytes());
+            buf.extend([0, r.flow.flags, r.protocol, 0]); //pad, TCP flags, protocol, ToS
+            buf.extend([0; 8]); //AS numbers, masks and padding
+        }
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+        buf
+    }
+
+    fn v9(&mut self, records: &[Record]) -> Vec<u8> {
+        let now = Local::now();
+        let mut buf = vec![];
+        buf.extend(9u16.to_be_bytes());
+        buf.extend([0; 2]); //record count, set below
+        buf.extend(self.uptime(now.timestamp_micros()).to_be_bytes());
+        buf.extend((now.timestamp() as u32).to_be_bytes());
+        buf.extend(self.sequence.to_be_bytes());
+        buf.extend(self.domain.to_be_bytes());
+        let mut count = 0;
+        if self.datagrams.is_multiple_of(TEMPLATE_REFRESH) {
+            set(&mut buf, 0, true, |b| {
+                template(b, TEMPLATE_V4, &V9_FIELDS_V4);
+                template(b, TEMPLATE_V6, &V9_FIELDS_V6);
+            });
+            count += 2;
+        }
+        for (id, v4) in [(TEMPLATE_V4, true), (TEMPLATE_V6, false)] {
+            let family: Vec<&Record> = records.iter().filter(|r| r.src.is_ipv4() == v4).collect();
+            if family.is_empty() {
+                continue;
+            }
+            set(&mut buf, id, true, |b| {
+                for r in &family {
+                    common_fields(b, r);
+                    b.extend(self.uptime(r.flow.first).to_be_bytes());
+                    b.extend(self.uptime(r.flow.last).to_be_bytes());
+                }
+            });
+            count += family.len();
+        }
+        buf[2..4].copy_from_slice(&(count as u16).to_be_bytes());
+        self.sequence = self.sequence.wrapping_add(1);
+        buf
+    }
+
+    fn ipfix(&mut self, records: &[Record]) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend(10u16.to_be_bytes());
+        buf.extend([0; 2]); //message length, set below
+        buf.extend((Local::now().timestamp() as u32).to_be_bytes());
+        buf.extend(self.sequence.to_be_bytes());
+        buf.extend(self.domain.to_be_bytes());
+        if self.datagrams.is_multiple_of(TEMPLATE_REFRESH) {
+            set(&mut buf, 2, false, |b| {
+                template(b, TEMPLATE_V4, &IPFIX_FIELDS_V4);
+                template(b, TEMPLATE_V6, &IPFIX_FIELDS_V6);
+            });
+        }
+        for (id, v4) in [(TEMPLATE_V4, true), (TEMPLATE_V6, false)] {
+            let family: Vec<&Record> = records.iter().filter(|r| r.src.is_ipv4() == v4).collect();
+            if family.is_empty() {
+                continue;
+            }
+            set(&mut buf, id, false, |b| {
+                for r in &family {
+                    common_fields(b, r);
+                    b.extend(((r.flow.first / 1000) as u64).to_be_bytes());
+                    b.extend(((r.flow.last / 1000) as u64).to_be_bytes());
+                }
+            });
+        }
+        let len = buf.len() as u16;
+        buf[2..4].copy_from_slice(&len.to_be_bytes());
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+        buf
+    }
+}
+
+/// Builds the record of direction `dir` of a connection
+fn record(con: &Connection, dir: usize, flow: Flow) -> Option<Record> {
+    let a: IpAddr = con.ip_1.parse().ok()?;
+    let b: IpAddr = con.ip_2.parse().ok()?;
+    let port_a: u16 = con.port_1.parse().ok()?;
+    let port_b: u16 = con.port_2.parse().ok()?;
+    //direction 0 is the one of the first packet, sent by address B
+    let ((src, src_port), (dst, dst_port)) = if dir == 0 { ((b, port_b), (a, port_a)) } else { ((a, port_a), (b, port_b)) };
+    let protocol = if con.l4 == Transport::TCP { 6 } else { 17 };
+    Some(Record { src, dst, src_port, dst_port, protocol, flow })
+}
+
+fn octets(ip: &IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(a) => a.octets().to_vec(),
+        IpAddr::V6(a) => a.octets().to_vec(),
+    }
+}
+
+/// Writes a flowset/set with its header, padding it to 4 bytes if `pad`
+fn set(buf: &mut Vec<u8>, id: u16, pad: bool, content: impl FnOnce(&mut Vec<u8>)) {
+    let start = buf.len();
+    buf.extend(id.to_be_bytes());
+    buf.extend([0; 2]);
+    content(buf);
+    while pad && !(buf.len() - start).is_multiple_of(4) {
+        buf.push(0);
+    }
+    let len = (buf.len() - start) as u16;
+    buf[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn template(buf: &mut Vec<u8>, id: u16, fields: &[(u16, u16)]) {
+    buf.extend(id.to_be_bytes());
+    buf.extend((fields.len() as u16).to_be_bytes());
+    for (kind, len) in fields {
+        buf.extend(kind.to_be_bytes());
+        buf.extend(len.to_be_bytes());
+    }
+}
+
+/// Addresses, ports, protocol, TCP flags, packets and bytes, shared by the v9 and IPFIX templates
+fn common_fields(buf: &mut Vec<u8>, r: &Record) {
+    buf.extend(octets(&r.src));
+    buf.extend(octets(&r.dst));
+    buf.extend(r.src_port.to_be_bytes());
+    buf.extend(r.dst_port.to_be_bytes());
+    buf.push(r.protocol);
+    buf.push(r.flow.flags);
+    buf.extend(r.flow.packets.to_be_bytes());
+    buf.extend(r.flow.bytes.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Connection from address B (port 40000) to address A (port 80)
+    fn connection(l3: u8, a: &str, b: &str, l4: u8) -> Connection {
+        let now = Local::now();
+        Connection::new(l3, a.to_string(), b.to_string(), l4, String::from("80"), String::from("40000"), now, now, 0, String::new())
+    }
+
+    /// Exporter sending to a collector on localhost, and the collector
+    fn exporter(version: u16) -> (FlowExport, UdpSocket) {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let export = FlowExport::new(&collector.local_addr().unwrap().to_string(), version, 0x0102_0304, 60.0, 15.0).unwrap();
+        (export, collector)
+    }
+
+    fn receive(collector: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0; 65535];
+        let len = collector.recv(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Big-endian number of `len` bytes at `at`
+    fn be(buf: &[u8], at: usize, len: usize) -> u64 {
+        buf[at..at + len].iter().fold(0, |n, b| (n << 8) | *b as u64)
+    }
+
+    /// Two IPv4 TCP flows, one for each direction, and an IPv6 UDP flow, the first one sent 1 s after `boot`
+    fn add_flows(export: &mut FlowExport) -> Vec<Connection> {
+        let ts = export.boot + 1_000_000;
+        export.add(0, 0, 100, 0x02, ts);
+        export.add(0, 0, 60, 0x10, ts + 2_000_000);
+        export.add(0, 1, 1500, 0x12, ts + 500_000);
+        export.add(1, 0, 80, 0, ts + 700_000);
+        vec![connection(4, "10.0.0.1", "10.0.0.2", 0), connection(6, "2001:db8::1", "2001:db8::2", 1)]
+    }
+
+    #[test]
+    fn unsupported_version() {
+        assert!(FlowExport::new("127.0.0.1:2055", 7, 0, 60.0, 15.0).is_err());
+    }
+
+    #[test]
+    fn v5_layout() {
+        let (mut export, collector) = exporter(5);
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        let buf = receive(&collector);
+        //IPv6 flows cannot be carried
+        assert_eq!(buf.len(), 24 + 48 * 2);
+        assert_eq!(be(&buf, 0, 2), 5);
+        assert_eq!(be(&buf, 2, 2), 2);
+        assert_eq!(be(&buf, 16, 4), 0);
+        assert_eq!(&buf[20..22], &[0x03, 0x04]);
+        //oldest flow first: B to A
+        let r = &buf[24..72];
+        assert_eq!(&r[0..4], &[10, 0, 0, 2]);
+        assert_eq!(&r[4..8], &[10, 0, 0, 1]);
+        assert_eq!(be(r, 16, 4), 2);
+        assert_eq!(be(r, 20, 4), 160);
+        assert_eq!(be(r, 24, 4), 1000);
+        assert_eq!(be(r, 28, 4), 3000);
+        assert_eq!(be(r, 32, 2), 40000);
+        assert_eq!(be(r, 34, 2), 80);
+        assert_eq!(&r[36..40], &[0, 0x12, 6, 0]);
+        let r = &buf[72..120];
+        assert_eq!(&r[0..4], &[10, 0, 0, 1]);
+        assert_eq!(be(r, 20, 4), 1500);
+        assert_eq!(be(r, 32, 2), 80);
+
+        //the sequence counts the flows
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        assert_eq!(be(&receive(&collector), 16, 4), 2);
+    }
+
+    #[test]
+    fn v9_layout() {
+        let (mut export, collector) = exporter(9);
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        let buf = receive(&collector);
+        assert_eq!(be(&buf, 0, 2), 9);
+        //2 templates and 3 records
+        assert_eq!(be(&buf, 2, 2), 5);
+        assert_eq!(be(&buf, 12, 4), 0);
+        assert_eq!(be(&buf, 16, 4), 0x0102_0304);
+
+        //template flowset, then a data flowset for each address family, padded to 4 bytes
+        assert_eq!(be(&buf, 20, 2), 0);
+        assert_eq!(be(&buf, 22, 2), 4 + 2 * (4 + 4 * 10));
+        assert_eq!(be(&buf, 24, 2), TEMPLATE_V4 as u64);
+        assert_eq!(be(&buf, 26, 2), 10);
+        let v4_len: u16 = V9_FIELDS_V4.iter().map(|(_, len)| len).sum();
+        let v6_len: u16 = V9_FIELDS_V6.iter().map(|(_, len)| len).sum();
+        let at = 20 + 92;
+        assert_eq!(be(&buf, at, 2), TEMPLATE_V4 as u64);
+        assert_eq!(be(&buf, at + 2, 2), (4 + 2 * v4_len as u64).next_multiple_of(4));
+        let r = &buf[at + 4..at + 4 + v4_len as usize];
+        assert_eq!(&r[0..4], &[10, 0, 0, 2]);
+        assert_eq!(be(r, 8, 2), 40000);
+        assert_eq!(r[12], 6);
+        assert_eq!(be(r, 14, 8), 2);
+        assert_eq!(be(r, 22, 8), 160);
+        assert_eq!(be(r, 30, 4), 1000);
+        assert_eq!(be(r, 34, 4), 3000);
+        let at = at + be(&buf, at + 2, 2) as usize;
+        assert_eq!(be(&buf, at, 2), TEMPLATE_V6 as u64);
+        assert_eq!(be(&buf, at + 2, 2), (4 + v6_len as u64).next_multiple_of(4));
+        assert_eq!(buf[at + 4 + 36], 17);
+        assert_eq!(buf.len(), at + be(&buf, at + 2, 2) as usize);
+
+        //no templates until the refresh, the sequence counts the datagrams
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        let buf = receive(&collector);
+        assert_eq!(be(&buf, 2, 2), 3);
+        assert_eq!(be(&buf, 12, 4), 1);
+        assert_eq!(be(&buf, 20, 2), TEMPLATE_V4 as u64);
+    }
+
+    #[test]
+    fn ipfix_layout() {
+        let (mut export, collector) = exporter(10);
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        let buf = receive(&collector);
+        assert_eq!(be(&buf, 0, 2), 10);
+        assert_eq!(be(&buf, 2, 2), buf.len() as u64);
+        assert_eq!(be(&buf, 8, 4), 0);
+        assert_eq!(be(&buf, 12, 4), 0x0102_0304);
+
+        //template set, then unpadded data sets
+        assert_eq!(be(&buf, 16, 2), 2);
+        assert_eq!(be(&buf, 18, 2), 4 + 2 * (4 + 4 * 10));
+        let v4_len: u16 = IPFIX_FIELDS_V4.iter().map(|(_, len)| len).sum();
+        let v6_len: u16 = IPFIX_FIELDS_V6.iter().map(|(_, len)| len).sum();
+        let at = 16 + 92;
+        assert_eq!(be(&buf, at, 2), TEMPLATE_V4 as u64);
+        assert_eq!(be(&buf, at + 2, 2), 4 + 2 * v4_len as u64);
+        let r = &buf[at + 4..at + 4 + v4_len as usize];
+        assert_eq!(be(r, 30, 8), (export.boot / 1000 + 1000) as u64);
+        assert_eq!(be(r, 38, 8), (export.boot / 1000 + 3000) as u64);
+        let at = at + 4 + 2 * v4_len as usize;
+        assert_eq!(be(&buf, at, 2), TEMPLATE_V6 as u64);
+        assert_eq!(be(&buf, at + 2, 2), 4 + v6_len as u64);
+        assert_eq!(buf.len(), at + 4 + v6_len as usize);
+
+        //the sequence counts the data records
+        let connections = add_flows(&mut export);
+        export.flush(&connections).unwrap();
+        assert_eq!(be(&receive(&collector), 8, 4), 3);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let (mut export, collector) = exporter(5);
+        let now = Local::now().timestamp_micros();
+        export.add(0, 0, 100, 0, now - 20_000_000);
+        export.add(0, 1, 100, 0, now);
+        export.expire(&[connection(4, "10.0.0.1", "10.0.0.2", 1)]).unwrap();
+        let buf = receive(&collector);
+        assert_eq!(be(&buf, 2, 2), 1);
+        assert_eq!(&buf[24..28], &[10, 0, 0, 2]);
+        assert_eq!(buf[24 + 38], 17);
+        assert_eq!(export.flows.len(), 1);
+    }
+}
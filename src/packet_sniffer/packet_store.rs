@@ -0,0 +1,108 @@
+//! Bounded store of the first packets of each connection, used to extract a single flow after the capture.
+use pcap::PacketHeader;
+use std::collections::HashMap;
+
+/// Packets kept for a single connection
+#[derive(Clone, Default)]
+struct FlowPackets {
+    packets: Vec<(PacketHeader, Vec<u8>)>,
+    bytes: usize,
+}
+
+/// This struct keeps the first packets of each connection
+/// ## Properties
+/// * `max_packets`: Number of packets kept for each connection (0 disables the store)
+/// * `max_bytes`: Captured bytes kept for each connection
+/// * `flows`: Packets kept for each connection, indexed by connection index
+#[derive(Clone, Default)]
+pub(crate) struct PacketStore {
+    max_packets: usize,
+    max_bytes: usize,
+    flows: HashMap<usize, FlowPackets>,
+}
+
+impl PacketStore {
+    pub fn new(max_packets: usize, max_bytes: usize) -> Self {
+        PacketStore {
+            max_packets,
+            max_bytes,
+            flows: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_packets > 0 && self.max_bytes > 0
+    }
+
+    /// Keeps a copy of the packet if the connection has not reached its limits yet
+    /// ## Parameters
+    /// * `index`: Index of the connection the packet belongs to
+    /// * `header`: pcap header of the packet
+    /// * `data`: Captured bytes
+    pub fn add(&mut self, index: usize, header: &PacketHeader, data: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let flow = self.flows.entry(index).or_default();
+        if flow.packets.len() >= self.max_packets || flow.bytes + data.len() > self.max_bytes {
+            return;
+        }
+        flow.bytes += data.len();
+        flow.packets.push((*header, data.to_vec()));
+    }
+
+    /// Returns the packets kept for connection `index`, in capture order
+    pub fn packets(&self, index: usize) -> impl Iterator<Item = &(PacketHeader, Vec<u8>)> {
+        self.flows.get(&index).into_iter().flat_map(|f| f.packets.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(len: u32) -> PacketHeader {
+        // the timeval field types differ between platforms
+        let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+        header.caplen = len;
+        header.len = len;
+        header
+    }
+
+    fn lengths(store: &PacketStore, index: usize) -> Vec<usize> {
+        store.packets(index).map(|(_, data)| data.len()).collect()
+    }
+
+    #[test]
+    fn disabled() {
+        for store in [PacketStore::default(), PacketStore::new(0, 100), PacketStore::new(10, 0)] {
+            let mut store = store;
+            assert!(!store.is_enabled());
+            store.add(0, &header(10), &[0; 10]);
+            assert!(lengths(&store, 0).is_empty());
+        }
+    }
+
+    #[test]
+    fn packet_limit() {
+        let mut store = PacketStore::new(2, 1000);
+        assert!(store.is_enabled());
+        for len in [1, 2, 3] {
+            store.add(0, &header(len), &vec![0; len as usize]);
+        }
+        store.add(1, &header(4), &[0; 4]);
+        assert_eq!(lengths(&store, 0), vec![1, 2]);
+        assert_eq!(lengths(&store, 1), vec![4]);
+        assert!(lengths(&store, 2).is_empty());
+    }
+
+    #[test]
+    fn byte_limit() {
+        let mut store = PacketStore::new(10, 10);
+        // the second packet does not fit, the third one still does
+        for len in [6, 5, 4, 1] {
+            store.add(0, &header(len), &vec![0; len as usize]);
+        }
+        assert_eq!(lengths(&store, 0), vec![6, 4]);
+    }
+}
@@ -0,0 +1,341 @@
+//! pcapng writer (draft-ietf-opsawg-pcapng) with size and time based file rotation.
+use pcap::PacketHeader;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const BLOCK_SHB: u32 = 0x0a0d_0d0a;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+const IF_TSRESOL: u16 = 9;
+
+/// A capture interface, described in each file by an Interface Description Block
+/// ## Properties
+/// * `name`: Name of the device
+/// * `desc`: Description of the device
+/// * `linktype`: Link-layer header type of the capture
+/// * `snaplen`: Bytes captured of each packet
+/// * `nanos`: `true` if the timestamps of the packets are in nanoseconds, `false` if they are in microseconds
+#[derive(Clone, Debug)]
+pub(crate) struct Interface {
+    pub name: String,
+    pub desc: Option<String>,
+    pub linktype: i32,
+    pub snaplen: u32,
+    pub nanos: bool,
+}
+
+/// This struct writes packets to one or more pcapng files
+/// ## Properties
+/// * `path`: Path of the output file; when rotating, a sequence number is added before the extension
+/// * `rotate_size`: Size (bytes) after which a new file is started
+/// * `rotate_time`: Time after which a new file is started
+/// * `interfaces`: Interface metadata written in each file, the interface ID of a packet being its index
+/// * `file`: Current output file
+/// * `file_number`: Sequence number of the current file
+/// * `written`: Bytes written to the current file
+/// * `packets`: Packets written to the current file
+/// * `opened`: Time the current file was created
+pub(crate) struct PcapngWriter {
+    path: String,
+    rotate_size: Option<u64>,
+    rotate_time: Option<Duration>,
+    interfaces: Vec<Interface>,
+    file: BufWriter<File>,
+    file_number: u32,
+    written: u64,
+    packets: u64,
+    opened: Instant,
+}
+
+impl PcapngWriter {
+    /// Creates the first output file
+    /// ## Parameters
+    /// * `path`: Path of the output file
+    /// * `rotate_size`: Size (bytes) after which a new file is started, if any
+    /// * `rotate_time`: Time after which a new file is started, if any
+    /// * `interfaces`: Capture interfaces
+    pub fn new(path: &str, rotate_size: Option<u64>, rotate_time: Option<Duration>, interfaces: Vec<Interface>) -> Result<Self> {
+        let rotating = rotate_size.is_some() || rotate_time.is_some();
+        let file_name = Self::file_name(path, rotating, 1);
+        let mut writer = PcapngWriter {
+            path: path.to_string(),
+            rotate_size,
+            rotate_time,
+            interfaces,
+            file: BufWriter::new(File::create(file_name)?),
+            file_number: 1,
+            written: 0,
+            packets: 0,
+            opened: Instant::now(),
+        };
+        writer.write_headers()?;
+        Ok(writer)
+    }
+
+    /// Writes a packet as an Enhanced Packet Block, rotating the file first if needed
+    /// ## Parameters
+    /// * `interface`: Index of the interface that captured the packet
+    /// * `header`: pcap header of the packet
+    /// * `data`: Captured bytes
+    /// * `connection`: Connection number (as shown in the report) the packet belongs to
+    pub fn write_packet(&mut self, interface: usize, header: &PacketHeader, data: &[u8], connection: Option<usize>) -> Result<()> {
+        let size_exceeded = self.rotate_size.is_some_and(|s| self.written >= s);
+        let time_exceeded = self.rotate_time.is_some_and(|t| self.opened.elapsed() >= t);
+        // every file gets at least one packet, even when the headers alone exceed the size
+        if self.packets > 0 && (size_exceeded || time_exceeded) {
+            self.rotate()?;
+        }
+
+        //the timestamps are written with the resolution of the interface
+        let units = if self.interfaces.get(interface).is_some_and(|i| i.nanos) { 1_000_000_000 } else { 1_000_000 };
+        let ts = header.ts.tv_sec as u64 * units + header.ts.tv_usec as u64;
+        let mut body = vec![];
+        body.extend_from_slice(&(interface as u32).to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&header.len.to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+        if let Some(connection) = connection {
+            option(&mut body, OPT_COMMENT, format!("connection #{}", connection + 1).as_bytes());
+            option(&mut body, OPT_END, &[]);
+        }
+        self.packets += 1;
+        self.block(BLOCK_EPB, &body)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file_number += 1;
+        let file_name = Self::file_name(&self.path, true, self.file_number);
+        self.file = BufWriter::new(File::create(file_name)?);
+        self.written = 0;
+        self.packets = 0;
+        self.opened = Instant::now();
+        self.write_headers()
+    }
+
+    /// Writes the Section Header Block and an Interface Description Block for each interface
+    fn write_headers(&mut self) -> Result<()> {
+        let mut shb = vec![];
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        // version 1.0
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // section length not specified
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        option(&mut shb, SHB_USERAPPL, b"packet_sniffer");
+        option(&mut shb, OPT_END, &[]);
+        self.block(BLOCK_SHB, &shb)?;
+
+        for i in 0..self.interfaces.len() {
+            let interface = &self.interfaces[i];
+            let mut idb = vec![];
+            idb.extend_from_slice(&(interface.linktype as u16).to_le_bytes());
+            // reserved
+            idb.extend_from_slice(&0u16.to_le_bytes());
+            idb.extend_from_slice(&interface.snaplen.to_le_bytes());
+            option(&mut idb, IF_NAME, interface.name.as_bytes());
+            if let Some(desc) = &interface.desc {
+                option(&mut idb, IF_DESCRIPTION, desc.as_bytes());
+            }
+            // microsecond or nanosecond timestamps
+            option(&mut idb, IF_TSRESOL, &[if interface.nanos { 9 } else { 6 }]);
+            option(&mut idb, OPT_END, &[]);
+            self.block(BLOCK_IDB, &idb)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block_type: u32, body: &[u8]) -> Result<()> {
+        let total = (12 + body.len()) as u32;
+        self.file.write_all(&block_type.to_le_bytes())?;
+        self.file.write_all(&total.to_le_bytes())?;
+        self.file.write_all(body)?;
+        self.file.write_all(&total.to_le_bytes())?;
+        self.written += total as u64;
+        Ok(())
+    }
+
+    /// Name of file number `n`: `capture.pcapng` becomes `capture-0001.pcapng` when rotating
+    fn file_name(path: &str, rotating: bool, n: u32) -> String {
+        if !rotating {
+            return path.to_string();
+        }
+        let p = Path::new(path);
+        let stem = p.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = match p.extension() {
+            Some(ext) => format!("{}-{:04}.{}", stem, n, ext.to_string_lossy()),
+            None => format!("{}-{:04}", stem, n),
+        };
+        p.with_file_name(name).to_string_lossy().into_owned()
+    }
+}
+
+/// Appends an option (code, length, value padded to 32 bits)
+fn option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+/// This struct holds the settings of the pcapng output
+/// ## Properties
+/// * `path`: Path of the output file
+/// * `filter`: Secondary filter selecting the packets to save (syntax is available at <https://biot.com/capstats/bpf.html>), empty to save all of them
+/// * `rotate_size`: Size (bytes) after which a new file is started, if any
+/// * `rotate_time`: Time after which a new file is started, if any
+#[derive(Clone, Debug)]
+pub(crate) struct PcapOutput {
+    pub path: String,
+    pub filter: String,
+    pub rotate_size: Option<u64>,
+    pub rotate_time: Option<Duration>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory for the files written by `test`, emptied first
+    fn test_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("packet_sniffer-pcapng-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Splits a file into its blocks, checking the two length fields of each
+    fn blocks(path: &Path) -> Vec<(u32, Vec<u8>)> {
+        let data = std::fs::read(path).unwrap();
+        let mut blocks = vec![];
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+            let total = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(total % 4, 0);
+            assert_eq!(&rest[total - 4..total], &rest[4..8]);
+            blocks.push((block_type, rest[8..total - 4].to_vec()));
+            rest = &rest[total..];
+        }
+        blocks
+    }
+
+    fn interface(name: &str, nanos: bool) -> Interface {
+        Interface { name: name.to_string(), desc: Some(String::from("test")), linktype: 1, snaplen: 65535, nanos }
+    }
+
+    fn header(sec: i64, frac: i64, len: u32) -> PacketHeader {
+        // the timeval field types differ between platforms
+        let mut header: PacketHeader = unsafe { std::mem::zeroed() };
+        header.ts.tv_sec = sec as _;
+        header.ts.tv_usec = frac as _;
+        header.caplen = len;
+        header.len = len;
+        header
+    }
+
+    #[test]
+    fn layout() {
+        let path = test_dir("layout").join("capture.pcapng");
+        let mut writer = PcapngWriter::new(path.to_str().unwrap(), None, None,
+            vec![interface("eth0", false), interface("eth1", true)]).unwrap();
+        writer.write_packet(0, &header(1, 500_000, 5), &[1, 2, 3, 4, 5], Some(0)).unwrap();
+        writer.write_packet(1, &header(2, 7, 4), &[9, 9, 9, 9], None).unwrap();
+        writer.flush().unwrap();
+
+        let blocks = blocks(&path);
+        assert_eq!(blocks.iter().map(|b| b.0).collect::<Vec<_>>(), vec![BLOCK_SHB, BLOCK_IDB, BLOCK_IDB, BLOCK_EPB, BLOCK_EPB]);
+
+        let shb = &blocks[0].1;
+        assert_eq!(&shb[0..4], &BYTE_ORDER_MAGIC.to_le_bytes());
+        assert_eq!(&shb[4..8], &[1, 0, 0, 0]);
+        assert_eq!(&shb[8..16], &[0xff; 8]);
+        assert_eq!(&shb[16..20], &[4, 0, 14, 0]);
+        assert_eq!(&shb[20..34], b"packet_sniffer");
+
+        // link type, reserved, snaplen, if_name, if_description, if_tsresol, end
+        let idb = &blocks[2].1;
+        assert_eq!(&idb[0..8], &[1, 0, 0, 0, 0xff, 0xff, 0, 0]);
+        assert_eq!(&idb[8..16], &[2, 0, 4, 0, b'e', b't', b'h', b'1']);
+        assert_eq!(&idb[16..24], &[3, 0, 4, 0, b't', b'e', b's', b't']);
+        assert_eq!(&idb[24..32], &[9, 0, 1, 0, 9, 0, 0, 0]);
+        assert_eq!(&idb[32..], &[0, 0, 0, 0]);
+        assert_eq!(blocks[1].1[28], 6);
+
+        // interface, timestamp (high, low), captured and original length, padded data, comment
+        let epb = &blocks[3].1;
+        assert_eq!(&epb[0..4], &[0, 0, 0, 0]);
+        let ts = 1_500_000u64;
+        assert_eq!(&epb[4..8], &((ts >> 32) as u32).to_le_bytes());
+        assert_eq!(&epb[8..12], &(ts as u32).to_le_bytes());
+        assert_eq!(&epb[12..20], &[5, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(&epb[20..28], &[1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(&epb[28..32], &[1, 0, 13, 0]);
+        assert_eq!(&epb[32..48], b"connection #1\0\0\0");
+        assert_eq!(&epb[48..], &[0, 0, 0, 0]);
+
+        // nanosecond interface, no comment
+        let epb = &blocks[4].1;
+        let ts = 2_000_000_007u64;
+        assert_eq!(&epb[0..4], &[1, 0, 0, 0]);
+        assert_eq!(&epb[8..12], &(ts as u32).to_le_bytes());
+        assert_eq!(epb.len(), 24);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn size_rotation() {
+        let dir = test_dir("rotation");
+        let path = dir.join("capture.pcapng");
+        let mut writer = PcapngWriter::new(path.to_str().unwrap(), Some(1), None, vec![interface("eth0", false)]).unwrap();
+        for i in 0..3 {
+            writer.write_packet(0, &header(i, 0, 4), &[0; 4], None).unwrap();
+        }
+        writer.flush().unwrap();
+        assert!(!path.exists());
+        for n in 1..=3 {
+            let blocks = blocks(&dir.join(format!("capture-{:04}.pcapng", n)));
+            assert_eq!(blocks.iter().map(|b| b.0).collect::<Vec<_>>(), vec![BLOCK_SHB, BLOCK_IDB, BLOCK_EPB]);
+        }
+        assert!(!dir.join("capture-0004.pcapng").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(PcapngWriter::file_name("out/capture.pcapng", false, 1), "out/capture.pcapng");
+        assert_eq!(PcapngWriter::file_name("out/capture.pcapng", true, 12), "out/capture-0012.pcapng");
+        assert_eq!(PcapngWriter::file_name("capture", true, 2), "capture-0002");
+    }
+
+    #[test]
+    fn options() {
+        let mut buf = vec![];
+        option(&mut buf, OPT_COMMENT, b"hello");
+        assert_eq!(buf, vec![1, 0, 5, 0, b'h', b'e', b'l', b'l', b'o', 0, 0, 0]);
+        option(&mut buf, OPT_END, &[]);
+        assert_eq!(buf.len(), 16);
+    }
+}
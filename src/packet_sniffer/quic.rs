@@ -0,0 +1,523 @@
+//! QUIC long header detection and Initial packet decryption (RFC 9000, RFC 9001, RFC 9369).
+//!
+//! Initial packets are protected with keys derived from the client's Destination Connection ID
+//! and a public, version-specific salt, so any observer can remove the protection and read the
+//! TLS ClientHello carried inside the CRYPTO frames.
+use super::reader::Reader;
+use super::tls::{handshake_len, parse_client_hello, ClientHello};
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::Aes128Gcm;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+
+const VERSION_1: u32 = 0x0000_0001;
+const VERSION_2: u32 = 0x6b33_43cf;
+
+const SALT_V1: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+const SALT_V2: [u8; 20] = [
+    0x0d, 0xed, 0xe3, 0xde, 0xf7, 0x00, 0xa6, 0xdb, 0x81, 0x93, 0x81, 0xbe, 0x6e, 0x26, 0x9d, 0xcb,
+    0xf9, 0xbd, 0x2e, 0xd9,
+];
+const SALT_DRAFT_29: [u8; 20] = [
+    0xaf, 0xbf, 0xec, 0x28, 0x99, 0x93, 0xd2, 0x4c, 0x9e, 0x97, 0x86, 0xf1, 0x9c, 0x61, 0x11, 0xe0,
+    0x43, 0x90, 0xa8, 0x99,
+];
+const SALT_DRAFT_23: [u8; 20] = [
+    0xc3, 0xee, 0xf7, 0x12, 0xc7, 0x2e, 0xbb, 0x5a, 0x11, 0xa7, 0xd2, 0x43, 0x2b, 0xb4, 0x63, 0x65,
+    0xbe, 0xf9, 0xf5, 0x02,
+];
+
+/// Upper bound on the CRYPTO data buffered per flow while waiting for a full ClientHello
+const MAX_CRYPTO_BUFFER: usize = 16 * 1024;
+
+/// This struct contains what has been learnt about a QUIC flow
+/// ## Properties
+/// * `version`: Printable version name, e.g. `v1`, `v2`, `draft-29`
+/// * `hello`: ClientHello fields, once the Initial packets have been decrypted
+#[derive(Clone, Debug, Default)]
+pub(crate) struct QuicInfo {
+    pub version: String,
+    pub hello: Option<ClientHello>,
+}
+
+impl QuicInfo {
+    /// Application protocol label: `HTTP3` when the client offers an h3 ALPN, `QUIC` otherwise
+    pub fn label(&self) -> String {
+        let h3 = self.hello.as_ref().is_some_and(|h| {
+            h.alpn.iter().any(|p| p == "h3" || p.starts_with("h3-"))
+        });
+        if h3 {
+            String::from("HTTP3")
+        } else {
+            String::from("QUIC")
+        }
+    }
+}
+
+/// Keys protecting the client Initial packets
+struct InitialKeys {
+    key: [u8; 16],
+    iv: [u8; 12],
+    hp: [u8; 16],
+}
+
+/// Per-flow decryption state
+#[derive(Default)]
+struct QuicFlow {
+    keys: Option<(u32, Vec<u8>)>,
+    crypto: BTreeMap<u64, Vec<u8>>,
+    done: bool,
+}
+
+/// This struct keeps the QUIC state of every flow, indexed by connection number
+#[derive(Default)]
+pub(crate) struct QuicTracker {
+    flows: HashMap<usize, QuicFlow>,
+}
+
+impl QuicTracker {
+    /// Inspects a UDP payload belonging to connection `id`.
+    /// Returns `None` if the datagram does not start with a QUIC long header of a known version
+    pub fn inspect(&mut self, id: usize, datagram: &[u8]) -> Option<QuicInfo> {
+        let first = parse_long_header(datagram)?;
+        let mut info = QuicInfo {
+            version: version_name(first.version),
+            hello: None,
+        };
+        let flow = self.flows.entry(id).or_default();
+        if flow.done || salt(first.version).is_none() {
+            return Some(info);
+        }
+
+        // a datagram may carry several coalesced packets
+        let mut rest = datagram;
+        while let Some(header) = parse_long_header(rest) {
+            let packet_len = header.packet_len.min(rest.len());
+            if header.is_initial() {
+                flow.decrypt_initial(&header, &rest[..packet_len]);
+            }
+            rest = &rest[packet_len..];
+        }
+
+        if let Some(hello) = flow.client_hello() {
+            flow.done = true;
+            flow.crypto.clear();
+            info.hello = Some(hello);
+        }
+        Some(info)
+    }
+}
+
+impl QuicFlow {
+    fn decrypt_initial(&mut self, header: &LongHeader, packet: &[u8]) {
+        // the keys stay bound to the first DCID chosen by the client, even after it switches
+        // to the server-chosen connection ID
+        let mut candidates = vec![];
+        if let Some((version, dcid)) = &self.keys {
+            candidates.push((*version, dcid.clone()));
+        }
+        candidates.push((header.version, header.dcid.clone()));
+
+        for (version, dcid) in candidates {
+            let keys = match initial_keys(version, &dcid) {
+                Some(k) => k,
+                None => continue,
+            };
+            if let Some(plain) = unprotect(&keys, header, packet) {
+                if self.keys.is_none() {
+                    self.keys = Some((version, dcid));
+                }
+                self.add_frames(&plain);
+                return;
+            }
+        }
+    }
+
+    fn add_frames(&mut self, mut plain: &[u8]) {
+        while let Some((frame_type, rest)) = read_varint_slice(plain) {
+            plain = rest;
+            match frame_type {
+                // PADDING, PING
+                0x00 | 0x01 => (),
+                // ACK, ACK_ECN
+                0x02 | 0x03 => match skip_ack(plain, frame_type == 0x03) {
+                    Some(rest) => plain = rest,
+                    None => return,
+                },
+                // CRYPTO
+                0x06 => {
+                    let mut r = VarReader(Reader::new(plain));
+                    let (offset, len) = match (r.varint(), r.varint()) {
+                        (Some(o), Some(l)) => (o, l as usize),
+                        _ => return,
+                    };
+                    let data = match r.0.take(len) {
+                        Some(d) => d,
+                        None => return,
+                    };
+                    if (offset as usize) + len <= MAX_CRYPTO_BUFFER {
+                        self.crypto.insert(offset, data.to_vec());
+                    }
+                    plain = &plain[r.0.position()..];
+                }
+                // CONNECTION_CLOSE or anything not allowed in Initial packets
+                _ => return,
+            }
+        }
+    }
+
+    /// Rebuilds the contiguous CRYPTO stream and parses it once the ClientHello is complete
+    fn client_hello(&self) -> Option<ClientHello> {
+        let mut stream: Vec<u8> = vec![];
+        for (offset, data) in &self.crypto {
+            let offset = *offset as usize;
+            if offset > stream.len() {
+                break;
+            }
+            let end = offset + data.len();
+            if end > stream.len() {
+                stream.extend_from_slice(&data[stream.len() - offset..]);
+            }
+        }
+        let needed = handshake_len(&stream)?;
+        if stream.len() < needed {
+            return None;
+        }
+        parse_client_hello(&stream[..needed])
+    }
+}
+
+/// Fields of a QUIC long header needed to decrypt the packet
+struct LongHeader {
+    first: u8,
+    version: u32,
+    dcid: Vec<u8>,
+    /// Offset of the Packet Number field (Initial packets only)
+    pn_offset: usize,
+    /// Total length of this packet inside the datagram
+    packet_len: usize,
+}
+
+impl LongHeader {
+    fn is_initial(&self) -> bool {
+        let packet_type = (self.first >> 4) & 0x03;
+        match self.version {
+            VERSION_2 => packet_type == 1,
+            _ => packet_type == 0,
+        }
+    }
+}
+
+fn parse_long_header(data: &[u8]) -> Option<LongHeader> {
+    let mut r = VarReader(Reader::new(data));
+    let first = r.0.u8()?;
+    // Header Form and Fixed Bit must both be set
+    if first & 0xc0 != 0xc0 {
+        return None;
+    }
+    let version = r.0.u32()?;
+    if version == 0 || !is_known_version(version) {
+        return None;
+    }
+    let dcid_len = r.0.u8()? as usize;
+    if dcid_len > 20 {
+        return None;
+    }
+    let dcid = r.0.take(dcid_len)?.to_vec();
+    let scid_len = r.0.u8()? as usize;
+    if scid_len > 20 {
+        return None;
+    }
+    r.0.skip(scid_len)?;
+
+    let mut header = LongHeader {
+        first,
+        version,
+        dcid,
+        pn_offset: 0,
+        packet_len: data.len(),
+    };
+    let packet_type = (first >> 4) & 0x03;
+    let retry = match version {
+        VERSION_2 => packet_type == 0,
+        _ => packet_type == 3,
+    };
+    if retry {
+        return Some(header);
+    }
+    if header.is_initial() {
+        let token_len = r.varint()? as usize;
+        r.0.skip(token_len)?;
+    }
+    let length = r.varint()? as usize;
+    header.pn_offset = r.0.position();
+    header.packet_len = header.pn_offset + length;
+    if header.packet_len > data.len() {
+        return None;
+    }
+    Some(header)
+}
+
+fn is_known_version(version: u32) -> bool {
+    version == VERSION_1
+        || version == VERSION_2
+        || version >> 8 == 0x00ff_0000
+        || version >> 8 == 0x0051_3035 // Google QUIC "Q05x"
+        || version >> 8 == 0x0054_3035 // Google QUIC "T05x"
+}
+
+fn version_name(version: u32) -> String {
+    match version {
+        VERSION_1 => String::from("v1"),
+        VERSION_2 => String::from("v2"),
+        v if v >> 8 == 0x00ff_0000 => format!("draft-{}", v & 0xff),
+        v => String::from_utf8_lossy(&v.to_be_bytes()).into_owned(),
+    }
+}
+
+fn salt(version: u32) -> Option<&'static [u8; 20]> {
+    match version {
+        VERSION_1 => Some(&SALT_V1),
+        VERSION_2 => Some(&SALT_V2),
+        0xff00_001d..=0xff00_0020 => Some(&SALT_DRAFT_29),
+        0xff00_0017..=0xff00_001c => Some(&SALT_DRAFT_23),
+        _ => None,
+    }
+}
+
+/// Derives the client Initial keys for `version` from the client's Destination Connection ID
+fn initial_keys(version: u32, dcid: &[u8]) -> Option<InitialKeys> {
+    let (initial, _) = Hkdf::<Sha256>::extract(Some(salt(version)?), dcid);
+    let initial = Hkdf::<Sha256>::from_prk(&initial).ok()?;
+    let mut client_secret = [0u8; 32];
+    expand_label(&initial, b"client in", &mut client_secret)?;
+    let client = Hkdf::<Sha256>::from_prk(&client_secret).ok()?;
+
+    let prefix: &[u8] = if version == VERSION_2 { b"quicv2 " } else { b"quic " };
+    let mut keys = InitialKeys {
+        key: [0; 16],
+        iv: [0; 12],
+        hp: [0; 16],
+    };
+    expand_label(&client, &[prefix, b"key"].concat(), &mut keys.key)?;
+    expand_label(&client, &[prefix, b"iv"].concat(), &mut keys.iv)?;
+    expand_label(&client, &[prefix, b"hp"].concat(), &mut keys.hp)?;
+    Some(keys)
+}
+
+/// HKDF-Expand-Label from TLS 1.3 (RFC 8446, section 7.1) with an empty context
+fn expand_label(hkdf: &Hkdf<Sha256>, label: &[u8], out: &mut [u8]) -> Option<()> {
+    let full_label = [b"tls13 ", label].concat();
+    let mut info = vec![];
+    info.extend_from_slice(&(out.len() as u16).to_be_bytes());
+    info.push(full_label.len() as u8);
+    info.extend_from_slice(&full_label);
+    info.push(0);
+    hkdf.expand(&info, out).ok()
+}
+
+/// Removes header protection and decrypts the payload of an Initial packet,
+/// returning the plaintext frames
+fn unprotect(keys: &InitialKeys, header: &LongHeader, packet: &[u8]) -> Option<Vec<u8>> {
+    let sample_offset = header.pn_offset + 4;
+    if packet.len() < sample_offset + 16 {
+        return None;
+    }
+    let mut mask = GenericArray::clone_from_slice(&packet[sample_offset..sample_offset + 16]);
+    Aes128::new(GenericArray::from_slice(&keys.hp)).encrypt_block(&mut mask);
+
+    let mut head = packet[..header.pn_offset + 4].to_vec();
+    head[0] ^= mask[0] & 0x0f;
+    let pn_len = (head[0] & 0x03) as usize + 1;
+    head.truncate(header.pn_offset + pn_len);
+    let mut pn: u64 = 0;
+    for i in 0..pn_len {
+        head[header.pn_offset + i] ^= mask[1 + i];
+        pn = (pn << 8) | head[header.pn_offset + i] as u64;
+    }
+
+    let mut nonce = keys.iv;
+    for (i, b) in pn.to_be_bytes().iter().enumerate() {
+        nonce[4 + i] ^= b;
+    }
+    let cipher = Aes128Gcm::new(GenericArray::from_slice(&keys.key));
+    cipher
+        .decrypt(
+            GenericArray::from_slice(&nonce),
+            Payload {
+                msg: &packet[header.pn_offset + pn_len..],
+                aad: &head,
+            },
+        )
+        .ok()
+}
+
+/// Reader extension decoding QUIC variable-length integers
+struct VarReader<'a>(Reader<'a>);
+
+impl<'a> VarReader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let first = self.0.u8()?;
+        let len = 1usize << (first >> 6);
+        let mut value = (first & 0x3f) as u64;
+        for b in self.0.take(len - 1)? {
+            value = (value << 8) | *b as u64;
+        }
+        Some(value)
+    }
+}
+
+fn read_varint_slice(data: &[u8]) -> Option<(u64, &[u8])> {
+    let mut r = VarReader(Reader::new(data));
+    let value = r.varint()?;
+    Some((value, &data[r.0.position()..]))
+}
+
+fn skip_ack(data: &[u8], ecn: bool) -> Option<&[u8]> {
+    let mut r = VarReader(Reader::new(data));
+    // largest acknowledged, ack delay
+    r.varint()?;
+    r.varint()?;
+    let ranges = r.varint()?;
+    // first ack range, then (gap, range) pairs
+    r.varint()?;
+    for _ in 0..ranges {
+        r.varint()?;
+        r.varint()?;
+    }
+    if ecn {
+        for _ in 0..3 {
+            r.varint()?;
+        }
+    }
+    Some(&data[r.0.position()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tls::tests::client_hello;
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// Destination Connection ID of the examples of RFC 9001, appendix A
+    const DCID: &str = "8394c8f03e515708";
+
+    /// Client Initial packet of version 1 with a 4-byte packet number, protected as in RFC 9001, appendix A.2
+    fn initial(pn: u32, crypto_offset: u8, crypto: &[u8]) -> Vec<u8> {
+        // 1162 bytes of frames, as in the example, so that the datagram is 1200 bytes
+        let mut frames = vec![0x06, crypto_offset, 0x40 | (crypto.len() >> 8) as u8, crypto.len() as u8];
+        frames.extend_from_slice(crypto);
+        frames.resize(1162, 0);
+        let mut header = hex(&format!("c30000000108{}0000449e", DCID));
+        let pn_offset = header.len();
+        header.extend_from_slice(&pn.to_be_bytes());
+
+        let keys = initial_keys(VERSION_1, &hex(DCID)).unwrap();
+        let mut nonce = keys.iv;
+        for (i, b) in (pn as u64).to_be_bytes().iter().enumerate() {
+            nonce[4 + i] ^= b;
+        }
+        let cipher = Aes128Gcm::new(GenericArray::from_slice(&keys.key));
+        let sealed = cipher.encrypt(GenericArray::from_slice(&nonce), Payload { msg: &frames, aad: &header }).unwrap();
+        let mut packet = [header, sealed].concat();
+
+        let mut mask = GenericArray::clone_from_slice(&packet[pn_offset + 4..pn_offset + 20]);
+        Aes128::new(GenericArray::from_slice(&keys.hp)).encrypt_block(&mut mask);
+        packet[0] ^= mask[0] & 0x0f;
+        for i in 0..4 {
+            packet[pn_offset + i] ^= mask[1 + i];
+        }
+        packet
+    }
+
+    #[test]
+    fn rfc9001_client_initial_keys() {
+        let keys = initial_keys(VERSION_1, &hex(DCID)).unwrap();
+        assert_eq!(keys.key.to_vec(), hex("1f369613dd76d5467730efcbe3b1a22d"));
+        assert_eq!(keys.iv.to_vec(), hex("fa044b2f42a3fd3b46fb255c"));
+        assert_eq!(keys.hp.to_vec(), hex("9f50449e04a0e810283a1e9933adedd2"));
+    }
+
+    #[test]
+    fn rfc9369_client_initial_keys() {
+        let keys = initial_keys(VERSION_2, &hex(DCID)).unwrap();
+        assert_eq!(keys.key.to_vec(), hex("8b1a0bc121284290a29e0971b5cd045d"));
+        assert_eq!(keys.iv.to_vec(), hex("91f73e2351d8fa91660e909f"));
+        assert_eq!(keys.hp.to_vec(), hex("45b95e15235d6f45a6b19cbcb0294ba9"));
+    }
+
+    #[test]
+    fn rfc9001_header_protection_mask() {
+        // sample and mask of the client Initial packet of RFC 9001, appendix A.2
+        let keys = initial_keys(VERSION_1, &hex(DCID)).unwrap();
+        let mut mask = GenericArray::clone_from_slice(&hex("d1b1c98dd7689fb8ec11d242b123dc9b"));
+        Aes128::new(GenericArray::from_slice(&keys.hp)).encrypt_block(&mut mask);
+        assert_eq!(mask[..5].to_vec(), hex("437b9aec36"));
+    }
+
+    #[test]
+    fn client_initial() {
+        let packet = initial(2, 0, &client_hello(Some("example.com"), &["h3"]));
+        assert_eq!(packet.len(), 1200);
+        let header = parse_long_header(&packet).unwrap();
+        assert!(header.is_initial());
+        assert_eq!(header.dcid, hex(DCID));
+        assert_eq!(header.pn_offset, 18);
+        assert_eq!(header.packet_len, 1200);
+
+        let info = QuicTracker::default().inspect(0, &packet).unwrap();
+        assert_eq!(info.version, "v1");
+        let hello = info.hello.as_ref().unwrap();
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+        assert_eq!(info.label(), "HTTP3");
+    }
+
+    #[test]
+    fn client_hello_over_two_packets() {
+        let hello = client_hello(Some("example.com"), &["doq"]);
+        let (first, second) = hello.split_at(20);
+        let mut tracker = QuicTracker::default();
+        let info = tracker.inspect(3, &initial(0, 0, first)).unwrap();
+        assert_eq!(info.hello, None);
+        let info = tracker.inspect(3, &initial(1, first.len() as u8, second)).unwrap();
+        assert_eq!(info.hello.as_ref().and_then(|h| h.server_name.as_deref()), Some("example.com"));
+        assert_eq!(info.label(), "QUIC");
+    }
+
+    #[test]
+    fn not_quic() {
+        let mut tracker = QuicTracker::default();
+        // short header, then a DNS query
+        assert!(tracker.inspect(0, &hex("4000000001")).is_none());
+        assert!(tracker.inspect(0, &hex("123401000001000000000000")).is_none());
+        // unknown version
+        assert!(tracker.inspect(0, &hex("c01a2a3a4a0800")).is_none());
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(version_name(VERSION_2), "v2");
+        assert_eq!(version_name(0xff00_001d), "draft-29");
+        assert_eq!(version_name(0x5130_3530), "Q050");
+        assert!(salt(0x5130_3530).is_none());
+    }
+
+    #[test]
+    fn varints() {
+        // examples of RFC 9000, appendix A.1
+        for (data, value) in [("c2197c5eff14e88c", 151_288_809_941_952_652), ("9d7f3e7d", 494_878_333), ("7bbd", 15_293), ("25", 37)] {
+            let data = hex(data);
+            assert_eq!(read_varint_slice(&data), Some((value, &[][..])));
+        }
+        assert_eq!(read_varint_slice(&hex("7b")), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! Bounds-checked big-endian reader shared by the protocol decoders.
+
+/// Simple bounds-checked reader over a byte slice
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.remaining() < n {
+            return None;
+        }
+        let s = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Some(s)
+    }
+
+    pub fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|b| ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_endian_fields() {
+        let data = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a];
+        let mut r = Reader::new(&data);
+        assert_eq!(r.u8(), Some(0x01));
+        assert_eq!(r.u16(), Some(0x0203));
+        assert_eq!(r.u24(), Some(0x040506));
+        assert_eq!(r.u32(), Some(0x0708090a));
+        assert_eq!((r.position(), r.remaining()), (10, 0));
+        assert_eq!(r.u8(), None);
+    }
+
+    #[test]
+    fn bounds() {
+        let mut r = Reader::new(&[1, 2, 3]);
+        assert_eq!(r.u32(), None);
+        // a failed read consumes nothing
+        assert_eq!(r.position(), 0);
+        assert_eq!(r.skip(1), Some(()));
+        assert_eq!(r.take(2), Some(&[2u8, 3][..]));
+        assert_eq!(r.take(0), Some(&[][..]));
+        assert_eq!(r.skip(1), None);
+    }
+}
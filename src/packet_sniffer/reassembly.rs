@@ -0,0 +1,253 @@
+//! TCP stream reassembly: orders the segments of each direction, dropping retransmitted and
+//! overlapping bytes, and keeps the data not parsed yet by the decoders.
+use std::collections::{BTreeMap, HashMap};
+
+/// Out-of-order bytes kept per direction; past this limit the missing data is given up
+const MAX_PENDING: usize = 256 * 1024;
+/// Bytes kept per direction waiting for a decoder to find a complete message
+const MAX_UNPARSED: usize = 64 * 1024;
+
+/// This struct describes a TCP segment
+/// ## Properties
+/// * `seq`: Sequence number
+/// * `syn`: SYN flag (the sequence number is the initial one)
+/// * `payload`: TCP payload
+pub(crate) struct Segment<'a> {
+    pub seq: u32,
+    pub syn: bool,
+    pub payload: &'a [u8],
+}
+
+/// State of one direction of a connection
+/// ## Properties
+/// * `next_seq`: Sequence number of the next byte expected, `None` until the first segment
+/// * `offset`: Stream offset of the next byte expected
+/// * `pending`: Segments received ahead of `next_seq`, by stream offset
+/// * `pending_bytes`: Total size of `pending`
+/// * `unparsed`: In-order bytes not consumed by the decoders yet
+/// * `gaps`: Number of times missing data was given up
+#[derive(Clone, Debug, Default)]
+struct HalfStream {
+    next_seq: Option<u32>,
+    offset: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: usize,
+    unparsed: Vec<u8>,
+    gaps: u32,
+}
+
+impl HalfStream {
+    /// Adds a segment, returning the bytes that became contiguous
+    fn add(&mut self, segment: &Segment) -> Vec<u8> {
+        let mut seq = segment.seq;
+        if segment.syn {
+            // the SYN takes a sequence number but carries no stream byte
+            seq = seq.wrapping_add(1);
+            if self.next_seq.is_none() {
+                self.next_seq = Some(seq);
+            }
+        }
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        if segment.payload.is_empty() {
+            return vec![];
+        }
+        // relative position, correct across sequence number wrap-around
+        let delta = seq.wrapping_sub(next_seq) as i32 as i64;
+        let start = self.offset as i64 + delta;
+        if start < 0 {
+            // retransmission of bytes before the start of the stream
+            let skip = (-start) as usize;
+            if skip >= segment.payload.len() {
+                return vec![];
+            }
+            self.store(0, &segment.payload[skip..]);
+        } else {
+            self.store(start as u64, segment.payload);
+        }
+        self.drain()
+    }
+
+    /// Keeps a segment starting at stream offset `start`; the first copy of a byte wins
+    fn store(&mut self, start: u64, data: &[u8]) {
+        let end = start + data.len() as u64;
+        if end <= self.offset {
+            return;
+        }
+        let (start, data) = if start < self.offset {
+            (self.offset, &data[(self.offset - start) as usize..])
+        } else {
+            (start, data)
+        };
+        if let Some(old) = self.pending.get(&start) {
+            if old.len() >= data.len() {
+                return;
+            }
+            self.pending_bytes -= old.len();
+        }
+        self.pending_bytes += data.len();
+        self.pending.insert(start, data.to_vec());
+
+        // a hole that never gets filled must not block the stream forever
+        if self.pending_bytes > MAX_PENDING {
+            if let Some(&first) = self.pending.keys().next() {
+                if first > self.offset {
+                    self.gaps += 1;
+                    self.advance(first - self.offset);
+                }
+            }
+        }
+    }
+
+    /// Moves the contiguous pending segments to the stream
+    fn drain(&mut self) -> Vec<u8> {
+        let mut data = vec![];
+        while let Some((&start, _)) = self.pending.iter().next() {
+            if start > self.offset {
+                break;
+            }
+            let chunk = self.pending.remove(&start).unwrap_or_default();
+            self.pending_bytes -= chunk.len();
+            let skip = (self.offset - start) as usize;
+            if skip < chunk.len() {
+                data.extend_from_slice(&chunk[skip..]);
+                self.advance((chunk.len() - skip) as u64);
+            }
+        }
+        data
+    }
+
+    fn advance(&mut self, len: u64) {
+        self.offset += len;
+        if let Some(next_seq) = self.next_seq.as_mut() {
+            *next_seq = next_seq.wrapping_add(len as u32);
+        }
+    }
+}
+
+/// This struct reassembles the TCP connections, indexed by connection number.
+/// Direction 0 is the one of the first packet of the connection, direction 1 the opposite one
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TcpReassembler {
+    flows: HashMap<usize, [HalfStream; 2]>,
+}
+
+impl TcpReassembler {
+    /// Adds a segment of connection `id` sent in direction `dir`, returning the bytes that became contiguous
+    pub fn add(&mut self, id: usize, dir: usize, segment: &Segment) -> Vec<u8> {
+        self.flows.entry(id).or_default()[dir].add(segment)
+    }
+
+    /// Takes the bytes of connection `id`, direction `dir`, not consumed by the decoders yet
+    pub fn take_unparsed(&mut self, id: usize, dir: usize) -> Vec<u8> {
+        self.flows.get_mut(&id).map(|f| std::mem::take(&mut f[dir].unparsed)).unwrap_or_default()
+    }
+
+    /// Gives back the bytes a decoder could not consume yet, waiting for the rest of the message
+    pub fn keep_unparsed(&mut self, id: usize, dir: usize, data: Vec<u8>) {
+        // a message this long is not going to be decoded
+        if data.len() > MAX_UNPARSED {
+            return;
+        }
+        if let Some(flow) = self.flows.get_mut(&id) {
+            flow[dir].unparsed = data;
+        }
+    }
+
+    /// Number of times missing data of connection `id` was given up, in both directions
+    pub fn gaps(&self, id: usize) -> u32 {
+        self.flows.get(&id).map_or(0, |f| f[0].gaps + f[1].gaps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(seq: u32, payload: &[u8]) -> Segment<'_> {
+        Segment { seq, syn: false, payload }
+    }
+
+    /// Reassembler of connection 0 whose direction 0 starts with a SYN with sequence number `isn`
+    fn opened(isn: u32) -> TcpReassembler {
+        let mut r = TcpReassembler::default();
+        assert!(r.add(0, 0, &Segment { seq: isn, syn: true, payload: &[] }).is_empty());
+        r
+    }
+
+    #[test]
+    fn in_order() {
+        let mut r = opened(1000);
+        assert_eq!(r.add(0, 0, &segment(1001, b"abc")), b"abc");
+        assert_eq!(r.add(0, 0, &segment(1004, b"def")), b"def");
+        //the other direction is independent
+        assert_eq!(r.add(0, 1, &segment(7, b"xyz")), b"xyz");
+        assert_eq!(r.gaps(0), 0);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut r = opened(1000);
+        assert!(r.add(0, 0, &segment(1007, b"ghi")).is_empty());
+        assert!(r.add(0, 0, &segment(1004, b"def")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(1001, b"abc")), b"abcdefghi");
+    }
+
+    #[test]
+    fn retransmissions_and_overlaps() {
+        let mut r = opened(1000);
+        assert_eq!(r.add(0, 0, &segment(1001, b"abcdef")), b"abcdef");
+        assert!(r.add(0, 0, &segment(1001, b"abc")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(1005, b"efGH")), b"GH");
+        //the first copy of a byte wins
+        assert!(r.add(0, 0, &segment(1012, b"kl")).is_empty());
+        assert!(r.add(0, 0, &segment(1012, b"K")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(1009, b"IJK")), b"IJKkl");
+    }
+
+    #[test]
+    fn before_the_first_segment() {
+        //no SYN: the stream starts with the first segment seen
+        let mut r = TcpReassembler::default();
+        assert_eq!(r.add(0, 0, &segment(5000, b"xyz")), b"xyz");
+        assert!(r.add(0, 0, &segment(4998, b"ab")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(4998, b"abxyzQ")), b"Q");
+    }
+
+    #[test]
+    fn sequence_wrap_around() {
+        let mut r = opened(u32::MAX - 1);
+        assert_eq!(r.add(0, 0, &segment(u32::MAX, b"ab")), b"ab");
+        assert!(r.add(0, 0, &segment(3, b"ef")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(1, b"cd")), b"cdef");
+    }
+
+    #[test]
+    fn gap_given_up() {
+        let mut r = opened(0);
+        assert_eq!(r.add(0, 0, &segment(1, b"abc")), b"abc");
+        //bytes 4..14 never arrive: the stream waits for them until too much data is pending
+        let ahead = vec![b'x'; MAX_PENDING / 2];
+        assert!(r.add(0, 0, &segment(14, &ahead)).is_empty());
+        let next = 14 + ahead.len() as u32;
+        assert!(r.add(0, 0, &segment(next, &ahead)).is_empty());
+        assert_eq!(r.gaps(0), 0);
+        let next = next + ahead.len() as u32;
+        assert_eq!(r.add(0, 0, &segment(next, b"end")).len(), MAX_PENDING + 3);
+        assert_eq!(r.gaps(0), 1);
+        //the missing bytes are dropped when they arrive at last
+        assert!(r.add(0, 0, &segment(4, b"late")).is_empty());
+        assert_eq!(r.add(0, 0, &segment(next + 3, b"more")), b"more");
+    }
+
+    #[test]
+    fn unparsed() {
+        let mut r = opened(0);
+        r.add(0, 0, &segment(1, b"partial"));
+        r.keep_unparsed(0, 0, b"partial".to_vec());
+        assert!(r.take_unparsed(0, 1).is_empty());
+        assert_eq!(r.take_unparsed(0, 0), b"partial");
+        assert!(r.take_unparsed(0, 0).is_empty());
+        r.keep_unparsed(0, 0, vec![0; MAX_UNPARSED + 1]);
+        assert!(r.take_unparsed(0, 0).is_empty());
+    }
+}
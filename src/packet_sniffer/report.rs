@@ -0,0 +1,337 @@
+//! Output policies of the report file: overwrite or append, timestamp-templated names, rotation with retention
+//! and compression of the rotated files.
+use chrono::format::{Item, StrftimeItems};
+use chrono::Local;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// This enum provides the compressions of the rotated report files
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression \"{}\": use none, gzip or zstd", s)),
+        }
+    }
+}
+
+/// This struct defines how the report file is written
+/// ## Properties
+/// * `append`: `false` to overwrite the file every time the report is written, `true` to append a new section
+/// * `templated`: `true` if the file name has `strftime` fields to replace (see `valid_template`), `false` to use it as it is
+/// * `rotate_size`: Size (bytes) that makes the file rotate before the next report, 0 = never
+/// * `rotate_time`: Age (secs) that makes the file rotate before the next report, 0 = never
+/// * `keep`: Rotated files kept, the oldest ones are deleted, 0 = all of them
+/// * `compression`: Compression of the rotated files
+#[derive(Clone, Debug, Default)]
+pub struct ReportPolicy {
+    pub append: bool,
+    pub templated: bool,
+    pub rotate_size: u64,
+    pub rotate_time: u64,
+    pub keep: usize,
+    pub compression: Compression,
+}
+
+/// `true` if all the `strftime` fields of `template` are valid
+pub(crate) fn valid_template(template: &str) -> bool {
+    StrftimeItems::new(template).all(|item| item != Item::Error)
+}
+
+/// This struct writes the report file following a `ReportPolicy`
+/// ## Properties
+/// * `template`: File name, with `strftime` fields (e.g. `results-%Y%m%d-%H%M%S.txt`) replaced by the time the
+///   file is started if the policy is templated
+/// * `policy`: Output policy
+/// * `current`: File being written and the time it was started
+/// * `rotated`: Files rotated during the capture, oldest first
+pub(crate) struct ReportFile {
+    template: String,
+    policy: ReportPolicy,
+    current: Option<(PathBuf, Instant)>,
+    rotated: VecDeque<PathBuf>,
+}
+
+impl ReportFile {
+    pub fn new(template: &str, policy: ReportPolicy) -> Self {
+        ReportFile { template: template.to_string(), policy, current: None, rotated: VecDeque::new() }
+    }
+
+    /// Writes a report with `content`, returning the file written
+    pub fn write(&mut self, content: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<PathBuf> {
+        let templated = self.policy.templated;
+        if let Some((path, started)) = &self.current {
+            let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            let rotate = (self.policy.rotate_size > 0 && size >= self.policy.rotate_size)
+                || (self.policy.rotate_time > 0 && started.elapsed().as_secs() >= self.policy.rotate_time)
+                //overwriting a templated name starts a new file every time
+                || (templated && !self.policy.append);
+            if rotate {
+                let path = path.clone();
+                self.current = None;
+                self.rotate(&path, templated)?;
+            }
+        }
+        let path = match &self.current {
+            Some((path, _)) => path.clone(),
+            None => {
+                let path = PathBuf::from(self.file_name()?);
+                self.current = Some((path.clone(), Instant::now()));
+                path
+            },
+        };
+
+        let file = if self.policy.append {
+            OpenOptions::new().create(true).append(true).open(&path)?
+        } else {
+            File::create(&path)?
+        };
+        let mut writer = BufWriter::new(file);
+        if self.policy.append {
+            writeln!(writer, "\n===== Report written at {} =====\n", Local::now().format("%Y/%m/%d %H:%M:%S"))?;
+        }
+        content(&mut writer)?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    /// Name of a new file: the template, with its fields replaced by the current time if templated
+    fn file_name(&self) -> io::Result<String> {
+        if !self.policy.templated {
+            return Ok(self.template.clone());
+        }
+        let mut name = String::new();
+        write!(name, "{}", Local::now().format(&self.template))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid file name template {}", self.template)))?;
+        Ok(name)
+    }
+
+    /// Moves the current file aside, compresses it and deletes the oldest rotated files beyond `keep`
+    fn rotate(&mut self, path: &Path, templated: bool) -> io::Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        //a templated name is already unique, the others get the rotation time
+        let mut rotated = if templated {
+            path.to_path_buf()
+        } else {
+            let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            let mut name = format!("{}-{}", stem, Local::now().format("%Y%m%d-%H%M%S%.3f"));
+            if let Some(ext) = path.extension() {
+                name = format!("{}.{}", name, ext.to_string_lossy());
+            }
+            let rotated = path.with_file_name(name);
+            fs::rename(path, &rotated)?;
+            rotated
+        };
+        let ext = match self.policy.compression {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        };
+        if let Some(ext) = ext {
+            let mut name = rotated.clone().into_os_string();
+            name.push(".");
+            name.push(ext);
+            let compressed = PathBuf::from(name);
+            let mut input = File::open(&rotated)?;
+            let output = File::create(&compressed)?;
+            match self.policy.compression {
+                Compression::Gzip => {
+                    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                    io::copy(&mut input, &mut encoder)?;
+                    encoder.finish()?;
+                },
+                _ => zstd::stream::copy_encode(&mut input, output, 0)?,
+            }
+            fs::remove_file(&rotated)?;
+            rotated = compressed;
+        }
+        self.rotated.push_back(rotated);
+        while self.policy.keep > 0 && self.rotated.len() > self.policy.keep {
+            if let Some(old) = self.rotated.pop_front() {
+                let _ = fs::remove_file(old);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the content of a report
+pub(crate) type Render = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// A report to write
+/// ## Properties
+/// * `render`: Writes the content of the report
+/// * `file`: File to write, `None` for the report file
+/// * `done`: Called with the path of the file written, or the error
+pub(crate) struct ReportJob {
+    pub render: Render,
+    pub file: Option<String>,
+    pub done: Box<dyn FnOnce(io::Result<PathBuf>) + Send>,
+}
+
+/// Renders and writes a report, to the report file following its policy or to a file of its own
+pub(crate) fn run(job: ReportJob, report: &Mutex<ReportFile>) {
+    let res = match job.file {
+        None => report.lock().unwrap().write(job.render),
+        Some(file) => File::create(&file).and_then(|f| {
+            let mut writer = BufWriter::new(f);
+            (job.render)(&mut writer)?;
+            writer.flush()
+        }).map(|_| PathBuf::from(file)),
+    };
+    (job.done)(res);
+}
+
+/// Renders and writes the reports sent on the returned channel, in order, from a thread of its own: the capture never
+/// waits for the rendering, the disk, the rotation or the compression. The thread ends when the channel is dropped
+pub(crate) fn spawn_writer(file: Arc<Mutex<ReportFile>>) -> (Sender<ReportJob>, JoinHandle<()>) {
+    let (sender, receiver) = channel::<ReportJob>();
+    let handle = thread::spawn(move || {
+        for job in receiver {
+            run(job, &file);
+        }
+    });
+    (sender, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_fields() {
+        assert!(valid_template("results-%Y%m%d-%H%M%S.txt"));
+        assert!(valid_template("results.txt"));
+        assert!(!valid_template("results-100%.txt"));
+        assert!(!valid_template("a%Q.txt"));
+    }
+
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("packet_sniffer-report-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Names of the files in `dir`, sorted
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    fn text(s: &'static str) -> impl FnOnce(&mut dyn Write) -> io::Result<()> {
+        move |w: &mut dyn Write| write!(w, "{}", s)
+    }
+
+    #[test]
+    fn overwrite_and_append() {
+        let dir = test_dir("append");
+        let path = dir.join("results.txt");
+        let mut report = ReportFile::new(path.to_str().unwrap(), ReportPolicy::default());
+        report.write(text("first")).unwrap();
+        assert_eq!(report.write(text("second")).unwrap(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        let mut report = ReportFile::new(path.to_str().unwrap(), ReportPolicy { append: true, ..Default::default() });
+        report.write(text("third")).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("second\n===== Report written at "));
+        assert!(content.ends_with("=====\n\nthird"));
+        assert_eq!(files(&dir), vec!["results.txt"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn size_rotation_and_retention() {
+        let dir = test_dir("rotation");
+        let path = dir.join("results.txt");
+        let policy = ReportPolicy { rotate_size: 1, keep: 2, compression: Compression::Gzip, ..Default::default() };
+        let mut report = ReportFile::new(path.to_str().unwrap(), policy);
+        for content in ["one", "two", "three", "four"] {
+            report.write(text(content)).unwrap();
+            // rotated names have millisecond resolution
+            thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "four");
+        let names = files(&dir);
+        assert_eq!(names.len(), 3);
+        let rotated: Vec<&String> = names.iter().filter(|n| n.starts_with("results-") && n.ends_with(".txt.gz")).collect();
+        assert_eq!(rotated.len(), 2);
+        // the oldest one was deleted
+        let mut decoded = String::new();
+        io::Read::read_to_string(&mut flate2::read::GzDecoder::new(File::open(dir.join(rotated[0])).unwrap()), &mut decoded).unwrap();
+        assert_eq!(decoded, "two");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn templated_names() {
+        let dir = test_dir("templated");
+        let template = dir.join("results-%H%M%S%.6f.txt");
+        let policy = ReportPolicy { templated: true, compression: Compression::Zstd, ..Default::default() };
+        let mut report = ReportFile::new(template.to_str().unwrap(), policy);
+        let first = report.write(text("one")).unwrap();
+        thread::sleep(std::time::Duration::from_millis(2));
+        let second = report.write(text("two")).unwrap();
+        assert_ne!(first, second);
+        assert!(!first.to_string_lossy().contains('%'));
+        // overwriting a templated name starts a new file, the previous one is compressed in place
+        let mut compressed = first.into_os_string();
+        compressed.push(".zst");
+        assert_eq!(zstd::stream::decode_all(File::open(compressed).unwrap()).unwrap(), b"one");
+        assert_eq!(fs::read_to_string(second).unwrap(), "two");
+        assert_eq!(files(&dir).len(), 2);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn writer_thread() {
+        let dir = test_dir("writer");
+        let report = Arc::new(Mutex::new(ReportFile::new(dir.join("results.txt").to_str().unwrap(), ReportPolicy::default())));
+        let (sender, handle) = spawn_writer(report);
+        let (done, written) = channel();
+        for file in [None, Some(dir.join("dump.txt").to_string_lossy().into_owned()), Some(dir.join("missing/dump.txt").to_string_lossy().into_owned())] {
+            let done = done.clone();
+            sender.send(ReportJob { render: Box::new(text("report")), file, done: Box::new(move |res| done.send(res).unwrap()) }).unwrap();
+        }
+        drop(sender);
+        handle.join().unwrap();
+        let results: Vec<io::Result<PathBuf>> = written.try_iter().collect();
+        assert_eq!(results[0].as_ref().unwrap(), &dir.join("results.txt"));
+        assert_eq!(results[1].as_ref().unwrap(), &dir.join("dump.txt"));
+        assert!(results[2].is_err());
+        assert_eq!(fs::read_to_string(dir.join("dump.txt")).unwrap(), "report");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn compressions() {
+        assert_eq!("none".parse(), Ok(Compression::None));
+        assert_eq!("gz".parse(), Ok(Compression::Gzip));
+        assert_eq!("zstd".parse(), Ok(Compression::Zstd));
+        assert!("xz".parse::<Compression>().is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Requests managing a running capture, served by the control socket and by the HTTP API.
+//!
+//! Pause, resume and stop act on the synchronization variable of the capture, the other requests need the capture state
+//! and are answered by the capture loop. Replies are JSON objects with `"ok"` set to `true`, or to `false` along with
+//! an `"error"` message.
+use super::SyncStruct;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+
+/// Time waited for the capture loop to answer a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// This enum provides the requests accepted on the control socket and, through its routes, by the HTTP API
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub(crate) enum Request {
+    Pause,
+    Resume,
+    Stop,
+    /// Writes the report now, to the report file or to `file`
+    Snapshot { file: Option<String> },
+    Status,
+    /// Returns the connection table
+    Connections,
+    /// Returns the Prometheus metrics, as text in `"metrics"`
+    Metrics,
+    /// Replaces the capture filter, or shows it if `filter` is missing
+    Filter { filter: Option<String> },
+    /// Writes the packets kept for a connection (see `Sniffer::set_flow_buffer`) to `file`, by default `connection<n>.pcap`
+    Extract { connection: usize, file: Option<String> },
+}
+
+/// A request that needs the capture state, answered by the capture loop through `reply`
+pub(crate) struct Query {
+    pub request: Request,
+    pub reply: Sender<Value>,
+}
+
+/// Answers a request: pause, resume and stop act on `waiter`, the others are passed to the capture loop
+pub(crate) fn answer(request: Request, waiter: &SyncStruct, queries: &Sender<Query>) -> Value {
+    match request {
+        Request::Pause => match waiter.set_pause(true) {
+            true => ok(json!({})),
+            false => error("The capture is already paused"),
+        },
+        Request::Resume => match waiter.set_pause(false) {
+            true => ok(json!({})),
+            false => error("The capture is not paused"),
+        },
+        Request::Stop => {
+            waiter.stop();
+            ok(json!({}))
+        },
+        request => {
+            let (reply, answer) = channel();
+            match queries.send(Query { request, reply }) {
+                Ok(_) => answer.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| error("The capture is over")),
+                Err(_) => error("The capture is over"),
+            }
+        },
+    }
+}
+
+/// Successful reply, with the fields of `fields`
+pub(crate) fn ok(fields: Value) -> Value {
+    let mut reply = json!({ "ok": true });
+    if let (Some(reply), Value::Object(fields)) = (reply.as_object_mut(), fields) {
+        reply.extend(fields);
+    }
+    reply
+}
+
+/// Failed reply
+pub(crate) fn error(message: &str) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use super::super::ExecState;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::Instant;
+
+    pub(crate) fn waiter() -> Arc<SyncStruct> {
+        let state = ExecState { time_interval: 60.0, start_time: Instant::now(), pause_time: Instant::now(), pause: false };
+        Arc::new(SyncStruct { state: Mutex::new(state), cv: Condvar::new() })
+    }
+
+    /// Answers every query with its request, as the capture loop would with the capture state
+    pub(crate) fn capture_loop() -> Sender<Query> {
+        let (queries, received) = channel::<Query>();
+        thread::spawn(move || {
+            for query in received {
+                let _ = query.reply.send(ok(json!({ "request": format!("{:?}", query.request) })));
+            }
+        });
+        queries
+    }
+
+    #[test]
+    fn requests() {
+        let parse = |line: &str| serde_json::from_str::<Request>(line).map(|r| format!("{:?}", r));
+        assert_eq!(parse(r#"{"cmd":"pause"}"#).unwrap(), "Pause");
+        assert_eq!(parse(r#"{"cmd":"snapshot"}"#).unwrap(), "Snapshot { file: None }");
+        assert_eq!(parse(r#"{"cmd":"snapshot","file":"now.txt"}"#).unwrap(), "Snapshot { file: Some(\"now.txt\") }");
+        assert_eq!(parse(r#"{"cmd":"filter","filter":"tcp port 80"}"#).unwrap(), "Filter { filter: Some(\"tcp port 80\") }");
+        assert_eq!(parse(r#"{"cmd":"extract","connection":3}"#).unwrap(), "Extract { connection: 3, file: None }");
+        assert!(parse(r#"{"cmd":"extract"}"#).is_err());
+        assert!(parse(r#"{"cmd":"reboot"}"#).is_err());
+        assert!(parse(r#"{"file":"now.txt"}"#).is_err());
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(ok(json!({})), json!({ "ok": true }));
+        assert_eq!(ok(json!({ "packets": 3 })), json!({ "ok": true, "packets": 3 }));
+        assert_eq!(error("No such connection"), json!({ "ok": false, "error": "No such connection" }));
+    }
+
+    #[test]
+    fn answers() {
+        let waiter = waiter();
+        let queries = capture_loop();
+        assert_eq!(answer(Request::Pause, &waiter, &queries), ok(json!({})));
+        assert_eq!(answer(Request::Pause, &waiter, &queries), error("The capture is already paused"));
+        assert_eq!(answer(Request::Resume, &waiter, &queries), ok(json!({})));
+        assert_eq!(answer(Request::Resume, &waiter, &queries), error("The capture is not paused"));
+        assert_eq!(answer(Request::Status, &waiter, &queries), ok(json!({ "request": "Status" })));
+        assert_eq!(answer(Request::Stop, &waiter, &queries), ok(json!({})));
+        assert_eq!(waiter.state.lock().unwrap().time_interval, 0.0);
+
+        let (queries, received) = channel();
+        drop(received);
+        assert_eq!(answer(Request::Metrics, &waiter, &queries), error("The capture is over"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Time-bucketed traffic series per interface, application protocol and connection, exported as CSV or JSON.
+use chrono::{Local, TimeZone};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Result, Write};
+
+/// Packets and bytes of a bucket, split by direction
+#[derive(Clone, Copy, Debug, Default)]
+struct Counters {
+    packets_in: u64,
+    bytes_in: u64,
+    packets_out: u64,
+    bytes_out: u64,
+}
+
+impl Counters {
+    fn add(&mut self, bytes: u32, outgoing: bool) {
+        if outgoing {
+            self.packets_out += 1;
+            self.bytes_out += bytes as u64;
+        } else {
+            self.packets_in += 1;
+            self.bytes_in += bytes as u64;
+        }
+    }
+}
+
+/// Counters of each bucket, by bucket start (us since the epoch)
+type Series = BTreeMap<i64, Counters>;
+
+/// This struct keeps the traffic time series
+/// ## Properties
+/// * `bucket`: Bucket length (us)
+/// * `top`: Number of connections exported, the ones with the most bytes
+/// * `interfaces`: Series of each capture interface
+/// * `protocols`: Series of each application protocol
+/// * `connections`: Series of each connection, by connection index
+#[derive(Clone, Debug)]
+pub(crate) struct TimeSeries {
+    bucket: i64,
+    top: usize,
+    interfaces: BTreeMap<String, Series>,
+    protocols: BTreeMap<String, Series>,
+    connections: HashMap<usize, Series>,
+}
+
+impl TimeSeries {
+    /// ## Parameters
+    /// * `bucket`: Bucket length (us)
+    /// * `top`: Number of connections exported
+    pub fn new(bucket: i64, top: usize) -> Self {
+        TimeSeries {
+            bucket: bucket.max(1),
+            top,
+            interfaces: BTreeMap::new(),
+            protocols: BTreeMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    /// Accounts a packet
+    /// ## Parameters
+    /// * `interface`: Capture interface
+    /// * `app_prot`: Application protocol of the connection
+    /// * `connection`: Connection index
+    /// * `ts`: Packet timestamp (us since the epoch)
+    /// * `bytes`: Packet length
+    /// * `outgoing`: `true` if the packet was sent by an address of the interface
+    pub fn add(&mut self, interface: &str, app_prot: &str, connection: usize, ts: i64, bytes: u32, outgoing: bool) {
+        let start = ts.div_euclid(self.bucket) * self.bucket;
+        for series in [
+            self.interfaces.entry(interface.to_string()).or_default(),
+            self.protocols.entry(app_prot.to_string()).or_default(),
+            self.connections.entry(connection).or_default(),
+        ] {
+            series.entry(start).or_default().add(bytes, outgoing);
+        }
+    }
+
+    /// Connections with the most bytes, at most `top`
+    fn top_connections(&self) -> Vec<(usize, &Series)> {
+        let total = |s: &Series| s.values().map(|c| c.bytes_in + c.bytes_out).sum::<u64>();
+        let mut connections: Vec<(usize, &Series)> = self.connections.iter().map(|(i, s)| (*i, s)).collect();
+        connections.sort_by(|a, b| total(b.1).cmp(&total(a.1)).then(a.0.cmp(&b.0)));
+        connections.truncate(self.top);
+        connections
+    }
+
+    /// All the exported series as (kind, key, series), connections are numbered as in the report
+    fn all(&self) -> Vec<(&str, String, &Series)> {
+        let mut all: Vec<(&str, String, &Series)> = vec![];
+        all.extend(self.interfaces.iter().map(|(k, s)| ("interface", k.clone(), s)));
+        all.extend(self.protocols.iter().map(|(k, s)| ("app_prot", k.clone(), s)));
+        all.extend(self.top_connections().into_iter().map(|(i, s)| ("connection", (i + 1).to_string(), s)));
+        all
+    }
+
+    /// Writes one row per series and bucket: `series,key,bucket_start,packets_in,bytes_in,packets_out,bytes_out`
+    pub fn write_csv(&self, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, "series,key,bucket_start,packets_in,bytes_in,packets_out,bytes_out")?;
+        for (kind, key, series) in self.all() {
+            for (start, c) in series {
+                writeln!(writer, "{},\"{}\",{},{},{},{},{}", kind, key.replace('"', "\"\""), time(*start),
+                    c.packets_in, c.bytes_in, c.packets_out, c.bytes_out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the series as a JSON document grouped by kind and key
+    pub fn write_json(&self, writer: &mut dyn Write) -> Result<()> {
+        let mut groups: BTreeMap<&str, serde_json::Map<String, Value>> = BTreeMap::new();
+        for (kind, key, series) in self.all() {
+            let buckets: Vec<Value> = series.iter().map(|(start, c)| json!({
+                "bucket_start": time(*start),
+                "packets_in": c.packets_in,
+                "bytes_in": c.bytes_in,
+                "packets_out": c.packets_out,
+                "bytes_out": c.bytes_out,
+            })).collect();
+            groups.entry(kind).or_default().insert(key, Value::from(buckets));
+        }
+        let doc = json!({
+            "bucket_secs": self.bucket as f64 / 1_000_000.0,
+            "interfaces": groups.remove("interface").unwrap_or_default(),
+            "app_prots": groups.remove("app_prot").unwrap_or_default(),
+            "connections": groups.remove("connection").unwrap_or_default(),
+        });
+        serde_json::to_writer_pretty(&mut *writer, &doc)?;
+        writeln!(writer)
+    }
+}
+
+/// Formats a bucket start as local time with microseconds
+fn time(us: i64) -> String {
+    match Local.timestamp_micros(us).single() {
+        Some(t) => t.format("%Y-%m-%dT%H:%M:%S%.6f%:z").to_string(),
+        None => us.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three connections over two 1 s buckets: #2 has the most bytes, then #1, then #3
+    fn series() -> TimeSeries {
+        let mut s = TimeSeries::new(1_000_000, 2);
+        s.add("eth0", "HTTP", 0, 10_000_000, 100, true);
+        s.add("eth0", "HTTP", 0, 10_999_999, 50, false);
+        s.add("eth0", "DNS", 1, 11_000_000, 500, false);
+        s.add("eth1", "DNS", 2, 11_500_000, 10, true);
+        s
+    }
+
+    #[test]
+    fn buckets() {
+        let s = series();
+        let eth0 = &s.interfaces["eth0"];
+        assert_eq!(eth0.keys().copied().collect::<Vec<_>>(), vec![10_000_000, 11_000_000]);
+        let first = eth0[&10_000_000];
+        assert_eq!((first.packets_in, first.bytes_in, first.packets_out, first.bytes_out), (1, 50, 1, 100));
+        let dns = &s.protocols["DNS"][&11_000_000];
+        assert_eq!((dns.packets_in, dns.bytes_in, dns.packets_out, dns.bytes_out), (1, 500, 1, 10));
+        // a zero bucket length is taken as 1 us
+        assert_eq!(TimeSeries::new(0, 1).bucket, 1);
+    }
+
+    #[test]
+    fn top_connections() {
+        let s = series();
+        assert_eq!(s.top_connections().iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 0]);
+        let all: Vec<(&str, String)> = s.all().into_iter().map(|(kind, key, _)| (kind, key)).collect();
+        assert_eq!(all, vec![
+            ("interface", String::from("eth0")),
+            ("interface", String::from("eth1")),
+            ("app_prot", String::from("DNS")),
+            ("app_prot", String::from("HTTP")),
+            ("connection", String::from("2")),
+            ("connection", String::from("1")),
+        ]);
+    }
+
+    #[test]
+    fn csv() {
+        let mut s = TimeSeries::new(1_000_000, 0);
+        s.add("eth0", "say \"hi\"", 0, 10_000_000, 100, true);
+        let mut out = vec![];
+        s.write_csv(&mut out).unwrap();
+        let start = time(10_000_000);
+        assert_eq!(String::from_utf8(out).unwrap(), format!(
+            "series,key,bucket_start,packets_in,bytes_in,packets_out,bytes_out\n\
+             interface,\"eth0\",{0},0,0,1,100\n\
+             app_prot,\"say \"\"hi\"\"\",{0},0,0,1,100\n", start));
+    }
+
+    #[test]
+    fn json() {
+        let mut out = vec![];
+        series().write_json(&mut out).unwrap();
+        let doc: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(doc["bucket_secs"], json!(1.0));
+        assert_eq!(doc["interfaces"]["eth0"].as_array().unwrap().len(), 2);
+        assert_eq!(doc["app_prots"]["DNS"][0], json!({
+            "bucket_start": time(11_000_000),
+            "packets_in": 1,
+            "bytes_in": 500,
+            "packets_out": 1,
+            "bytes_out": 10,
+        }));
+        let connections = doc["connections"].as_object().unwrap();
+        assert_eq!(connections.keys().collect::<Vec<_>>(), vec!["1", "2"]);
+    }
+}
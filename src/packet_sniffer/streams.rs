@@ -0,0 +1,118 @@
+//! Export of the reassembled TCP payload, one file per connection direction.
+use pcap::{BpfProgram, Capture, Linktype};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::Path;
+
+/// This struct writes the reassembled payload of the selected connections
+/// ## Properties
+/// * `dir`: Folder the files are written to
+/// * `filter`: Filter selecting the connections: a connection is exported from its first matching packet on. Empty selects all of them
+/// * `programs`: `filter` compiled for the link type of each device, once the capture is open
+/// * `selected`: Connections selected so far
+/// * `created`: Files already created, by connection and direction
+pub(crate) struct StreamExport {
+    dir: String,
+    filter: String,
+    programs: Vec<BpfProgram>,
+    selected: HashSet<usize>,
+    created: HashSet<(usize, usize)>,
+}
+
+impl StreamExport {
+    pub fn new(dir: String, filter: String) -> Self {
+        StreamExport {
+            dir,
+            filter,
+            programs: vec![],
+            selected: HashSet::new(),
+            created: HashSet::new(),
+        }
+    }
+
+    /// Compiles the filter for the link type of each device of the capture
+    pub fn compile(&mut self, linktypes: &[Linktype]) -> std::result::Result<(), pcap::Error> {
+        if self.filter.is_empty() {
+            return Ok(());
+        }
+        self.programs = linktypes
+            .iter()
+            .map(|linktype| Capture::dead(*linktype)?.compile(&self.filter, true))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(())
+    }
+
+    /// Selects connection `id` if the packet, captured by device `interface`, matches the filter
+    pub fn select(&mut self, id: usize, interface: usize, packet: &[u8]) {
+        if !self.selected.contains(&id) && (self.filter.is_empty() || self.programs.get(interface).is_some_and(|p| p.filter(packet))) {
+            self.selected.insert(id);
+        }
+    }
+
+    /// Tells whether connection `id` is exported
+    pub fn is_selected(&self, id: usize) -> bool {
+        self.selected.contains(&id)
+    }
+
+    /// Appends reassembled data to the file of connection `id`, direction `dir`, if the connection is selected
+    /// ## Parameters
+    /// * `id`: Connection index
+    /// * `dir`: Direction of the data
+    /// * `name`: File name, created on the first write
+    /// * `data`: Reassembled payload
+    pub fn write(&mut self, id: usize, dir: usize, name: &str, data: &[u8]) -> Result<()> {
+        if !self.selected.contains(&id) {
+            return Ok(());
+        }
+        let path = Path::new(&self.dir).join(name);
+        // files are reopened on each write to keep the number of open descriptors bounded
+        let mut file = if self.created.insert((id, dir)) {
+            File::create(path)?
+        } else {
+            OpenOptions::new().append(true).open(path)?
+        };
+        file.write_all(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("packet_sniffer-streams-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn selected_connections() {
+        let dir = test_dir("selected");
+        let mut export = StreamExport::new(dir.to_string_lossy().into_owned(), String::new());
+        // not selected yet
+        export.write(0, 0, "0-a.bin", b"lost").unwrap();
+        assert!(!export.is_selected(0));
+        export.select(0, 0, &[]);
+        assert!(export.is_selected(0));
+        export.write(0, 0, "0-a.bin", b"hello ").unwrap();
+        export.write(0, 1, "0-b.bin", b"reply").unwrap();
+        export.write(0, 0, "0-a.bin", b"world").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("0-a.bin")).unwrap(), "hello world");
+        assert_eq!(std::fs::read_to_string(dir.join("0-b.bin")).unwrap(), "reply");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn existing_file_replaced() {
+        let dir = test_dir("replaced");
+        std::fs::write(dir.join("1-a.bin"), "previous capture").unwrap();
+        let mut export = StreamExport::new(dir.to_string_lossy().into_owned(), String::new());
+        export.select(1, 0, &[]);
+        export.write(1, 0, "1-a.bin", b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("1-a.bin")).unwrap(), "new");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,254 @@
+//! Aggregated statistics of the capture: top talkers, top server ports and protocol hierarchy.
+use super::Connection;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io::{Result, Write};
+
+/// Number of entries kept in each top list
+const TOP: usize = 10;
+
+/// Traffic of a host
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct HostStats {
+    pub host: String,
+    pub bytes: u64,
+    pub connections: u64,
+}
+
+/// Traffic towards a server port
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct PortStats {
+    pub transport: String,
+    pub port: String,
+    pub bytes: u64,
+    pub connections: u64,
+}
+
+/// Traffic of a protocol, with its share of the total bytes
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct ProtocolStats {
+    pub protocol: String,
+    pub bytes: u64,
+    pub connections: u64,
+    pub percent: f64,
+}
+
+/// This struct summarises the connections of a capture
+/// ## Properties
+/// * `total_bytes`, `total_connections`: Totals of the capture
+/// * `hosts_by_bytes`: Hosts exchanging the most bytes
+/// * `hosts_by_connections`: Hosts taking part in the most connections
+/// * `server_ports`: Server ports receiving the most connections
+/// * `app_prots`: Bytes of each application protocol label
+/// * `ip_versions`: IPv4/IPv6 split
+/// * `transports`: TCP/UDP split
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct Summary {
+    pub total_bytes: u64,
+    pub total_connections: u64,
+    pub hosts_by_bytes: Vec<HostStats>,
+    pub hosts_by_connections: Vec<HostStats>,
+    pub server_ports: Vec<PortStats>,
+    pub app_prots: Vec<ProtocolStats>,
+    pub ip_versions: Vec<ProtocolStats>,
+    pub transports: Vec<ProtocolStats>,
+}
+
+impl Summary {
+    pub fn new(connections: &[Connection]) -> Self {
+        let mut hosts: HashMap<&str, HostStats> = HashMap::new();
+        let mut ports: HashMap<(String, &str), PortStats> = HashMap::new();
+        let mut app_prots: HashMap<String, ProtocolStats> = HashMap::new();
+        let mut ip_versions: HashMap<String, ProtocolStats> = HashMap::new();
+        let mut transports: HashMap<String, ProtocolStats> = HashMap::new();
+        let mut total_bytes = 0;
+
+        for con in connections {
+            let bytes = con.bytes_a_to_b as u64 + con.bytes_b_to_a as u64;
+            total_bytes += bytes;
+            for ip in [&con.ip_1, &con.ip_2] {
+                let host = hosts.entry(ip.as_str()).or_insert_with(|| HostStats { host: ip.clone(), ..Default::default() });
+                host.bytes += bytes;
+                host.connections += 1;
+            }
+            let port = server_port(con);
+            let entry = ports.entry((con.l4.to_string(), port)).or_insert_with(|| PortStats {
+                transport: con.l4.to_string(),
+                port: port.to_string(),
+                ..Default::default()
+            });
+            entry.bytes += bytes;
+            entry.connections += 1;
+            // the unknown protocols are labelled as in the connection table
+            let prot = if con.app_prot.is_empty() { String::from("Unknown") } else { con.app_prot.clone() };
+            for (map, key) in [
+                (&mut app_prots, prot),
+                (&mut ip_versions, con.l3.to_string()),
+                (&mut transports, con.l4.to_string()),
+            ] {
+                let entry = map.entry(key.clone()).or_insert_with(|| ProtocolStats { protocol: key, ..Default::default() });
+                entry.bytes += bytes;
+                entry.connections += 1;
+            }
+        }
+
+        let mut hosts_by_bytes: Vec<HostStats> = hosts.into_values().collect();
+        let mut hosts_by_connections = hosts_by_bytes.clone();
+        hosts_by_bytes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.host.cmp(&b.host)));
+        hosts_by_bytes.truncate(TOP);
+        hosts_by_connections.sort_by(|a, b| b.connections.cmp(&a.connections).then(a.host.cmp(&b.host)));
+        hosts_by_connections.truncate(TOP);
+        let mut server_ports: Vec<PortStats> = ports.into_values().collect();
+        server_ports.sort_by(|a, b| b.connections.cmp(&a.connections).then(b.bytes.cmp(&a.bytes)).then(a.port.cmp(&b.port)));
+        server_ports.truncate(TOP);
+
+        Summary {
+            total_bytes,
+            total_connections: connections.len() as u64,
+            hosts_by_bytes,
+            hosts_by_connections,
+            server_ports,
+            app_prots: shares(app_prots, total_bytes),
+            ip_versions: shares(ip_versions, total_bytes),
+            transports: shares(transports, total_bytes),
+        }
+    }
+
+    /// Writes the summary as tables, in the same layout as the connection report
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, "\n Summary: {} connections, {} bytes\n", self.total_connections, self.total_bytes)?;
+        writeln!(writer, " Top hosts by bytes")?;
+        write_hosts(&self.hosts_by_bytes, writer)?;
+        writeln!(writer, "\n Top hosts by connections")?;
+        write_hosts(&self.hosts_by_connections, writer)?;
+        writeln!(writer, "\n Top server ports")?;
+        writeln!(writer, "| {0: <18} | {1: <9} | {2: <11} | Bytes", "Transport Protocol", "Port", "Connections")?;
+        for p in &self.server_ports {
+            writeln!(writer, "| {0: <18} | {1: <9} | {2: <11} | {3}", p.transport, p.port, p.connections, p.bytes)?;
+        }
+        for (title, stats) in [
+            ("Application protocols", &self.app_prots),
+            ("IP protocols", &self.ip_versions),
+            ("Transport protocols", &self.transports),
+        ] {
+            writeln!(writer, "\n {}", title)?;
+            writeln!(writer, "| {0: <21} | {1: <11} | {2: <13} | % Bytes", "Protocol", "Connections", "Bytes")?;
+            for p in stats {
+                writeln!(writer, "| {0: <21} | {1: <11} | {2: <13} | {3:.1}", p.protocol, p.connections, p.bytes, p.percent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Port of the server side: the destination of the first packet, unless only the source is a well-known port
+fn server_port(con: &Connection) -> &str {
+    let well_known = |p: &str| p.parse::<u16>().is_ok_and(|p| p < 1024);
+    if !well_known(&con.port_1) && well_known(&con.port_2) {
+        &con.port_2
+    } else {
+        &con.port_1
+    }
+}
+
+/// Sorts the protocols by bytes and computes their share of `total`
+fn shares(map: HashMap<String, ProtocolStats>, total: u64) -> Vec<ProtocolStats> {
+    let mut stats: Vec<ProtocolStats> = map.into_values().collect();
+    for s in stats.iter_mut() {
+        s.percent = if total > 0 { s.bytes as f64 * 100.0 / total as f64 } else { 0.0 };
+    }
+    stats.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.protocol.cmp(&b.protocol)));
+    stats
+}
+
+fn write_hosts(hosts: &[HostStats], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "| {0: <40} | {1: <11} | Bytes", "Address", "Connections")?;
+    for h in hosts {
+        writeln!(writer, "| {0: <40} | {1: <11} | {2}", h.host, h.connections, h.bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    /// Connection from `b` to `a`, given as (address, port)
+    fn connection(a: (&str, &str), b: (&str, &str), l4: u8, bytes: u32, app_prot: &str) -> Connection {
+        let now = Local::now();
+        let l3 = if a.0.contains(':') { 6 } else { 4 };
+        Connection::new(l3, a.0.to_string(), b.0.to_string(), l4, a.1.to_string(), b.1.to_string(), now, now, bytes,
+            app_prot.to_string())
+    }
+
+    fn connections() -> Vec<Connection> {
+        vec![
+            connection(("10.0.0.1", "80"), ("10.0.0.2", "40000"), 0, 1000, "HTTP"),
+            connection(("10.0.0.1", "80"), ("10.0.0.3", "40001"), 0, 500, "HTTP"),
+            // the reply of a DNS server, seen first
+            connection(("fe80::1", "40002"), ("fe80::2", "53"), 1, 200, ""),
+            connection(("10.0.0.2", "8080"), ("10.0.0.3", "50000"), 0, 300, "Unknown"),
+        ]
+    }
+
+    fn hosts(stats: &[HostStats]) -> Vec<(&str, u64, u64)> {
+        stats.iter().map(|h| (h.host.as_str(), h.bytes, h.connections)).collect()
+    }
+
+    fn protocols(stats: &[ProtocolStats]) -> Vec<(&str, u64, f64)> {
+        stats.iter().map(|p| (p.protocol.as_str(), p.bytes, p.percent)).collect()
+    }
+
+    #[test]
+    fn totals_and_tops() {
+        let s = Summary::new(&connections());
+        assert_eq!((s.total_connections, s.total_bytes), (4, 2000));
+        assert_eq!(hosts(&s.hosts_by_bytes), vec![
+            ("10.0.0.1", 1500, 2),
+            ("10.0.0.2", 1300, 2),
+            ("10.0.0.3", 800, 2),
+            ("fe80::1", 200, 1),
+            ("fe80::2", 200, 1),
+        ]);
+        assert_eq!(hosts(&s.hosts_by_connections)[..3], hosts(&s.hosts_by_bytes)[..3]);
+        let ports: Vec<(&str, &str, u64, u64)> = s.server_ports.iter()
+            .map(|p| (p.transport.as_str(), p.port.as_str(), p.connections, p.bytes))
+            .collect();
+        assert_eq!(ports, vec![("TCP", "80", 2, 1500), ("TCP", "8080", 1, 300), ("UDP", "53", 1, 200)]);
+    }
+
+    #[test]
+    fn protocol_shares() {
+        let s = Summary::new(&connections());
+        assert_eq!(protocols(&s.app_prots), vec![("HTTP", 1500, 75.0), ("Unknown", 500, 25.0)]);
+        assert_eq!(protocols(&s.ip_versions), vec![("IPv4", 1800, 90.0), ("IPv6", 200, 10.0)]);
+        assert_eq!(protocols(&s.transports), vec![("TCP", 1800, 90.0), ("UDP", 200, 10.0)]);
+
+        let empty = Summary::new(&[connection(("10.0.0.1", "80"), ("10.0.0.2", "40000"), 0, 0, "")]);
+        assert_eq!(protocols(&empty.app_prots), vec![("Unknown", 0, 0.0)]);
+    }
+
+    #[test]
+    fn top_limit() {
+        let connections: Vec<Connection> = (0..TOP + 2)
+            .map(|i| connection(("10.0.0.1", &(40000 + i).to_string()), (&format!("10.0.1.{}", i), "80"), 0, i as u32, ""))
+            .collect();
+        let s = Summary::new(&connections);
+        assert_eq!(s.hosts_by_bytes.len(), TOP);
+        assert_eq!(s.hosts_by_bytes[0].host, "10.0.0.1");
+        assert_eq!(s.hosts_by_bytes[1].host, format!("10.0.1.{}", TOP + 1));
+        assert_eq!(s.server_ports.len(), 1);
+        assert_eq!(s.server_ports[0].connections, TOP as u64 + 2);
+    }
+
+    #[test]
+    fn tables() {
+        let mut out = vec![];
+        Summary::new(&connections()).write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Summary: 4 connections, 2000 bytes"));
+        assert!(out.contains(&format!("| {0: <18} | {1: <9} | {2: <11} | 1500", "TCP", "80", 2)));
+        assert!(out.contains(&format!("| {0: <21} | {1: <11} | {2: <13} | 75.0", "HTTP", 2, 1500)));
+    }
+}
@@ -0,0 +1,350 @@
+//! TCP performance metrics computed from the headers: RTT, retransmissions, duplicate ACKs,
+//! out-of-order segments, zero windows and advertised windows.
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+
+/// Sequence holes tracked per direction to tell out-of-order segments from retransmissions
+const MAX_HOLES: usize = 16;
+/// Unacknowledged segments tracked per direction for the RTT estimate
+const MAX_IN_FLIGHT: usize = 64;
+
+/// This struct describes the TCP header fields used by the metrics
+/// ## Properties
+/// * `seq`, `ack_num`: Sequence and acknowledgment numbers
+/// * `syn`, `ack`, `fin`, `rst`: Flags
+/// * `window`: Advertised window, not scaled
+/// * `wscale`: Window scale option (SYN segments only)
+/// * `len`: Payload length
+pub(crate) struct TcpPacket {
+    pub seq: u32,
+    pub ack_num: u32,
+    pub syn: bool,
+    pub ack: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub window: u16,
+    pub wscale: Option<u8>,
+    pub len: u32,
+}
+
+/// Metrics of one direction of a connection
+#[derive(Clone, Debug, Default)]
+struct Direction {
+    /// Sequence number following the highest byte sent
+    next_seq: Option<u32>,
+    /// Ranges skipped by the sender, that later segments may fill
+    holes: Vec<(u32, u32)>,
+    /// End sequence number and timestamp (us) of the segments not acknowledged yet
+    in_flight: VecDeque<(u32, i64)>,
+    /// Acknowledgment number and window of the last ACK
+    last_ack: Option<(u32, u16)>,
+    /// Smoothed time (us) from a segment to its acknowledgment, as in RFC 6298:
+    /// the path from the capture point to the receiver and back
+    srtt: Option<f64>,
+    wscale: Option<u8>,
+    zero_window: bool,
+    retransmissions: u32,
+    dup_acks: u32,
+    out_of_order: u32,
+    zero_windows: u32,
+    max_window: u64,
+}
+
+/// This struct holds the TCP metrics of a connection.
+/// Direction 0 is the one of the first packet of the connection, direction 1 the opposite one
+/// ## Properties
+/// * `dirs`: Per-direction metrics
+/// * `syn`: Direction and timestamp (us) of the first SYN
+/// * `syn_ack`: `true` once the SYN-ACK has been seen
+/// * `handshake_rtt`: Time (us) from the SYN to the ACK completing the handshake
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TcpMetrics {
+    dirs: [Direction; 2],
+    syn: Option<(usize, i64)>,
+    syn_ack: bool,
+    handshake_rtt: Option<i64>,
+}
+
+impl TcpMetrics {
+    /// Accounts a segment
+    /// ## Parameters
+    /// * `dir`: Direction of the segment
+    /// * `p`: Header fields of the segment
+    /// * `ts`: Timestamp (us) of the segment
+    pub fn update(&mut self, dir: usize, p: &TcpPacket, ts: i64) {
+        self.handshake(dir, p, ts);
+        if p.syn {
+            self.dirs[dir].wscale = p.wscale;
+        }
+        self.sequence(dir, p, ts);
+        if p.ack {
+            self.acknowledgment(dir, p, ts);
+        }
+
+        // windows are scaled only if both sides sent the option, and never on SYN segments
+        let scaled = !p.syn && self.dirs.iter().all(|d| d.wscale.is_some());
+        let d = &mut self.dirs[dir];
+        let window = if scaled { (p.window as u64) << d.wscale.unwrap_or(0).min(14) } else { p.window as u64 };
+        d.max_window = d.max_window.max(window);
+        if p.window == 0 && !p.syn && !p.rst {
+            if !d.zero_window {
+                d.zero_windows += 1;
+            }
+            d.zero_window = true;
+        } else {
+            d.zero_window = false;
+        }
+    }
+
+    /// Smoothed RTT (us): the sum of the estimates of both directions, once both have one
+    fn srtt(&self) -> Option<f64> {
+        Some(self.dirs[0].srtt? + self.dirs[1].srtt?)
+    }
+
+    fn handshake(&mut self, dir: usize, p: &TcpPacket, ts: i64) {
+        if p.syn && !p.ack {
+            self.syn.get_or_insert((dir, ts));
+        } else if p.syn && p.ack {
+            self.syn_ack = true;
+        } else if p.ack && self.syn_ack && self.handshake_rtt.is_none() {
+            if let Some((syn_dir, syn_ts)) = self.syn {
+                if syn_dir == dir {
+                    self.handshake_rtt = Some(ts - syn_ts);
+                }
+            }
+        }
+    }
+
+    /// Classifies the segment as new data, retransmission or out-of-order
+    fn sequence(&mut self, dir: usize, p: &TcpPacket, ts: i64) {
+        let seg_len = p.len + p.syn as u32 + p.fin as u32;
+        let d = &mut self.dirs[dir];
+        let end = p.seq.wrapping_add(seg_len);
+        let next = match d.next_seq {
+            Some(n) => n,
+            None => {
+                d.next_seq = Some(end);
+                if seg_len > 0 {
+                    d.in_flight.push_back((end, ts));
+                }
+                return;
+            }
+        };
+        if seg_len == 0 {
+            return;
+        }
+        if after(end, next) {
+            if after(p.seq, next) {
+                if d.holes.len() >= MAX_HOLES {
+                    d.holes.remove(0);
+                }
+                d.holes.push((next, p.seq));
+            } else if p.seq != next {
+                // partially resends data already seen
+                d.retransmissions += 1;
+            }
+            d.next_seq = Some(end);
+            if d.in_flight.len() >= MAX_IN_FLIGHT {
+                d.in_flight.pop_front();
+            }
+            d.in_flight.push_back((end, ts));
+        } else if let Some(pos) = d.holes.iter().position(|&(s, e)| !after(s, p.seq) && after(e, p.seq)) {
+            d.out_of_order += 1;
+            let (s, e) = d.holes.remove(pos);
+            if after(p.seq, s) {
+                d.holes.push((s, p.seq));
+            }
+            if after(e, end) {
+                d.holes.push((end, e));
+            }
+        } else {
+            d.retransmissions += 1;
+            // Karn's algorithm: no RTT sample from retransmitted data
+            d.in_flight.retain(|&(e, _)| !after(e, p.seq));
+        }
+    }
+
+    /// Takes an RTT sample from the data acknowledged and counts duplicate ACKs
+    fn acknowledgment(&mut self, dir: usize, p: &TcpPacket, ts: i64) {
+        let other = &mut self.dirs[1 - dir];
+        let mut sample = None;
+        while let Some(&(end, sent)) = other.in_flight.front() {
+            if after(end, p.ack_num) {
+                break;
+            }
+            other.in_flight.pop_front();
+            sample = Some(ts - sent);
+        }
+        // duplicate ACKs only make sense while data is outstanding
+        let outstanding = other.next_seq.is_some_and(|n| after(n, p.ack_num));
+        if let Some(sample) = sample {
+            let sample = sample as f64;
+            other.srtt = Some(match other.srtt {
+                Some(srtt) => 0.875 * srtt + 0.125 * sample,
+                None => sample,
+            });
+        }
+
+        let d = &mut self.dirs[dir];
+        let pure_ack = p.len == 0 && !p.syn && !p.fin && !p.rst;
+        if pure_ack && outstanding && d.last_ack == Some((p.ack_num, p.window)) {
+            d.dup_acks += 1;
+        }
+        d.last_ack = Some((p.ack_num, p.window));
+    }
+}
+
+/// Tells whether sequence number `a` comes after `b`, across wrap-around
+fn after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+fn ms(us: Option<f64>) -> String {
+    us.map_or(String::from("-"), |us| format!("{:.3}", us / 1000.0))
+}
+
+/// Writes the metrics as a table, in the same layout as the connection report.
+/// Per-direction values are shown as `A->B / B->A`, where direction 1 goes from address A to address B
+pub(crate) fn write(metrics: &BTreeMap<usize, &TcpMetrics>, writer: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(writer, "\n TCP metrics (per direction: A->B / B->A)\n")?;
+    writeln!(writer, "| N°    | {0: <18} | {1: <12} | {2: <15} | {3: <13} | {4: <13} | {5: <13} | Max Window",
+        "Handshake RTT (ms)", "RTT (ms)", "Retransmissions", "Dup ACKs", "Out of Order", "Zero Windows")?;
+    for (index, m) in metrics {
+        let pair = |f: fn(&Direction) -> u64| format!("{} / {}", f(&m.dirs[1]), f(&m.dirs[0]));
+        writeln!(writer, "| {0: <5} | {1: <18} | {2: <12} | {3: <15} | {4: <13} | {5: <13} | {6: <13} | {7}",
+            index + 1,
+            ms(m.handshake_rtt.map(|r| r as f64)),
+            ms(m.srtt()),
+            pair(|d| d.retransmissions as u64),
+            pair(|d| d.dup_acks as u64),
+            pair(|d| d.out_of_order as u64),
+            pair(|d| d.zero_windows as u64),
+            pair(|d| d.max_window))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(seq: u32, ack_num: u32, flags: &str, window: u16, len: u32) -> TcpPacket {
+        TcpPacket {
+            seq,
+            ack_num,
+            syn: flags.contains('S'),
+            ack: flags.contains('A'),
+            fin: flags.contains('F'),
+            rst: flags.contains('R'),
+            window,
+            wscale: None,
+            len,
+        }
+    }
+
+    /// Handshake from direction 0 with window scales 7 and 2, taking 25 ms
+    fn handshake() -> TcpMetrics {
+        let mut m = TcpMetrics::default();
+        m.update(0, &TcpPacket { wscale: Some(7), ..segment(100, 0, "S", 64240, 0) }, 0);
+        m.update(1, &TcpPacket { wscale: Some(2), ..segment(500, 101, "SA", 65160, 0) }, 10_000);
+        m.update(0, &segment(101, 501, "A", 1000, 0), 25_000);
+        m
+    }
+
+    #[test]
+    fn handshake_rtt_and_windows() {
+        let m = handshake();
+        assert_eq!(m.handshake_rtt, Some(25_000));
+        // samples of 10 ms (SYN) and 15 ms (SYN-ACK), one for each half of the path
+        assert_eq!(m.srtt(), Some(25_000.0));
+        // the windows of SYN segments are never scaled
+        assert_eq!(m.dirs[0].max_window, 1000 << 7);
+        assert_eq!(m.dirs[1].max_window, 65160);
+    }
+
+    #[test]
+    fn steady_flow_rtt() {
+        let mut m = handshake();
+        let (mut seq_a, mut seq_b, mut ts) = (101, 501, 30_000);
+        for _ in 0..50 {
+            // B answers A after 10 ms, A acknowledges B after 15 ms
+            m.update(0, &segment(seq_a, seq_b, "A", 1000, 100), ts);
+            seq_a += 100;
+            m.update(1, &segment(seq_b, seq_a, "A", 1000, 100), ts + 10_000);
+            seq_b += 100;
+            m.update(0, &segment(seq_a, seq_b, "A", 1000, 0), ts + 25_000);
+            ts += 50_000;
+        }
+        assert_eq!(m.srtt(), m.handshake_rtt.map(|r| r as f64));
+    }
+
+    #[test]
+    fn unscaled_windows() {
+        let mut m = TcpMetrics::default();
+        m.update(0, &TcpPacket { wscale: Some(7), ..segment(100, 0, "S", 100, 0) }, 0);
+        m.update(1, &segment(500, 101, "SA", 100, 0), 0);
+        m.update(0, &segment(101, 501, "A", 1000, 0), 0);
+        assert_eq!(m.dirs[0].max_window, 1000);
+    }
+
+    #[test]
+    fn retransmissions_and_out_of_order() {
+        let mut m = handshake();
+        m.update(0, &segment(101, 501, "A", 1000, 100), 30_000);
+        // 201..301 is skipped
+        m.update(0, &segment(301, 501, "A", 1000, 100), 31_000);
+        for _ in 0..3 {
+            m.update(1, &segment(501, 201, "A", 500, 0), 40_000);
+        }
+        m.update(0, &segment(201, 501, "A", 1000, 100), 41_000);
+        m.update(0, &segment(101, 501, "A", 1000, 100), 42_000);
+        // partially new data
+        m.update(0, &segment(351, 501, "A", 1000, 100), 43_000);
+        let d = &m.dirs[0];
+        assert_eq!((d.out_of_order, d.retransmissions), (1, 2));
+        assert!(d.holes.is_empty());
+        assert_eq!(d.next_seq, Some(451));
+        assert_eq!(m.dirs[1].dup_acks, 2);
+    }
+
+    #[test]
+    fn no_dup_acks_without_outstanding_data() {
+        let mut m = handshake();
+        for _ in 0..3 {
+            m.update(1, &segment(501, 101, "A", 500, 0), 30_000);
+        }
+        assert_eq!(m.dirs[1].dup_acks, 0);
+    }
+
+    #[test]
+    fn zero_windows() {
+        let mut m = handshake();
+        for window in [0, 0, 100, 0] {
+            m.update(1, &segment(501, 101, "A", window, 0), 30_000);
+        }
+        m.update(1, &segment(501, 101, "R", 0, 0), 30_000);
+        assert_eq!(m.dirs[1].zero_windows, 2);
+    }
+
+    #[test]
+    fn sequence_order() {
+        assert!(after(1, 0));
+        assert!(!after(0, 0));
+        assert!(!after(0, 1));
+        assert!(after(5, u32::MAX - 5));
+        assert!(!after(u32::MAX - 5, 5));
+    }
+
+    #[test]
+    fn table() {
+        let m = handshake();
+        let metrics: BTreeMap<usize, &TcpMetrics> = [(2, &m)].into_iter().collect();
+        let mut out = vec![];
+        write(&metrics, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let row = out.lines().last().unwrap();
+        assert!(row.starts_with("| 3     | 25.000             | 25.000       | 0 / 0"));
+        assert!(row.ends_with(&format!("| 65160 / {}", 1000 << 7)));
+        assert_eq!(ms(None), "-");
+    }
+}
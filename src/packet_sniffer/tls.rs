@@ -0,0 +1,158 @@
+//! Minimal TLS handshake parsing, limited to the fields the sniffer reports.
+use super::reader::Reader;
+
+/// This struct contains the fields extracted from a TLS ClientHello
+/// ## Properties
+/// * `server_name`: Host name carried by the Server Name Indication extension
+/// * `alpn`: Protocols offered through the Application-Layer Protocol Negotiation extension
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ClientHello {
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+}
+
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_ALPN: u16 = 16;
+
+/// Returns the length of the handshake message at the start of `data` (header included),
+/// or `None` if not even the header is available yet
+pub(crate) fn handshake_len(data: &[u8]) -> Option<usize> {
+    let mut r = Reader::new(data);
+    r.u8()?;
+    Some(4 + r.u24()?)
+}
+
+/// Parses a ClientHello handshake message (without the TLS record header)
+pub(crate) fn parse_client_hello(data: &[u8]) -> Option<ClientHello> {
+    let mut r = Reader::new(data);
+    if r.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = r.u24()?;
+    let mut r = Reader::new(r.take(len)?);
+    // legacy_version + random
+    r.skip(2 + 32)?;
+    let session_id = r.u8()? as usize;
+    r.skip(session_id)?;
+    let suites = r.u16()? as usize;
+    r.skip(suites)?;
+    let compression = r.u8()? as usize;
+    r.skip(compression)?;
+
+    let mut hello = ClientHello::default();
+    if r.remaining() == 0 {
+        return Some(hello);
+    }
+    let ext_len = r.u16()? as usize;
+    let mut exts = Reader::new(r.take(ext_len)?);
+    while exts.remaining() >= 4 {
+        let ext_type = exts.u16()?;
+        let len = exts.u16()? as usize;
+        let body = exts.take(len)?;
+        match ext_type {
+            EXT_SERVER_NAME => hello.server_name = parse_server_name(body),
+            EXT_ALPN => hello.alpn = parse_alpn(body).unwrap_or_default(),
+            _ => (),
+        }
+    }
+    Some(hello)
+}
+
+fn parse_server_name(body: &[u8]) -> Option<String> {
+    let mut r = Reader::new(body);
+    let list_len = r.u16()? as usize;
+    let mut list = Reader::new(r.take(list_len)?);
+    while list.remaining() > 3 {
+        let name_type = list.u8()?;
+        let len = list.u16()? as usize;
+        let name = list.take(len)?;
+        // host_name(0) is the only type defined
+        if name_type == 0 {
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+    None
+}
+
+fn parse_alpn(body: &[u8]) -> Option<Vec<String>> {
+    let mut r = Reader::new(body);
+    let list_len = r.u16()? as usize;
+    let mut list = Reader::new(r.take(list_len)?);
+    let mut protocols = vec![];
+    while list.remaining() > 0 {
+        let len = list.u8()? as usize;
+        protocols.push(String::from_utf8_lossy(list.take(len)?).into_owned());
+    }
+    Some(protocols)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// ClientHello handshake message offering `alpn`, with the SNI extension if `server_name` is set
+    pub(crate) fn client_hello(server_name: Option<&str>, alpn: &[&str]) -> Vec<u8> {
+        let mut exts = vec![];
+        if let Some(name) = server_name {
+            let mut list = vec![0];
+            list.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            list.extend_from_slice(name.as_bytes());
+            extension(&mut exts, EXT_SERVER_NAME, &list);
+        }
+        if !alpn.is_empty() {
+            let mut list = vec![];
+            for p in alpn {
+                list.push(p.len() as u8);
+                list.extend_from_slice(p.as_bytes());
+            }
+            extension(&mut exts, EXT_ALPN, &list);
+        }
+        // an extension the parser skips
+        extension(&mut exts, 43, &[0x02, 0x03, 0x04]);
+
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x11; 32]);
+        body.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        body.extend_from_slice(&exts);
+        let mut hello = vec![HANDSHAKE_CLIENT_HELLO];
+        hello.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        hello.extend_from_slice(&body);
+        hello
+    }
+
+    /// Appends an extension whose body is a list prefixed by its 2-byte length
+    fn extension(exts: &mut Vec<u8>, ext_type: u16, list: &[u8]) {
+        exts.extend_from_slice(&ext_type.to_be_bytes());
+        exts.extend_from_slice(&(list.len() as u16 + 2).to_be_bytes());
+        exts.extend_from_slice(&(list.len() as u16).to_be_bytes());
+        exts.extend_from_slice(list);
+    }
+
+    #[test]
+    fn sni_and_alpn() {
+        let data = client_hello(Some("example.com"), &["h3", "h2"]);
+        assert_eq!(handshake_len(&data), Some(data.len()));
+        let hello = parse_client_hello(&data).unwrap();
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+        assert_eq!(hello.alpn, vec!["h3", "h2"]);
+    }
+
+    #[test]
+    fn no_extensions() {
+        let hello = parse_client_hello(&client_hello(None, &[])).unwrap();
+        assert_eq!(hello, ClientHello::default());
+    }
+
+    #[test]
+    fn truncated() {
+        let data = client_hello(Some("example.com"), &["h3"]);
+        assert_eq!(handshake_len(&data[..2]), None);
+        assert_eq!(parse_client_hello(&data[..data.len() - 1]), None);
+        // not a ClientHello
+        let mut server_hello = data.clone();
+        server_hello[0] = 2;
+        assert_eq!(parse_client_hello(&server_hello), None);
+    }
+}
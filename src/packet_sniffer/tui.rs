@@ -0,0 +1,344 @@
+//! Full-screen terminal interface: live connection table, bandwidth sparklines, filter box and detail pane.
+use super::Connection;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Sparkline, Table, TableState};
+use ratatui::Terminal;
+use std::collections::{HashMap, VecDeque};
+use std::io::{stdout, Result, Stdout};
+use std::time::{Duration, Instant};
+
+/// Samples kept for each sparkline
+const HISTORY: usize = 120;
+/// Time between two bandwidth samples
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Minimum time between two redraws
+const DRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// This enum provides the requests the user can make from the interface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Action {
+    Pause,
+    Resume,
+    Quit,
+}
+
+/// Column the connection table is sorted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    Bytes,
+    Start,
+    Protocol,
+}
+
+/// This struct holds what the interface shows besides the connections
+/// ## Properties
+/// * `device`: Capture device
+/// * `paused`: `true` if the capture is paused
+/// * `remaining`: Capture time left (secs)
+/// * `warning`: Warning about the packets dropped, if any
+pub(crate) struct Status<'a> {
+    pub device: &'a str,
+    pub paused: bool,
+    pub remaining: f64,
+    pub warning: Option<&'a str>,
+}
+
+/// This struct draws the interface and reads the keyboard
+/// ## Properties
+/// * `terminal`: Terminal in raw mode, on the alternate screen
+/// * `sort`: Column the table is sorted by
+/// * `filter`: Text the shown connections must contain
+/// * `editing`: Content of the filter box while it is being edited
+/// * `state`: Selected row of the table
+/// * `selected`: Index of the selected connection, kept across re-sorts
+/// * `visible`: Indexes of the connections shown, in table order
+/// * `total`, `history`: Bytes per second of all the traffic and of each connection
+/// * `last`: Bytes of each connection at the last sample
+pub(crate) struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    sort: SortKey,
+    filter: String,
+    editing: Option<String>,
+    state: TableState,
+    selected: Option<usize>,
+    visible: Vec<usize>,
+    total: VecDeque<u64>,
+    history: HashMap<usize, VecDeque<u64>>,
+    last: Vec<u64>,
+    last_sample: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl Tui {
+    /// Switches the terminal to the full-screen interface, restored when the struct is dropped
+    pub fn new() -> Result<Self> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        Ok(Tui {
+            terminal,
+            sort: SortKey::Bytes,
+            filter: String::new(),
+            editing: None,
+            state: TableState::default(),
+            selected: None,
+            visible: vec![],
+            total: VecDeque::new(),
+            history: HashMap::new(),
+            last: vec![],
+            last_sample: Instant::now(),
+            last_draw: None,
+        })
+    }
+
+    /// Handles the pending key presses without blocking, returning the last request made
+    pub fn keys(&mut self, paused: bool) -> Result<Option<Action>> {
+        let mut action = None;
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(k) if k.kind == KeyEventKind::Press => k,
+                Event::Resize(_, _) => {
+                    self.last_draw = None;
+                    continue;
+                }
+                _ => continue,
+            };
+            self.last_draw = None;
+            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                return Ok(Some(Action::Quit));
+            }
+            if let Some(edit) = self.editing.as_mut() {
+                match key.code {
+                    KeyCode::Char(c) => edit.push(c),
+                    KeyCode::Backspace => {
+                        edit.pop();
+                    }
+                    KeyCode::Enter => {
+                        self.filter = self.editing.take().unwrap_or_default();
+                        self.state.select(Some(0));
+                    }
+                    KeyCode::Esc => self.editing = None,
+                    _ => (),
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') => return Ok(Some(Action::Quit)),
+                KeyCode::Char('p') if !paused => action = Some(Action::Pause),
+                KeyCode::Char('r') if paused => action = Some(Action::Resume),
+                KeyCode::Char(' ') => action = Some(if paused { Action::Resume } else { Action::Pause }),
+                KeyCode::Char('/') => self.editing = Some(self.filter.clone()),
+                KeyCode::Char('b') => self.sort = SortKey::Bytes,
+                KeyCode::Char('t') => self.sort = SortKey::Start,
+                KeyCode::Char('a') => self.sort = SortKey::Protocol,
+                KeyCode::Down => self.move_selection(1),
+                KeyCode::Up => self.move_selection(-1),
+                KeyCode::PageDown => self.move_selection(20),
+                KeyCode::PageUp => self.move_selection(-20),
+                KeyCode::Home => self.move_selection(i64::MIN / 2),
+                KeyCode::End => self.move_selection(i64::MAX / 2),
+                KeyCode::Esc => self.filter.clear(),
+                _ => (),
+            }
+        }
+        Ok(action)
+    }
+
+    fn move_selection(&mut self, delta: i64) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.state.selected().unwrap_or(0) as i64;
+        let row = (current + delta).clamp(0, self.visible.len() as i64 - 1) as usize;
+        self.state.select(Some(row));
+        self.selected = Some(self.visible[row]);
+    }
+
+    /// Takes a bandwidth sample if it is time to
+    fn sample(&mut self, connections: &[Connection]) {
+        if self.last_sample.elapsed() < SAMPLE_INTERVAL {
+            return;
+        }
+        let secs = self.last_sample.elapsed().as_secs_f64();
+        self.last_sample = Instant::now();
+        self.last.resize(connections.len(), 0);
+        let mut total = 0;
+        for (i, con) in connections.iter().enumerate() {
+            let bytes = con.bytes_a_to_b as u64 + con.bytes_b_to_a as u64;
+            let rate = ((bytes - self.last[i].min(bytes)) as f64 / secs) as u64;
+            self.last[i] = bytes;
+            total += rate;
+            let history = self.history.entry(i).or_default();
+            push(history, rate);
+        }
+        push(&mut self.total, total);
+    }
+
+    /// Redraws the interface, at most every `DRAW_INTERVAL` unless a key was pressed
+    /// ## Parameters
+    /// * `connections`: Connections found so far
+    /// * `details`: Returns the details of a connection
+    /// * `status`: State of the capture
+    pub fn draw(&mut self, connections: &[Connection], details: &dyn Fn(usize) -> String, status: &Status) -> Result<()> {
+        if !status.paused {
+            self.sample(connections);
+        }
+        if self.last_draw.is_some_and(|t| t.elapsed() < DRAW_INTERVAL) {
+            return Ok(());
+        }
+        self.last_draw = Some(Instant::now());
+
+        let filter = self.filter.to_lowercase();
+        let mut visible: Vec<usize> = (0..connections.len())
+            .filter(|i| filter.is_empty() || search_text(&connections[*i]).contains(&filter))
+            .collect();
+        let bytes = |i: &usize| connections[*i].bytes_a_to_b as u64 + connections[*i].bytes_b_to_a as u64;
+        match self.sort {
+            SortKey::Bytes => visible.sort_by_key(|i| std::cmp::Reverse(bytes(i))),
+            SortKey::Start => visible.sort_by_key(|i| connections[*i].ts_start),
+            SortKey::Protocol => visible.sort_by(|a, b| connections[*a].app_prot.cmp(&connections[*b].app_prot)
+                .then(bytes(b).cmp(&bytes(a)))),
+        }
+        // the selection follows the connection, not the row
+        let row = self.selected.and_then(|s| visible.iter().position(|i| *i == s))
+            .or_else(|| if visible.is_empty() { None } else { Some(self.state.selected().unwrap_or(0).min(visible.len() - 1)) });
+        self.state.select(row);
+        self.selected = row.map(|r| visible[r]);
+        self.visible = visible;
+
+        let rows: Vec<Row> = self.visible.iter().map(|i| {
+            let con = &connections[*i];
+            Row::new(vec![
+                (i + 1).to_string(),
+                con.l3.to_string(),
+                con.ip_1.clone(),
+                con.port_1.clone(),
+                con.ip_2.clone(),
+                con.port_2.clone(),
+                con.l4.to_string(),
+                con.app_prot.clone(),
+                con.ts_start.format("%H:%M:%S").to_string(),
+                con.bytes_a_to_b.to_string(),
+                con.bytes_b_to_a.to_string(),
+            ])
+        }).collect();
+        let sort = match self.sort {
+            SortKey::Bytes => "bytes",
+            SortKey::Start => "start time",
+            SortKey::Protocol => "protocol",
+        };
+        let mut header = format!(" Device: {} | {} | Time left: {:.1} s | Connections: {} shown / {} | Sort: {} | Filter: {}",
+            status.device, if status.paused { "PAUSED" } else { "CAPTURING" }, status.remaining.max(0.0),
+            self.visible.len(), connections.len(), sort, if self.filter.is_empty() { "-" } else { &self.filter });
+        if let Some(warning) = status.warning {
+            header = format!("{} | {}", header, warning);
+        }
+        let footer = match &self.editing {
+            Some(edit) => format!(" Filter: {}_   (Enter apply, Esc cancel)", edit),
+            None => String::from(" p/r/space pause-resume | b t a sort by bytes/start/protocol | / filter | Esc clear filter | Up/Down select | q quit"),
+        };
+        let selected = self.selected.map(|i| {
+            let con = &connections[i];
+            let text = vec![
+                Line::from(format!("#{}  {} {}  {}:{} <-> {}:{}", i + 1, con.l3, con.l4, con.ip_1, con.port_1, con.ip_2, con.port_2)),
+                Line::from(format!("Application protocol: {}", con.app_prot)),
+                Line::from(format!("Start: {}   End: {}", con.ts_start.format("%Y/%m/%d %H:%M:%S"), con.ts_end.format("%Y/%m/%d %H:%M:%S"))),
+                Line::from(format!("Bytes A->B: {}   Bytes B->A: {}", con.bytes_a_to_b, con.bytes_b_to_a)),
+                Line::from(format!("Details: {}", details(i))),
+            ];
+            (i, text)
+        });
+        let total: Vec<u64> = self.total.iter().copied().collect();
+        let history: Vec<u64> = self.selected.and_then(|i| self.history.get(&i)).map(|h| h.iter().copied().collect()).unwrap_or_default();
+        let state = &mut self.state;
+
+        self.terminal.draw(|f| {
+            let [top, spark, table, detail, bottom] = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Length(5),
+                Constraint::Min(5),
+                Constraint::Length(9),
+                Constraint::Length(1),
+            ]).areas(f.area());
+            f.render_widget(Paragraph::new(header).reversed(), top);
+            f.render_widget(Sparkline::default()
+                .block(Block::bordered().title(format!(" Bandwidth: {} B/s ", total.last().unwrap_or(&0))))
+                .data(&total), spark);
+
+            let widths = [
+                Constraint::Length(5), Constraint::Length(4), Constraint::Min(15), Constraint::Length(6),
+                Constraint::Min(15), Constraint::Length(6), Constraint::Length(4), Constraint::Length(12),
+                Constraint::Length(8), Constraint::Length(12), Constraint::Length(12),
+            ];
+            let table_widget = Table::new(rows, widths)
+                .header(Row::new(vec!["N°", "IP", "Address A", "Port A", "Address B", "Port B", "L4", "Protocol", "Start", "A->B", "B->A"]).bold())
+                .block(Block::bordered().title(" Connections "))
+                .row_highlight_style(Style::new().reversed());
+            f.render_stateful_widget(table_widget, table, state);
+
+            let [text, chart] = Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(detail);
+            match selected {
+                Some((i, lines)) => {
+                    f.render_widget(Paragraph::new(lines).wrap(ratatui::widgets::Wrap { trim: true })
+                        .block(Block::bordered().title(format!(" Connection #{} ", i + 1))), text);
+                    f.render_widget(Sparkline::default()
+                        .block(Block::bordered().title(format!(" {} B/s ", history.last().unwrap_or(&0))))
+                        .data(&history), chart);
+                }
+                None => f.render_widget(Paragraph::new("No connection selected").block(Block::bordered()), detail),
+            }
+            f.render_widget(Paragraph::new(footer), bottom);
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(stdout(), LeaveAlternateScreen);
+    }
+}
+
+fn push(history: &mut VecDeque<u64>, value: u64) {
+    if history.len() >= HISTORY {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+/// Text the filter box is matched against
+fn search_text(con: &Connection) -> String {
+    format!("{} {} {} {} {} {} {}", con.l3, con.ip_1, con.port_1, con.ip_2, con.port_2, con.l4, con.app_prot).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Local;
+
+    #[test]
+    fn history_bounded() {
+        let mut history = VecDeque::new();
+        for value in 0..HISTORY as u64 + 5 {
+            push(&mut history, value);
+        }
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!((history.front(), history.back()), (Some(&5), Some(&(HISTORY as u64 + 4))));
+    }
+
+    #[test]
+    fn filter_text() {
+        let now = Local::now();
+        let con = Connection::new(6, String::from("FE80:0000:0000:0000:0000:0000:0000:0001"), String::from("10.0.0.2"), 1,
+            String::from("5353"), String::from("5353"), now, now, 0, String::from("mDNS"));
+        let text = search_text(&con);
+        assert_eq!(text, "ipv6 fe80:0000:0000:0000:0000:0000:0000:0001 5353 10.0.0.2 5353 udp mdns");
+    }
+}
@@ -0,0 +1,351 @@
+//! Zeek `conn.log` output, in the TSV and in the JSON formats of Zeek.
+//!
+//! The originator is the sender of the first packet of the connection (address B), the responder is address A.
+use super::tcp_metrics::TcpPacket;
+use super::{Connection, Transport};
+use chrono::Local;
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Result, Write};
+
+const FIELDS: [&str; 21] = [
+    "ts", "uid", "id.orig_h", "id.orig_p", "id.resp_h", "id.resp_p", "proto", "service", "duration", "orig_bytes",
+    "resp_bytes", "conn_state", "local_orig", "local_resp", "missed_bytes", "history", "orig_pkts", "orig_ip_bytes",
+    "resp_pkts", "resp_ip_bytes", "tunnel_parents",
+];
+const TYPES: [&str; 21] = [
+    "time", "string", "addr", "port", "addr", "port", "enum", "string", "interval", "count", "count", "string", "bool",
+    "bool", "count", "string", "count", "count", "count", "count", "set[string]",
+];
+
+/// Counters and TCP flags of one side of a connection
+#[derive(Clone, Debug, Default)]
+struct Side {
+    packets: u64,
+    ip_bytes: u64,
+    bytes: u64,
+    syn: bool,
+    syn_ack: bool,
+    fin: bool,
+    rst: bool,
+}
+
+/// State of a connection: sides 0 (originator) and 1 (responder), and the history letters
+#[derive(Clone, Debug, Default)]
+struct ConnState {
+    sides: [Side; 2],
+    history: String,
+}
+
+/// This struct tracks the connections for the `conn.log`
+/// ## Properties
+/// * `conns`: State of each connection, by connection index
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnLog {
+    conns: HashMap<usize, ConnState>,
+}
+
+impl ConnLog {
+    /// Accounts a packet
+    /// ## Parameters
+    /// * `index`: Connection index
+    /// * `dir`: Direction, 0 if sent by the originator
+    /// * `ip_bytes`: Length of the IP packet
+    /// * `bytes`: Payload length
+    /// * `tcp`: TCP header fields, if any
+    pub fn add(&mut self, index: usize, dir: usize, ip_bytes: u64, bytes: u64, tcp: Option<&TcpPacket>) {
+        let conn = self.conns.entry(index).or_default();
+        let side = &mut conn.sides[dir];
+        side.packets += 1;
+        side.ip_bytes += ip_bytes;
+        side.bytes += bytes;
+        let mut letters = vec![];
+        if let Some(p) = tcp {
+            if p.syn && !p.ack {
+                side.syn = true;
+                letters.push('s');
+            }
+            if p.syn && p.ack {
+                side.syn_ack = true;
+                letters.push('h');
+            }
+            if p.ack && !p.syn && !p.fin && !p.rst && p.len == 0 {
+                letters.push('a');
+            }
+            if p.fin {
+                side.fin = true;
+                letters.push('f');
+            }
+            if p.rst {
+                side.rst = true;
+                letters.push('r');
+            }
+        }
+        if bytes > 0 {
+            letters.push('d');
+        }
+        //each letter is recorded once per side, upper case for the originator
+        for l in letters {
+            let l = if dir == 0 { l.to_ascii_uppercase() } else { l };
+            if !conn.history.contains(l) {
+                conn.history.push(l);
+            }
+        }
+    }
+
+    /// Writes the log in the Zeek TSV format, with its header
+    pub fn write_tsv(&self, connections: &[Connection], local_addrs: &[String], writer: &mut dyn Write) -> Result<()> {
+        let now = Local::now().format("%Y-%m-%d-%H-%M-%S");
+        writeln!(writer, "#separator \\x09\n#set_separator\t,\n#empty_field\t(empty)\n#unset_field\t-\n#path\tconn\n#open\t{}", now)?;
+        writeln!(writer, "#fields\t{}", FIELDS.join("\t"))?;
+        writeln!(writer, "#types\t{}", TYPES.join("\t"))?;
+        for (i, con) in connections.iter().enumerate() {
+            let record = self.record(i, con, local_addrs);
+            let row: Vec<String> = FIELDS.iter().map(|f| match &record[*f] {
+                Value::Null => String::from("-"),
+                Value::String(s) => s.clone(),
+                Value::Bool(b) => String::from(if *b { "T" } else { "F" }),
+                Value::Number(n) if *f == "ts" || *f == "duration" => format!("{:.6}", n.as_f64().unwrap_or(0.0)),
+                v => v.to_string(),
+            }).collect();
+            writeln!(writer, "{}", row.join("\t"))?;
+        }
+        writeln!(writer, "#close\t{}", Local::now().format("%Y-%m-%d-%H-%M-%S"))
+    }
+
+    /// Writes the log in the Zeek JSON format: one object per line, unset fields left out
+    pub fn write_json(&self, connections: &[Connection], local_addrs: &[String], writer: &mut dyn Write) -> Result<()> {
+        for (i, con) in connections.iter().enumerate() {
+            //fields in the order of Zeek, which serde_json would sort
+            let record = self.record(i, con, local_addrs);
+            let fields: Vec<String> = FIELDS.iter()
+                .filter(|f| !record[**f].is_null())
+                .map(|f| format!("{}:{}", Value::from(*f), record[*f]))
+                .collect();
+            writeln!(writer, "{{{}}}", fields.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Fields of connection `index`, `null` when unset
+    fn record(&self, index: usize, con: &Connection, local_addrs: &[String]) -> Map<String, Value> {
+        let default = ConnState::default();
+        let state = self.conns.get(&index).unwrap_or(&default);
+        let (orig, resp) = (&state.sides[0], &state.sides[1]);
+        let tcp = con.l4 == Transport::TCP;
+        let duration = (con.ts_end - con.ts_start).num_microseconds().unwrap_or(0) as f64 / 1_000_000.0;
+        let record = json!({
+            "ts": con.ts_start.timestamp_micros() as f64 / 1_000_000.0,
+            "uid": uid(index, con),
+            "id.orig_h": con.ip_2,
+            "id.orig_p": con.port_2.parse::<u16>().ok(),
+            "id.resp_h": con.ip_1,
+            "id.resp_p": con.port_1.parse::<u16>().ok(),
+            "proto": if tcp { "tcp" } else { "udp" },
+            "service": service(&con.app_prot),
+            "duration": duration,
+            "orig_bytes": orig.bytes,
+            "resp_bytes": resp.bytes,
+            "conn_state": if tcp { tcp_state(orig, resp) } else { udp_state(resp) },
+            "local_orig": local_addrs.contains(&con.ip_2),
+            "local_resp": local_addrs.contains(&con.ip_1),
+            "missed_bytes": 0,
+            "history": if state.history.is_empty() { None } else { Some(&state.history) },
+            "orig_pkts": orig.packets,
+            "orig_ip_bytes": orig.ip_bytes,
+            "resp_pkts": resp.packets,
+            "resp_ip_bytes": resp.ip_bytes,
+            "tunnel_parents": null,
+        });
+        match record {
+            Value::Object(m) => m,
+            _ => Map::new(),
+        }
+    }
+}
+
+/// Zeek-like unique ID of a connection: "C" followed by 96 bits in base 62
+fn uid(index: usize, con: &Connection) -> String {
+    let hash = |seed: u64| {
+        let mut h = DefaultHasher::new();
+        (seed, index, &con.ip_1, &con.ip_2, &con.port_1, &con.port_2, con.ts_start.timestamp_nanos_opt()).hash(&mut h);
+        h.finish()
+    };
+    let mut n = ((hash(0) as u128) << 32) | (hash(1) >> 32) as u128;
+    let digits = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut uid = vec![];
+    while n > 0 {
+        uid.push(digits[(n % 62) as usize]);
+        n /= 62;
+    }
+    uid.push(b'C');
+    uid.reverse();
+    String::from_utf8(uid).unwrap_or_default()
+}
+
+/// Zeek service name of an application protocol label
+fn service(app_prot: &str) -> Option<String> {
+    match app_prot {
+        "" | "Unknown" => None,
+        "HTTPS" | "IMAPS" | "SMTPS" | "MQTTS" | "TLS" => Some(String::from("ssl")),
+        "SFTP" => Some(String::from("ssh")),
+        p => Some(p.to_lowercase()),
+    }
+}
+
+/// Zeek connection state of a TCP connection
+fn tcp_state(orig: &Side, resp: &Side) -> &'static str {
+    let established = orig.syn && resp.syn_ack;
+    if !orig.syn && !resp.syn_ack {
+        return "OTH";
+    }
+    if !established {
+        return match (orig.syn, resp.syn_ack) {
+            (true, false) if resp.rst => "REJ",
+            (true, false) if orig.rst => "RSTOS0",
+            (true, false) if orig.fin => "SH",
+            (true, false) => "S0",
+            (false, true) if resp.rst => "RSTRH",
+            (false, true) if resp.fin => "SHR",
+            _ => "OTH",
+        };
+    }
+    match (orig.fin, resp.fin, orig.rst, resp.rst) {
+        (_, _, true, _) => "RSTO",
+        (_, _, _, true) => "RSTR",
+        (true, true, _, _) => "SF",
+        (true, false, _, _) => "S2",
+        (false, true, _, _) => "S3",
+        _ => "S1",
+    }
+}
+
+/// Zeek connection state of a UDP connection: S0 if only the originator sent packets
+fn udp_state(resp: &Side) -> &'static str {
+    if resp.packets > 0 { "SF" } else { "S0" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Side with the flags set among "syn", "syn_ack", "fin" and "rst"
+    fn side(flags: &[&str]) -> Side {
+        Side {
+            syn: flags.contains(&"syn"),
+            syn_ack: flags.contains(&"syn_ack"),
+            fin: flags.contains(&"fin"),
+            rst: flags.contains(&"rst"),
+            ..Side::default()
+        }
+    }
+
+    fn tcp(syn: bool, ack: bool, fin: bool, rst: bool, len: u32) -> TcpPacket {
+        TcpPacket { seq: 0, ack_num: 0, syn, ack, fin, rst, window: 0, wscale: None, len }
+    }
+
+    /// Connection from 10.0.0.2:40000 (originator) to 10.0.0.1:80 (responder), lasting 1.5 s
+    fn connection(l4: u8, app_prot: &str) -> Connection {
+        let start = chrono::TimeZone::timestamp_opt(&Local, 1_700_000_000, 250_000_000).unwrap();
+        let end = start + chrono::Duration::milliseconds(1500);
+        Connection::new(4, String::from("10.0.0.1"), String::from("10.0.0.2"), l4, String::from("80"), String::from("40000"),
+            start, end, 0, String::from(app_prot))
+    }
+
+    #[test]
+    fn tcp_states() {
+        let cases = [
+            (vec![], vec![], "OTH"),
+            (vec!["syn"], vec![], "S0"),
+            (vec!["syn"], vec!["rst"], "REJ"),
+            (vec!["syn", "rst"], vec![], "RSTOS0"),
+            (vec!["syn", "fin"], vec![], "SH"),
+            (vec![], vec!["syn_ack", "rst"], "RSTRH"),
+            (vec![], vec!["syn_ack", "fin"], "SHR"),
+            (vec![], vec!["syn_ack"], "OTH"),
+            (vec!["syn"], vec!["syn_ack"], "S1"),
+            (vec!["syn", "fin"], vec!["syn_ack", "fin"], "SF"),
+            (vec!["syn", "fin"], vec!["syn_ack"], "S2"),
+            (vec!["syn"], vec!["syn_ack", "fin"], "S3"),
+            (vec!["syn", "rst"], vec!["syn_ack", "fin"], "RSTO"),
+            (vec!["syn", "fin"], vec!["syn_ack", "rst"], "RSTR"),
+        ];
+        for (orig, resp, state) in cases {
+            assert_eq!(tcp_state(&side(&orig), &side(&resp)), state, "{:?} {:?}", orig, resp);
+        }
+    }
+
+    #[test]
+    fn udp_states() {
+        assert_eq!(udp_state(&Side::default()), "S0");
+        assert_eq!(udp_state(&Side { packets: 1, ..Side::default() }), "SF");
+    }
+
+    #[test]
+    fn history() {
+        let mut log = ConnLog::default();
+        log.add(0, 0, 60, 0, Some(&tcp(true, false, false, false, 0)));
+        log.add(0, 1, 60, 0, Some(&tcp(true, true, false, false, 0)));
+        log.add(0, 0, 52, 0, Some(&tcp(false, true, false, false, 0)));
+        log.add(0, 0, 152, 100, Some(&tcp(false, true, false, false, 100)));
+        log.add(0, 0, 152, 100, Some(&tcp(false, true, false, false, 100)));
+        log.add(0, 1, 1052, 1000, Some(&tcp(false, true, false, false, 1000)));
+        log.add(0, 0, 52, 0, Some(&tcp(false, true, true, false, 0)));
+        log.add(0, 1, 52, 0, Some(&tcp(false, true, true, false, 0)));
+        let state = &log.conns[&0];
+        assert_eq!(state.history, "ShADdFf");
+        assert_eq!((state.sides[0].packets, state.sides[0].ip_bytes, state.sides[0].bytes), (5, 468, 200));
+        assert_eq!(tcp_state(&state.sides[0], &state.sides[1]), "SF");
+    }
+
+    #[test]
+    fn tsv() {
+        let mut log = ConnLog::default();
+        log.add(0, 0, 60, 32, None);
+        let mut out = vec![];
+        log.write_tsv(&[connection(1, "DNS")], &[String::from("10.0.0.2")], &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "#separator \\x09");
+        assert_eq!(lines[6], format!("#fields\t{}", FIELDS.join("\t")));
+        assert_eq!(lines[7], format!("#types\t{}", TYPES.join("\t")));
+        assert!(lines[9].starts_with("#close\t"));
+        let row: Vec<&str> = lines[8].split('\t').collect();
+        assert_eq!(row.len(), FIELDS.len());
+        assert_eq!(row[0], "1700000000.250000");
+        assert!(row[1].starts_with('C'));
+        assert_eq!(&row[2..12], &["10.0.0.2", "40000", "10.0.0.1", "80", "udp", "dns", "1.500000", "32", "0", "S0"]);
+        assert_eq!(&row[12..], &["T", "F", "0", "D", "1", "60", "0", "0", "-"]);
+    }
+
+    #[test]
+    fn json() {
+        let mut out = vec![];
+        ConnLog::default().write_json(&[connection(0, "Unknown")], &[], &mut out).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert!(line.starts_with("{\"ts\":1700000000.25,\"uid\":\"C"));
+        //unset fields are left out
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["conn_state"], "OTH");
+        assert!(record.get("service").is_none());
+        assert!(record.get("history").is_none());
+        assert!(record.get("tunnel_parents").is_none());
+    }
+
+    #[test]
+    fn services() {
+        assert_eq!(service("HTTPS").as_deref(), Some("ssl"));
+        assert_eq!(service("HTTP").as_deref(), Some("http"));
+        assert_eq!(service("Unknown"), None);
+    }
+
+    #[test]
+    fn uids() {
+        let con = connection(0, "HTTP");
+        assert_eq!(uid(0, &con), uid(0, &con));
+        assert_ne!(uid(0, &con), uid(1, &con));
+        assert!(uid(0, &con).chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}